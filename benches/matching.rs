@@ -0,0 +1,133 @@
+//! Matching throughput benchmarks.
+//!
+//! Run with `cargo bench`. These measure the engine in isolation (no WAL, no
+//! gRPC): resting-order insertion, a single market order sweeping a deep
+//! book, and cancels pulled from the middle of deep levels. Use the reported
+//! numbers to quantify data-structure changes and catch regressions.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rust_decimal::Decimal;
+use xmarket_engine::engine::MatchingEngine;
+use xmarket_engine::types::{Order, OrderStatus, OrderType, Side, TimeInForce};
+
+fn limit(id: u64, side: Side, price: Decimal, qty: Decimal) -> Order {
+    Order {
+        id,
+        user_id: id,
+        market_id: "BTC-USD".into(),
+        side,
+        order_type: OrderType::Limit,
+        price,
+        quantity: qty,
+        remaining_quantity: qty,
+        status: OrderStatus::New,
+        time_in_force: TimeInForce::Gtc,
+        all_or_none: false,
+        account_group: None,
+        public: true,
+        quantity_in_quote: false,
+        last_look: false,
+        expires_at: None,
+        client_order_id: None,
+        session_id: None,
+        sequence: id,
+        timestamp: id as i64,
+        peg: None,
+        peg_offset_bps: Decimal::ZERO,
+    }
+}
+
+/// A book with `levels` ask levels of `per_level` orders each, one unit
+/// every whole price starting at 100.
+fn deep_book(levels: u64, per_level: u64) -> MatchingEngine {
+    let mut engine = MatchingEngine::new("BTC-USD", 16);
+    let mut id = 1u64;
+    for level in 0..levels {
+        for _ in 0..per_level {
+            engine.place_order(limit(
+                id,
+                Side::Sell,
+                Decimal::from(100 + level),
+                Decimal::ONE,
+            ));
+            id += 1;
+        }
+    }
+    engine
+}
+
+fn bench_place_resting(c: &mut Criterion) {
+    const N: u64 = 10_000;
+    let mut group = c.benchmark_group("place_resting");
+    group.throughput(Throughput::Elements(N));
+    group.bench_function(format!("{N}_non_crossing_limits"), |b| {
+        b.iter_batched(
+            || MatchingEngine::new("BTC-USD", 16),
+            |mut engine| {
+                for id in 1..=N {
+                    // Spread across 100 price levels, none crossing.
+                    engine.place_order(limit(
+                        id,
+                        Side::Buy,
+                        Decimal::from(id % 100 + 1),
+                        Decimal::ONE,
+                    ));
+                }
+                engine
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_market_sweep(c: &mut Criterion) {
+    const LEVELS: u64 = 100;
+    const PER_LEVEL: u64 = 10;
+    let mut group = c.benchmark_group("market_sweep");
+    group.throughput(Throughput::Elements(LEVELS * PER_LEVEL));
+    group.bench_function(format!("{LEVELS}_levels_x_{PER_LEVEL}"), |b| {
+        b.iter_batched(
+            || deep_book(LEVELS, PER_LEVEL),
+            |mut engine| {
+                let mut taker = limit(u64::MAX, Side::Buy, Decimal::ZERO, Decimal::MAX);
+                taker.order_type = OrderType::Market;
+                taker.quantity = Decimal::from(LEVELS * PER_LEVEL);
+                taker.remaining_quantity = taker.quantity;
+                engine.place_order(taker);
+                engine
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_cancel_mid_level(c: &mut Criterion) {
+    const LEVELS: u64 = 10;
+    const PER_LEVEL: u64 = 1_000;
+    let mut group = c.benchmark_group("cancel_mid_level");
+    group.throughput(Throughput::Elements(LEVELS));
+    group.bench_function(format!("middle_of_{PER_LEVEL}_deep_levels"), |b| {
+        b.iter_batched(
+            || deep_book(LEVELS, PER_LEVEL),
+            |mut engine| {
+                // One cancel from the middle of each level's queue.
+                for level in 0..LEVELS {
+                    engine.cancel_order(level * PER_LEVEL + PER_LEVEL / 2);
+                }
+                engine
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_place_resting,
+    bench_market_sweep,
+    bench_cancel_mid_level
+);
+criterion_main!(benches);
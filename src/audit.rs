@@ -0,0 +1,166 @@
+//! Compliance audit log, separate from the WAL.
+//!
+//! Every order decision — accepted, rejected with its reason, matched,
+//! cancelled — is emitted as one JSON line through an [`AuditSink`] written
+//! in parallel with the WAL. Unlike the WAL the log is human-readable and
+//! purely observational: it is never replayed, and write failures are
+//! counted rather than allowed to stall order flow.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+/// One audited order decision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp_ns: i64,
+    pub market_id: String,
+    /// Acting user from the authenticated request; 0 is an internal actor
+    /// (session teardown, admin tooling).
+    pub actor: u64,
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+/// What was decided, with the identifiers compliance needs to reconstruct
+/// the episode from this log alone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    OrderAccepted {
+        order_id: u64,
+    },
+    OrderRejected {
+        /// Stable reject code, as sent to the client.
+        reason: String,
+        detail: String,
+    },
+    OrderMatched {
+        taker_order_id: u64,
+        maker_order_id: u64,
+        trade_id: u64,
+        price: Decimal,
+        quantity: Decimal,
+    },
+    OrderCancelled {
+        order_id: u64,
+    },
+    CancelRejected {
+        order_id: u64,
+        reason: String,
+        detail: String,
+    },
+}
+
+/// Destination for audit records. Called synchronously in decision order
+/// under the exchange lock, so implementations should hand off quickly.
+pub trait AuditSink: Send {
+    fn record(&mut self, record: &AuditRecord);
+}
+
+/// [`AuditSink`] appending one JSON line per record to a file. Write
+/// failures are counted rather than propagated, matching the trade spill:
+/// the audit log must never stall matching.
+pub struct FileAuditSink {
+    file: std::fs::File,
+    write_errors: u64,
+}
+
+impl FileAuditSink {
+    /// Opens (creating directories as needed) the audit log for append.
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(FileAuditSink {
+            file,
+            write_errors: 0,
+        })
+    }
+
+    /// Records lost to write failures since open, for metrics.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&mut self, record: &AuditRecord) {
+        let Ok(mut line) = serde_json::to_vec(record) else {
+            self.write_errors += 1;
+            return;
+        };
+        line.push(b'\n');
+        if self.file.write_all(&line).is_err() {
+            self.write_errors += 1;
+        }
+    }
+}
+
+/// [`AuditSink`] printing one JSON line per record to stdout, for piping
+/// into external log collection.
+pub struct StdoutAuditSink;
+
+impl AuditSink for StdoutAuditSink {
+    fn record(&mut self, record: &AuditRecord) {
+        if let Ok(line) = serde_json::to_string(record) {
+            println!("{line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn records_serialize_as_flat_tagged_json_lines() {
+        let record = AuditRecord {
+            timestamp_ns: 42,
+            market_id: "BTC-USD".into(),
+            actor: 7,
+            action: AuditAction::OrderMatched {
+                taker_order_id: 2,
+                maker_order_id: 1,
+                trade_id: 9,
+                price: dec!(100.5),
+                quantity: dec!(0.25),
+            },
+        };
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains("\"action\":\"order_matched\""));
+        assert!(json.contains("\"actor\":7"));
+        assert!(json.contains("\"100.5\""));
+        assert_eq!(serde_json::from_str::<AuditRecord>(&json).unwrap(), record);
+    }
+
+    #[test]
+    fn file_sink_appends_readable_lines() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let mut sink = FileAuditSink::open(&path).unwrap();
+        for order_id in 1..=2 {
+            sink.record(&AuditRecord {
+                timestamp_ns: order_id as i64,
+                market_id: "BTC-USD".into(),
+                actor: 1,
+                action: AuditAction::OrderAccepted { order_id },
+            });
+        }
+        assert_eq!(sink.write_errors(), 0);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].action, AuditAction::OrderAccepted { order_id: 2 });
+    }
+}
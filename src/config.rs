@@ -0,0 +1,446 @@
+//! Engine configuration.
+//!
+//! Everything is read from `ENGINE_*` environment variables with sane
+//! defaults, matching how the rest of the platform is configured via `.env`.
+
+use crate::engine::{CrossedBookPolicy, FeeTier, RecoveryReplay};
+use crate::orderbook::LevelOrdering;
+use crate::snapshot::SnapshotFormat;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Per-market trading parameters, loaded from the markets file. A zero value
+/// means "unconstrained"; unconfigured markets use [`MarketConfig::default`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarketConfig {
+    /// Prices must be a multiple of this.
+    pub tick_size: Decimal,
+    /// Quantities must be a multiple of this.
+    pub lot_size: Decimal,
+    /// Minimum `price * quantity` for limit orders.
+    pub min_notional: Decimal,
+    /// Maker fee in basis points of trade notional; negative is a rebate.
+    pub maker_fee_bps: Decimal,
+    /// Taker fee in basis points of trade notional.
+    pub taker_fee_bps: Decimal,
+    /// Hard cap on resting price levels per side in the matching book;
+    /// orders that would add a level beyond it are rejected.
+    pub max_price_levels: u32,
+    /// Hard cap on resting orders within one price level; orders that
+    /// would join a level already at the cap are rejected, keeping
+    /// per-level queue operations bounded. Zero disables the cap.
+    pub max_orders_per_level: u32,
+    /// Display scale for outgoing price strings; zero leaves the scale
+    /// uncapped.
+    pub price_decimals: u32,
+    /// Display scale for outgoing quantity strings; zero leaves the scale
+    /// uncapped.
+    pub quantity_decimals: u32,
+    /// How long after quoting a `last_look` maker may decline a match, in
+    /// nanoseconds; zero disables last-look for the market.
+    pub last_look_window_ns: i64,
+    /// Rolling maximum resting age in nanoseconds: quotes older than this
+    /// are swept from the book. Unlike GTD expiry the age restarts when an
+    /// amend re-rests the order. Zero disables the sweep.
+    pub max_order_age_ns: i64,
+    /// Anti-flicker minimum resting time in nanoseconds: the owner cannot
+    /// cancel an order until it has rested this long (fills are
+    /// unaffected, as are internal cancels — session teardown, expiry,
+    /// admin). Zero disables the restriction.
+    pub min_resting_time_ns: i64,
+    /// Best-execution slack: trades executing through the reference price by
+    /// more than this (in price units) are flagged. Only consulted when a
+    /// reference feed is installed.
+    pub trade_through_tolerance: Decimal,
+    /// When set, a limit order's remainder is never rested at a price that
+    /// locks or crosses the opposite side (possible past a skipped
+    /// all-or-none maker); it is cancelled instead.
+    pub no_locked_quotes: bool,
+    /// Risk cap on one user's total open resting notional
+    /// (`price * remaining` summed over their resting orders). Limit orders
+    /// that would push the user past it are rejected; zero disables the cap.
+    pub max_user_notional: Decimal,
+    /// Public-tape reporting delay in nanoseconds, for dark-pool-style
+    /// markets: trades are matched and journaled immediately but held off
+    /// the public trade tape until this much time has passed. Zero reports
+    /// immediately.
+    pub trade_report_delay_ns: i64,
+    /// Price collar: limit orders whose price deviates from the reference
+    /// mid by more than this many basis points are rejected. Only consulted
+    /// while the market's engine has a reference feed installed; zero
+    /// disables the collar.
+    pub collar_bps: Decimal,
+    /// Standard quote sizes reported by the `GetQuoteTiers` RPC: for each
+    /// size, the VWAP to fill it against either side of the book. Empty
+    /// disables the RPC for the market.
+    pub quote_size_tiers: Vec<Decimal>,
+    /// Volume-tiered fee schedule: once a user's cumulative traded volume
+    /// on the market reaches a tier's threshold, that tier's rates replace
+    /// the flat maker/taker fees above. Empty keeps the flat rates for
+    /// everyone.
+    pub fee_tiers: Vec<FeeTier>,
+}
+
+impl MarketConfig {
+    /// The venue's take per unit notional: maker and taker fees combined.
+    /// Must stay at or above the configured floor so rebates cannot make a
+    /// market net-negative for the venue.
+    pub fn net_fee_bps(&self) -> Decimal {
+        self.maker_fee_bps + self.taker_fee_bps
+    }
+
+    /// Canonical wire form of a price: capped at `price_decimals` and with
+    /// trailing zeros stripped, so `50.0` and `50` serialize identically.
+    pub fn format_price(&self, value: Decimal) -> String {
+        canonical(value, self.price_decimals)
+    }
+
+    /// Canonical wire form of a quantity; see [`MarketConfig::format_price`].
+    pub fn format_quantity(&self, value: Decimal) -> String {
+        canonical(value, self.quantity_decimals)
+    }
+}
+
+fn canonical(value: Decimal, decimals: u32) -> String {
+    let capped = if decimals > 0 {
+        value.round_dp(decimals)
+    } else {
+        value
+    };
+    capped.normalize().to_string()
+}
+
+/// Loads `{"BTC-USD": {"tick_size": "0.5", ...}, ...}` from a JSON markets
+/// file. A missing file yields an empty map (all markets on defaults).
+pub fn load_market_configs(path: &Path) -> io::Result<HashMap<String, MarketConfig>> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// gRPC listen address (`ENGINE_LISTEN_ADDR`).
+    pub listen_addr: String,
+    /// Root directory for WAL segments and snapshots (`ENGINE_DATA_DIR`).
+    pub data_dir: PathBuf,
+    /// Maximum WAL segment size before rotation (`ENGINE_WAL_SEGMENT_MAX_BYTES`).
+    pub wal_segment_max_bytes: u64,
+    /// WAL retention window in seconds: checkpoints keep segments holding
+    /// entries younger than this even after a snapshot covers them, so the
+    /// raw log supports point-in-time recovery (and audits) anywhere inside
+    /// the window. 0 lets checkpoints reclaim freely
+    /// (`ENGINE_WAL_RETENTION_SECS`).
+    pub wal_retention_secs: u64,
+    /// Snapshot on-disk format (`ENGINE_SNAPSHOT_FORMAT`, `bincode` or `json`).
+    pub snapshot_format: SnapshotFormat,
+    /// Per-market recent-trades ring buffer size (`ENGINE_RECENT_TRADES_CAPACITY`).
+    pub recent_trades_capacity: usize,
+    /// When set, trades evicted from the ring are appended to
+    /// `data_dir/spill/<market>.trades` instead of dropped, so full history
+    /// survives the in-memory cap (`ENGINE_SPILL_EVICTED_TRADES`).
+    pub spill_evicted_trades: bool,
+    /// Default depth levels per side in market data (`ENGINE_DEPTH_LEVELS`).
+    pub depth_levels: usize,
+    /// When set, market-data depth folds levels beyond the cap into one
+    /// aggregate "rest of book" level per side (`ENGINE_DEPTH_AGGREGATE_TAIL`).
+    pub depth_aggregate_tail: bool,
+    /// When set, engines for every configured market are created at startup
+    /// rather than on first request, so the first live order or market-data
+    /// call pays no setup cost (`ENGINE_PREWARM_MARKETS`). Markets with
+    /// history are always recovered eagerly regardless.
+    pub prewarm_markets: bool,
+    /// GTD expiry reap interval in milliseconds (`ENGINE_REAP_INTERVAL_MS`).
+    pub reap_interval_ms: u64,
+    /// Retries after a failed snapshot save, with backoff in between
+    /// (`ENGINE_SNAPSHOT_SAVE_RETRIES`).
+    pub snapshot_save_retries: u32,
+    /// Fractional digits kept on derived quantities — fees, notionals,
+    /// VWAP — see [`crate::pricing::PricingPolicy`]
+    /// (`ENGINE_PRICING_SCALE`).
+    pub pricing_scale: u32,
+    /// In-level tie-break policy (`ENGINE_LEVEL_ORDERING`: `price_time`,
+    /// `fifo` or `price_time_size`).
+    pub level_ordering: LevelOrdering,
+    /// Seed for the per-engine deterministic matching RNG; any randomized
+    /// allocation draws from it, and replay with the same seed reproduces
+    /// the draws exactly (`ENGINE_MATCHING_SEED`).
+    pub matching_seed: u64,
+    /// What to do on detecting an internally crossed book — corrupted
+    /// state — before matching new flow into it
+    /// (`ENGINE_CROSSED_BOOK_POLICY`: `halt` or `uncross`).
+    pub crossed_book_policy: CrossedBookPolicy,
+    /// HTTP/2 keepalive ping interval in seconds, 0 to disable
+    /// (`ENGINE_HTTP2_KEEPALIVE_INTERVAL_SECS`).
+    pub http2_keepalive_interval_secs: u64,
+    /// How long to wait for a keepalive ping ack before closing the
+    /// connection (`ENGINE_HTTP2_KEEPALIVE_TIMEOUT_SECS`).
+    pub http2_keepalive_timeout_secs: u64,
+    /// Max concurrent HTTP/2 streams per connection, 0 for the transport
+    /// default (`ENGINE_MAX_CONCURRENT_STREAMS`).
+    pub max_concurrent_streams: u32,
+    /// Global cap on in-flight requests, 0 for unlimited; excess requests
+    /// are rejected with `resource_exhausted`
+    /// (`ENGINE_MAX_CONCURRENT_REQUESTS`).
+    pub max_concurrent_requests: usize,
+    /// Path to the per-market JSON config, hot-reloaded on SIGHUP
+    /// (`ENGINE_MARKETS_FILE`).
+    pub markets_file: PathBuf,
+    /// Consecutive WAL append failures that halt order entry with
+    /// `failed_precondition` until a write succeeds again; 0 disables the
+    /// circuit (`ENGINE_WAL_FAILURE_HALT_THRESHOLD`).
+    pub wal_failure_halt_threshold: u32,
+    /// Floor on per-market `maker_fee_bps + taker_fee_bps`; configs whose
+    /// rebate would take the venue below this are rejected
+    /// (`ENGINE_MIN_NET_FEE_BPS`).
+    pub min_net_fee_bps: Decimal,
+    /// Bearer token required on Admin RPCs; empty disables the check
+    /// (`ENGINE_ADMIN_TOKEN`).
+    pub admin_token: String,
+    /// Compliance audit log destination: empty disables it, `stdout`
+    /// prints JSON lines, anything else is a file path appended to
+    /// (`ENGINE_AUDIT_LOG`).
+    pub audit_log: String,
+    /// Journal an audit `OrderFilled` record when a resting maker becomes
+    /// fully filled (`ENGINE_WAL_FILLED_ORDERS`).
+    pub wal_filled_orders: bool,
+    /// Journal one aggregated `OrderMatched` record per matching pass
+    /// instead of a `TradeExecuted` per fill, so a deep sweep costs one
+    /// append while keeping per-fill detail inside the record
+    /// (`ENGINE_WAL_AGGREGATE_TRADES`).
+    pub wal_aggregate_trades: bool,
+    /// Periodic checkpoint (snapshot all markets, verify, truncate the WAL)
+    /// interval in seconds; 0 disables the timer
+    /// (`ENGINE_CHECKPOINT_INTERVAL_SECS`). A checkpoint also runs on
+    /// graceful shutdown.
+    pub checkpoint_interval_secs: u64,
+    /// Checkpoint once this many WAL bytes have been appended since the
+    /// last checkpoint, bounding recovery IO regardless of event rate.
+    /// Combined with the interval timer — whichever fires first wins; 0
+    /// disables the byte trigger (`ENGINE_CHECKPOINT_WAL_BYTES`).
+    pub checkpoint_wal_bytes: u64,
+    /// Budget for replaying the WAL tail at startup, in milliseconds. When
+    /// replay exceeds it the affected market falls back to its latest
+    /// snapshot and the rest of the tail is skipped, trading staleness for
+    /// bounded startup time; 0 replays everything regardless of duration
+    /// (`ENGINE_RECOVERY_TIMEOUT_MS`).
+    pub recovery_timeout_ms: u64,
+    /// How recovery rebuilds books from the WAL tail
+    /// (`ENGINE_RECOVERY_REPLAY`: `literal` applies the journaled trades,
+    /// cancels and reprices as recorded — the default — while `rematch`
+    /// re-runs the matching loop on each replayed placement).
+    pub recovery_replay: RecoveryReplay,
+    /// Periodic dust sweep interval in seconds: cancels resting orders
+    /// whose remainder has fallen below their market's lot size; 0
+    /// disables the sweep (`ENGINE_DUST_SWEEP_INTERVAL_SECS`).
+    pub dust_sweep_interval_secs: u64,
+    /// When set (the default), an order for a market id with no entry in
+    /// the markets file opens a brand-new market on the fly — the
+    /// historical behavior, and convenient in tests. Clearing it turns the
+    /// markets file into an allowlist: orders for unconfigured markets are
+    /// rejected with `not_found`, so a typo cannot silently create a market
+    /// (`ENGINE_ALLOW_UNLISTED_MARKETS`).
+    pub allow_unlisted_markets: bool,
+    /// When set, every snapshot write first runs
+    /// [`crate::orderbook::Orderbook::verify_invariants`] on the live book
+    /// and fails the snapshot instead of persisting an inconsistent state
+    /// (`ENGINE_SNAPSHOT_VERIFY_INVARIANTS`).
+    pub snapshot_verify_invariants: bool,
+    /// Strict mode: after every successful WAL write, cross-check the
+    /// exchange's view of each market's journal position against the WAL's
+    /// own per-market counter and halt order entry on divergence — a
+    /// persistence bug caught at the write instead of at the next recovery
+    /// (`ENGINE_STRICT_SEQUENCE_CHECKS`).
+    pub strict_sequence_checks: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            listen_addr: "0.0.0.0:50051".to_string(),
+            data_dir: PathBuf::from("./data"),
+            wal_segment_max_bytes: 64 * 1024 * 1024,
+            wal_retention_secs: 0,
+            snapshot_format: SnapshotFormat::default(),
+            recent_trades_capacity: 1024,
+            spill_evicted_trades: false,
+            depth_levels: 20,
+            depth_aggregate_tail: false,
+            prewarm_markets: false,
+            reap_interval_ms: 1000,
+            snapshot_save_retries: 2,
+            pricing_scale: 12,
+            level_ordering: LevelOrdering::default(),
+            matching_seed: 0,
+            crossed_book_policy: CrossedBookPolicy::default(),
+            http2_keepalive_interval_secs: 30,
+            http2_keepalive_timeout_secs: 20,
+            max_concurrent_streams: 0,
+            max_concurrent_requests: 0,
+            markets_file: PathBuf::from("./markets.json"),
+            wal_failure_halt_threshold: 3,
+            min_net_fee_bps: Decimal::ZERO,
+            admin_token: String::new(),
+            audit_log: String::new(),
+            wal_filled_orders: true,
+            wal_aggregate_trades: false,
+            checkpoint_interval_secs: 0,
+            checkpoint_wal_bytes: 0,
+            recovery_timeout_ms: 0,
+            recovery_replay: RecoveryReplay::default(),
+            dust_sweep_interval_secs: 0,
+            allow_unlisted_markets: true,
+            snapshot_verify_invariants: false,
+            strict_sequence_checks: false,
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or(default),
+        Err(_) => default,
+    }
+}
+
+impl EngineConfig {
+    pub fn from_env() -> Self {
+        let defaults = EngineConfig::default();
+        EngineConfig {
+            listen_addr: std::env::var("ENGINE_LISTEN_ADDR").unwrap_or(defaults.listen_addr),
+            data_dir: std::env::var("ENGINE_DATA_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(defaults.data_dir),
+            wal_segment_max_bytes: env_parse(
+                "ENGINE_WAL_SEGMENT_MAX_BYTES",
+                defaults.wal_segment_max_bytes,
+            ),
+            wal_retention_secs: env_parse(
+                "ENGINE_WAL_RETENTION_SECS",
+                defaults.wal_retention_secs,
+            ),
+            snapshot_format: env_parse("ENGINE_SNAPSHOT_FORMAT", defaults.snapshot_format),
+            recent_trades_capacity: env_parse(
+                "ENGINE_RECENT_TRADES_CAPACITY",
+                defaults.recent_trades_capacity,
+            ),
+            spill_evicted_trades: env_parse(
+                "ENGINE_SPILL_EVICTED_TRADES",
+                defaults.spill_evicted_trades,
+            ),
+            depth_levels: env_parse("ENGINE_DEPTH_LEVELS", defaults.depth_levels),
+            depth_aggregate_tail: env_parse(
+                "ENGINE_DEPTH_AGGREGATE_TAIL",
+                defaults.depth_aggregate_tail,
+            ),
+            prewarm_markets: env_parse("ENGINE_PREWARM_MARKETS", defaults.prewarm_markets),
+            reap_interval_ms: env_parse("ENGINE_REAP_INTERVAL_MS", defaults.reap_interval_ms),
+            snapshot_save_retries: env_parse(
+                "ENGINE_SNAPSHOT_SAVE_RETRIES",
+                defaults.snapshot_save_retries,
+            ),
+            pricing_scale: env_parse("ENGINE_PRICING_SCALE", defaults.pricing_scale),
+            level_ordering: env_parse("ENGINE_LEVEL_ORDERING", defaults.level_ordering),
+            matching_seed: env_parse("ENGINE_MATCHING_SEED", defaults.matching_seed),
+            crossed_book_policy: env_parse(
+                "ENGINE_CROSSED_BOOK_POLICY",
+                defaults.crossed_book_policy,
+            ),
+            http2_keepalive_interval_secs: env_parse(
+                "ENGINE_HTTP2_KEEPALIVE_INTERVAL_SECS",
+                defaults.http2_keepalive_interval_secs,
+            ),
+            http2_keepalive_timeout_secs: env_parse(
+                "ENGINE_HTTP2_KEEPALIVE_TIMEOUT_SECS",
+                defaults.http2_keepalive_timeout_secs,
+            ),
+            max_concurrent_streams: env_parse(
+                "ENGINE_MAX_CONCURRENT_STREAMS",
+                defaults.max_concurrent_streams,
+            ),
+            max_concurrent_requests: env_parse(
+                "ENGINE_MAX_CONCURRENT_REQUESTS",
+                defaults.max_concurrent_requests,
+            ),
+            markets_file: std::env::var("ENGINE_MARKETS_FILE")
+                .map(PathBuf::from)
+                .unwrap_or(defaults.markets_file),
+            wal_failure_halt_threshold: env_parse(
+                "ENGINE_WAL_FAILURE_HALT_THRESHOLD",
+                defaults.wal_failure_halt_threshold,
+            ),
+            min_net_fee_bps: env_parse("ENGINE_MIN_NET_FEE_BPS", defaults.min_net_fee_bps),
+            admin_token: std::env::var("ENGINE_ADMIN_TOKEN").unwrap_or(defaults.admin_token),
+            audit_log: std::env::var("ENGINE_AUDIT_LOG").unwrap_or(defaults.audit_log),
+            wal_filled_orders: env_parse("ENGINE_WAL_FILLED_ORDERS", defaults.wal_filled_orders),
+            wal_aggregate_trades: env_parse(
+                "ENGINE_WAL_AGGREGATE_TRADES",
+                defaults.wal_aggregate_trades,
+            ),
+            checkpoint_interval_secs: env_parse(
+                "ENGINE_CHECKPOINT_INTERVAL_SECS",
+                defaults.checkpoint_interval_secs,
+            ),
+            checkpoint_wal_bytes: env_parse(
+                "ENGINE_CHECKPOINT_WAL_BYTES",
+                defaults.checkpoint_wal_bytes,
+            ),
+            recovery_timeout_ms: env_parse(
+                "ENGINE_RECOVERY_TIMEOUT_MS",
+                defaults.recovery_timeout_ms,
+            ),
+            recovery_replay: env_parse("ENGINE_RECOVERY_REPLAY", defaults.recovery_replay),
+            dust_sweep_interval_secs: env_parse(
+                "ENGINE_DUST_SWEEP_INTERVAL_SECS",
+                defaults.dust_sweep_interval_secs,
+            ),
+            allow_unlisted_markets: env_parse(
+                "ENGINE_ALLOW_UNLISTED_MARKETS",
+                defaults.allow_unlisted_markets,
+            ),
+            snapshot_verify_invariants: env_parse(
+                "ENGINE_SNAPSHOT_VERIFY_INVARIANTS",
+                defaults.snapshot_verify_invariants,
+            ),
+            strict_sequence_checks: env_parse(
+                "ENGINE_STRICT_SEQUENCE_CHECKS",
+                defaults.strict_sequence_checks,
+            ),
+        }
+    }
+
+    pub fn wal_dir(&self) -> PathBuf {
+        self.data_dir.join("wal")
+    }
+
+    pub fn snapshot_dir(&self) -> PathBuf {
+        self.data_dir.join("snapshots")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn equal_decimals_share_one_canonical_wire_form() {
+        let config = MarketConfig {
+            price_decimals: 2,
+            ..MarketConfig::default()
+        };
+        assert_eq!(config.format_price(dec!(50.0)), "50");
+        assert_eq!(config.format_price(dec!(50)), "50");
+        // `round_dp` rounds midpoints to even.
+        assert_eq!(config.format_price(dec!(50.125)), "50.12");
+        // Zero decimals leaves the scale uncapped but still normalizes.
+        assert_eq!(config.format_quantity(dec!(0.1000)), "0.1");
+    }
+}
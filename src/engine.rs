@@ -0,0 +1,2389 @@
+//! Per-market matching engine.
+//!
+//! A [`MatchingEngine`] owns the [`Orderbook`] for one market, runs the
+//! price-time matching loop and keeps a ring buffer of recent trades for
+//! market data. It performs no journaling itself; the [`crate::exchange`]
+//! layer assigns ids, writes the WAL and routes commands to the right engine.
+
+use crate::orderbook::Orderbook;
+use crate::types::{
+    now_ns, Order, OrderId, OrderStatus, OrderType, PegReference, Side, TimeInForce, Trade,
+};
+use crate::pricing::PricingPolicy;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use tokio::sync::broadcast;
+
+/// Depth update published to market-data subscribers after each change.
+#[derive(Debug, Clone)]
+pub struct BookUpdate {
+    pub market_id: String,
+    /// Book stream position: increments once per broadcast. A hole in the
+    /// sequence seen by a subscriber means a dropped update.
+    pub stream_sequence: u64,
+    pub timestamp: i64,
+}
+
+/// Top-of-book for one market: best bid and ask as `(price, level size)`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bbo {
+    pub bid: Option<(Decimal, Decimal)>,
+    pub ask: Option<(Decimal, Decimal)>,
+}
+
+/// Published only when the BBO actually moves, for consumers that do not
+/// care about depth behind the touch.
+#[derive(Debug, Clone)]
+pub struct BboUpdate {
+    pub market_id: String,
+    pub bbo: Bbo,
+    pub timestamp: i64,
+}
+
+/// Resting-order life-cycle notification: published whenever a resting
+/// order's state changes (partial fill, full fill, cancel), carrying the
+/// order as of the change. Per-user filtering and granularity are the
+/// subscriber's concern.
+#[derive(Debug, Clone)]
+pub struct OrderUpdate {
+    pub market_id: String,
+    pub order: Order,
+    pub timestamp: i64,
+}
+
+/// A trade print with its aggressor side and the fees it accrued. The maker
+/// fee is negative when the market pays a rebate.
+#[derive(Debug, Clone)]
+pub struct TradePrint {
+    pub trade: Trade,
+    pub aggressor: Side,
+    pub maker_fee: Decimal,
+    pub taker_fee: Decimal,
+    /// Trade-tape stream position; see [`BookUpdate::stream_sequence`].
+    pub stream_sequence: u64,
+}
+
+/// Downstream consumer of matching output, for integrations (message-bus
+/// bridges, analytics) that should not be hardcoded into the engine. Sinks
+/// run synchronously after the in-process broadcast fan-out, so
+/// implementations should hand off quickly rather than block.
+pub trait TradeSink: Send {
+    /// Called once per executed trade, in execution order, including trades
+    /// of non-public takers (visibility only gates the public tape).
+    fn on_trade(&mut self, trade: &Trade, aggressor: Side);
+}
+
+/// Pre-trade risk veto, consulted with each prospective trade before any
+/// state changes. Returning `false` aborts the fill: neither order is
+/// touched, no trade id is consumed, and the taker stops matching with its
+/// remainder cancelled (a risk breach on one fill taints the whole sweep).
+/// Runs on the matching path under the exchange lock, so implementations
+/// must be fast and must not block. No check installed means every trade is
+/// approved.
+pub trait PreTradeCheck: Send {
+    fn approve(&mut self, trade: &Trade) -> bool;
+}
+
+/// Destination for trades evicted from the bounded recent-trades ring, so
+/// full history can outlive the in-memory cap (spilled to disk, shipped
+/// elsewhere, or dropped by not installing one). Only the raw prints leave
+/// memory: derived aggregates such as VWAP are maintained independently and
+/// are unaffected by eviction. Evictions happen on the matching path, so
+/// implementations should write cheaply and never block.
+pub trait TradeSpill: Send {
+    fn on_evict(&mut self, trade: &Trade);
+}
+
+/// [`TradeSpill`] appending evicted trades to a file as length-prefixed
+/// bincode frames, readable back via [`FileTradeSpill::read_all`]. Write
+/// failures are counted rather than propagated: spill is archival and must
+/// never stall matching.
+pub struct FileTradeSpill {
+    file: std::fs::File,
+    write_errors: u64,
+}
+
+impl FileTradeSpill {
+    /// Opens (creating directories as needed) the spill file for append.
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(FileTradeSpill {
+            file,
+            write_errors: 0,
+        })
+    }
+
+    /// Reads every spilled trade back, oldest first.
+    pub fn read_all(path: impl AsRef<std::path::Path>) -> std::io::Result<Vec<Trade>> {
+        let data = std::fs::read(path)?;
+        let mut trades = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= data.len() {
+            let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            offset += 4;
+            let end = offset + len;
+            if end > data.len() {
+                break;
+            }
+            let trade = bincode::deserialize(&data[offset..end])
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            trades.push(trade);
+            offset = end;
+        }
+        Ok(trades)
+    }
+
+    /// Evictions lost to write failures since open, for metrics.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors
+    }
+}
+
+impl TradeSpill for FileTradeSpill {
+    fn on_evict(&mut self, trade: &Trade) {
+        use std::io::Write;
+        let result = bincode::serialize(trade).map(|payload| {
+            let mut frame = (payload.len() as u32).to_le_bytes().to_vec();
+            frame.extend_from_slice(&payload);
+            self.file.write_all(&frame)
+        });
+        if !matches!(result, Ok(Ok(()))) {
+            self.write_errors += 1;
+        }
+    }
+}
+
+/// What the exchange does on finding a market's book internally crossed —
+/// a bid at or above an ask, which matching invariants should make
+/// impossible and therefore indicates corrupted state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossedBookPolicy {
+    /// Halt order entry and alert; an operator decides what to trust.
+    #[default]
+    Halt,
+    /// Match the crossed resting orders against each other until the book
+    /// uncrosses, then continue.
+    Uncross,
+}
+
+impl std::str::FromStr for CrossedBookPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halt" => Ok(CrossedBookPolicy::Halt),
+            "uncross" => Ok(CrossedBookPolicy::Uncross),
+            other => Err(format!("unknown crossed-book policy {other:?}")),
+        }
+    }
+}
+
+/// How recovery rebuilds a market's book from the WAL tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryReplay {
+    /// Apply the log as recorded: placements insert without matching and
+    /// the journaled trades, cancels and reprices are authoritative, so
+    /// the rebuilt book is exactly the historical one. Immune to anything
+    /// that would make a re-run of matching come out differently — a
+    /// changed market config, a pre-trade check that is not persisted.
+    #[default]
+    Literal,
+    /// Re-run the matching loop on each replayed placement and regenerate
+    /// the trades, as recovery did before literal replay existed. Replay
+    /// must then be bit-for-bit deterministic, including configuration.
+    Rematch,
+}
+
+impl std::str::FromStr for RecoveryReplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "literal" => Ok(RecoveryReplay::Literal),
+            "rematch" => Ok(RecoveryReplay::Rematch),
+            other => Err(format!("unknown recovery replay mode {other:?}")),
+        }
+    }
+}
+
+/// One rung of a volume-tiered fee schedule: at or above `min_volume` of
+/// cumulative traded base quantity on the market, a user pays these rates
+/// instead of the market's flat ones. See [`MatchingEngine::set_fee_tiers`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeTier {
+    pub min_volume: Decimal,
+    pub maker_fee_bps: Decimal,
+    pub taker_fee_bps: Decimal,
+}
+
+/// Execution-quality telemetry for one market: the fraction of incoming
+/// taker quantity that filled, and how long resting orders waited for
+/// their first and full fills. Counters accumulate from engine start;
+/// replay does not feed them, so they describe live flow only.
+#[derive(Debug, Clone, Default)]
+pub struct MatchQualityStats {
+    /// Quantity across all accepted incoming orders, in each order's own
+    /// unit (quote notional for quote-quantity sweeps).
+    pub incoming_quantity: Decimal,
+    /// The part of `incoming_quantity` that filled on arrival.
+    pub incoming_filled: Decimal,
+    /// Resting orders that have received their first fill, with the summed
+    /// placement-to-first-fill delay.
+    pub first_fills: u64,
+    pub time_to_first_fill_ns: i64,
+    /// Resting orders filled completely, with the summed placement-to-full-
+    /// fill delay.
+    pub full_fills: u64,
+    pub time_to_full_fill_ns: i64,
+}
+
+impl MatchQualityStats {
+    /// Filled fraction of incoming taker quantity; `None` before any flow.
+    pub fn fill_ratio(&self) -> Option<Decimal> {
+        (self.incoming_quantity > Decimal::ZERO)
+            .then(|| self.incoming_filled / self.incoming_quantity)
+    }
+
+    pub fn avg_time_to_first_fill_ns(&self) -> Option<i64> {
+        (self.first_fills > 0).then(|| self.time_to_first_fill_ns / self.first_fills as i64)
+    }
+
+    pub fn avg_time_to_full_fill_ns(&self) -> Option<i64> {
+        (self.full_fills > 0).then(|| self.time_to_full_fill_ns / self.full_fills as i64)
+    }
+}
+
+/// Trading phase of a market's session. Markets without a schedule stay in
+/// [`MarketPhase::Continuous`] forever; scheduled markets are driven
+/// through the cycle `PreOpen → Open → Continuous → Close → PreOpen` by an
+/// operator RPC or external scheduler. Orders accumulate without matching
+/// in the auction-call phases and cross in a single uncross on the phase
+/// transition, like an opening/closing auction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MarketPhase {
+    /// Accumulating orders ahead of the opening auction: limit orders rest
+    /// (even crossed), market orders cancel, nothing matches.
+    PreOpen,
+    /// Opening auction: entering this phase uncrosses the accumulated
+    /// book; matching is continuous for as long as the phase lasts.
+    Open,
+    /// Normal continuous matching (the default).
+    #[default]
+    Continuous,
+    /// Closing auction: entering this phase runs a final uncross; orders
+    /// placed afterwards accumulate for the next session's open.
+    Close,
+}
+
+impl MarketPhase {
+    /// Whether incoming orders match immediately in this phase, as opposed
+    /// to accumulating for the next auction uncross.
+    pub fn is_matching(self) -> bool {
+        matches!(self, MarketPhase::Open | MarketPhase::Continuous)
+    }
+
+    /// Stable wire identifier, mirrored by the `FromStr` impl.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MarketPhase::PreOpen => "pre_open",
+            MarketPhase::Open => "open",
+            MarketPhase::Continuous => "continuous",
+            MarketPhase::Close => "close",
+        }
+    }
+}
+
+impl std::str::FromStr for MarketPhase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pre_open" => Ok(MarketPhase::PreOpen),
+            "open" => Ok(MarketPhase::Open),
+            "continuous" => Ok(MarketPhase::Continuous),
+            "close" => Ok(MarketPhase::Close),
+            other => Err(format!("unknown market phase {other:?}")),
+        }
+    }
+}
+
+/// Phase-transition notification, broadcast to subscribers whenever a
+/// market actually changes phase.
+#[derive(Debug, Clone)]
+pub struct PhaseUpdate {
+    pub market_id: String,
+    pub phase: MarketPhase,
+    pub timestamp: i64,
+}
+
+/// Source of an externally maintained reference price (an NBBO-like feed)
+/// used for best-execution trade-through checks. Implementations are updated
+/// out-of-band, so `reference` takes `&self`; share mutable state behind a
+/// lock or atomics.
+pub trait ReferencePriceProvider: Send {
+    /// Current reference `(bid, ask)` for the market, if known.
+    fn reference(&self, market_id: &str) -> Option<(Decimal, Decimal)>;
+}
+
+pub struct MatchingEngine {
+    pub market_id: String,
+    pub orderbook: Orderbook,
+    /// Most recent trades, newest at the back.
+    pub recent_trades: VecDeque<Trade>,
+    recent_trades_capacity: usize,
+    /// Where trades evicted from the ring go; `None` drops them.
+    spill: Option<Box<dyn TradeSpill>>,
+    /// Pre-trade risk veto; `None` approves everything.
+    pre_trade: Option<Box<dyn PreTradeCheck>>,
+    /// Session phase; routes placements between continuous matching and
+    /// auction-call accumulation. Persisted in snapshots and journaled on
+    /// transition so replay reproduces auction timing.
+    phase: MarketPhase,
+    /// Phase-transition notifications.
+    phase_tx: broadcast::Sender<PhaseUpdate>,
+    next_trade_id: u64,
+    book_tx: broadcast::Sender<BookUpdate>,
+    /// Stream positions stamped on outgoing book and trade messages, one
+    /// counter per stream so subscribers can detect dropped messages.
+    book_stream_sequence: u64,
+    trade_stream_sequence: u64,
+    /// BBO-change notifications; `last_bbo` is the last value published so
+    /// mutations that leave the touch unchanged emit nothing.
+    bbo_tx: broadcast::Sender<BboUpdate>,
+    last_bbo: Bbo,
+    /// Execution-quality counters; see [`MatchQualityStats`].
+    quality: MatchQualityStats,
+    /// Live trade prints with aggressor side and fees.
+    trade_tx: broadcast::Sender<TradePrint>,
+    /// Resting-order state changes; see [`OrderUpdate`].
+    order_tx: broadcast::Sender<OrderUpdate>,
+    /// Min-heap of `(expires_at, order_id)` for GTD orders. Entries are
+    /// lazily deleted: cancels and fills leave stale entries behind, which
+    /// the reaper skips when the order is no longer resting.
+    expiry_heap: BinaryHeap<Reverse<(i64, u64)>>,
+    /// Fee schedule in basis points of notional; maker may be negative
+    /// (a rebate). Applied by `execute_trade`.
+    maker_fee_bps: Decimal,
+    taker_fee_bps: Decimal,
+    /// Volume-tiered fee schedule, sorted ascending by threshold; empty
+    /// leaves the flat rates above for everyone.
+    fee_tiers: Vec<FeeTier>,
+    /// Cumulative traded base quantity per user, for tier selection.
+    /// Persisted in snapshots so discounts survive a restart.
+    user_volumes: HashMap<u64, Decimal>,
+    /// Lot size the market trades in; zero disables lot-grid clamping.
+    /// Matched quantities are rounded down to this grid and sub-lot
+    /// remainders are cancelled as dust instead of resting untradeably.
+    lot_size: Decimal,
+    /// Tick size from the market config; pegged re-prices snap to this
+    /// grid. Zero leaves peg prices unsnapped.
+    tick_size: Decimal,
+    /// Ids of resting pegged orders, maintained as they rest and lazily
+    /// pruned as they leave the book. Rebuilt from the book on snapshot
+    /// restore via [`MatchingEngine::rebuild_pegged_index`].
+    pegged_orders: Vec<u64>,
+    /// Peg re-prices since the exchange last drained them via
+    /// [`MatchingEngine::take_repriced`], as `(order_id, new_price)`.
+    repriced: Vec<(u64, Decimal)>,
+    /// Makers whose sub-lot remainders were cancelled during matching,
+    /// drained by the exchange for journaling (see
+    /// [`MatchingEngine::take_dust_cancels`]).
+    dust_cancels: Vec<Order>,
+    /// Rounding applied to every derived quantity (fees, notional, VWAP).
+    pricing: PricingPolicy,
+    /// Net fees accrued per user: positive owes the venue, negative is
+    /// rebate owed to the user. Fees net across maker and taker roles.
+    fee_ledger: HashMap<u64, Decimal>,
+    /// Makers fully consumed since the exchange last drained them via
+    /// [`MatchingEngine::take_filled_makers`], as `(order_id, quantity)`.
+    filled_makers: Vec<(u64, Decimal)>,
+    /// Registered matching-result sinks, invoked per trade.
+    sinks: Vec<Box<dyn TradeSink>>,
+    /// Min-heap of `(timestamp, order_id)` over resting orders, mirroring
+    /// the expiry heap's lazy-deletion scheme, for the oldest-order metric
+    /// and stale-quote eviction.
+    age_heap: BinaryHeap<Reverse<(i64, u64)>>,
+    /// Rolling maximum resting age from the market config; zero disables
+    /// stale-quote eviction.
+    max_order_age_ns: i64,
+    /// Reference feed for trade-through checks; `None` disables them.
+    reference: Option<Box<dyn ReferencePriceProvider>>,
+    /// How far through the reference a trade may execute before being
+    /// flagged, in price units.
+    trade_through_tolerance: Decimal,
+    /// Public-tape reporting delay from the market config; trades are
+    /// matched and journaled immediately but held off the tape (live
+    /// stream and backfill) until `timestamp + delay` has passed. Zero
+    /// reports immediately.
+    trade_report_delay_ns: i64,
+    /// Prints awaiting their reporting delay, oldest first; released by
+    /// [`MatchingEngine::flush_trade_prints`].
+    held_prints: VecDeque<TradePrint>,
+    /// Total trades flagged as through the reference, for metrics.
+    trade_through_count: u64,
+    /// Flagged trades since the last drain, as `(trade_id, through_by)`.
+    trade_through_flags: Vec<(u64, Decimal)>,
+    /// No-lock policy from the market config: when set, a limit remainder
+    /// that would rest locking or crossing the opposite touch is cancelled
+    /// instead of rested.
+    no_locked_quotes: bool,
+    /// Last-look window from the market config; zero disables the check.
+    last_look_window_ns: i64,
+    /// Makers cancelled by last look since the exchange last drained them
+    /// via [`MatchingEngine::take_last_look_cancels`].
+    last_look_cancels: Vec<Order>,
+    /// Deterministic matching RNG state (SplitMix64). No current level
+    /// ordering draws randomness, but any future randomized allocation
+    /// (pro-rata leftovers, random tie-breaks) must draw from
+    /// [`MatchingEngine::next_random`]: the state is seeded from config,
+    /// advances only with draws, and is persisted in snapshots, so replay
+    /// reproduces every draw exactly.
+    rng_state: u64,
+    /// `(timestamp, notional, volume)` per trade for rolling VWAP, oldest at
+    /// the front. Bounded by lazily evicting entries older than
+    /// [`MAX_VWAP_WINDOW_NS`] on insert.
+    vwap_trades: VecDeque<(i64, Decimal, Decimal)>,
+}
+
+/// Trades older than this are dropped from the VWAP window on insert, so the
+/// deque stays bounded even when nobody queries VWAP.
+const MAX_VWAP_WINDOW_NS: i64 = 3_600_000_000_000;
+
+impl MatchingEngine {
+    pub fn new(market_id: impl Into<String>, recent_trades_capacity: usize) -> Self {
+        let market_id = market_id.into();
+        let (book_tx, _) = broadcast::channel(1024);
+        let (bbo_tx, _) = broadcast::channel(1024);
+        let (trade_tx, _) = broadcast::channel(1024);
+        let (order_tx, _) = broadcast::channel(1024);
+        let (phase_tx, _) = broadcast::channel(1024);
+        MatchingEngine {
+            orderbook: Orderbook::new(market_id.clone()),
+            market_id,
+            recent_trades: VecDeque::new(),
+            recent_trades_capacity,
+            spill: None,
+            pre_trade: None,
+            phase: MarketPhase::default(),
+            phase_tx,
+            next_trade_id: 1,
+            book_tx,
+            bbo_tx,
+            book_stream_sequence: 0,
+            trade_stream_sequence: 0,
+            last_bbo: Bbo::default(),
+            quality: MatchQualityStats::default(),
+            trade_tx,
+            order_tx,
+            expiry_heap: BinaryHeap::new(),
+            maker_fee_bps: Decimal::ZERO,
+            taker_fee_bps: Decimal::ZERO,
+            fee_tiers: Vec::new(),
+            user_volumes: HashMap::new(),
+            lot_size: Decimal::ZERO,
+            tick_size: Decimal::ZERO,
+            pegged_orders: Vec::new(),
+            repriced: Vec::new(),
+            dust_cancels: Vec::new(),
+            pricing: PricingPolicy::default(),
+            fee_ledger: HashMap::new(),
+            rng_state: 0,
+            vwap_trades: VecDeque::new(),
+            filled_makers: Vec::new(),
+            sinks: Vec::new(),
+            no_locked_quotes: false,
+            last_look_window_ns: 0,
+            last_look_cancels: Vec::new(),
+            age_heap: BinaryHeap::new(),
+            max_order_age_ns: 0,
+            reference: None,
+            trade_report_delay_ns: 0,
+            held_prints: VecDeque::new(),
+            trade_through_tolerance: Decimal::ZERO,
+            trade_through_count: 0,
+            trade_through_flags: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the expiry and age heaps from the current book, used after
+    /// restoring a snapshot.
+    pub fn rebuild_expiry_heap(&mut self) {
+        self.expiry_heap = self
+            .orderbook
+            .orders
+            .values()
+            .filter_map(|o| o.expires_at.map(|at| Reverse((at, o.id))))
+            .collect();
+        self.age_heap = self
+            .orderbook
+            .orders
+            .values()
+            .map(|o| Reverse((o.timestamp, o.id)))
+            .collect();
+    }
+
+    pub fn next_trade_id(&self) -> u64 {
+        self.next_trade_id
+    }
+
+    pub fn set_next_trade_id(&mut self, id: u64) {
+        self.next_trade_id = id;
+    }
+
+    pub fn subscribe_book(&self) -> broadcast::Receiver<BookUpdate> {
+        self.book_tx.subscribe()
+    }
+
+    pub fn subscribe_bbo(&self) -> broadcast::Receiver<BboUpdate> {
+        self.bbo_tx.subscribe()
+    }
+
+    pub fn subscribe_phases(&self) -> broadcast::Receiver<PhaseUpdate> {
+        self.phase_tx.subscribe()
+    }
+
+    pub fn phase(&self) -> MarketPhase {
+        self.phase
+    }
+
+    /// Restores the phase from a snapshot without running an auction or
+    /// emitting a transition event.
+    pub fn restore_phase(&mut self, phase: MarketPhase) {
+        self.phase = phase;
+    }
+
+    /// Moves the market to `phase`. Entering [`MarketPhase::Open`] or
+    /// [`MarketPhase::Close`] runs the auction uncross over the accumulated
+    /// book and returns its trades — within each crossed pair the younger
+    /// order takes, so auction fills print at the earlier order's price.
+    /// Every actual transition broadcasts a [`PhaseUpdate`]; setting the
+    /// current phase again is a no-op.
+    pub fn set_phase(&mut self, phase: MarketPhase) -> Vec<Trade> {
+        if phase == self.phase {
+            return Vec::new();
+        }
+        self.phase = phase;
+        let trades = match phase {
+            MarketPhase::Open | MarketPhase::Close => self.uncross(),
+            MarketPhase::PreOpen | MarketPhase::Continuous => Vec::new(),
+        };
+        let _ = self.phase_tx.send(PhaseUpdate {
+            market_id: self.market_id.clone(),
+            phase,
+            timestamp: now_ns(),
+        });
+        trades
+    }
+
+    /// Whether the book is internally crossed: a resting bid at or above a
+    /// resting ask. Matching invariants should make this impossible, so a
+    /// crossed book indicates corruption (see [`CrossedBookPolicy`]).
+    pub fn book_is_crossed(&self) -> bool {
+        match (self.orderbook.best_bid(), self.orderbook.best_ask()) {
+            (Some(bid), Some(ask)) => bid.price >= ask.price,
+            _ => false,
+        }
+    }
+
+    /// Matches crossed resting orders against each other until the book
+    /// uncrosses, returning the trades. Within each crossed pair the
+    /// younger order acts as taker, so trades print at the price of the
+    /// order that rested first — the same rule as live matching.
+    pub fn uncross(&mut self) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        loop {
+            let pair = match (
+                self.orderbook.orders_in_priority(Side::Buy).next(),
+                self.orderbook.orders_in_priority(Side::Sell).next(),
+            ) {
+                (Some(bid), Some(ask)) if bid.price >= ask.price => (bid.clone(), ask.clone()),
+                _ => break,
+            };
+            let (bid, ask) = pair;
+            let taker_id = if bid.sequence > ask.sequence { bid.id } else { ask.id };
+            let Some(mut taker) = self.orderbook.remove_order(taker_id) else {
+                break;
+            };
+            let maker = if taker_id == bid.id { ask } else { bid };
+            let quantity = taker.remaining_quantity.min(maker.remaining_quantity);
+            let Some(trade) = self.execute_trade(&mut taker, &maker, quantity) else {
+                // A risk veto mid-repair: put the untouched taker back and
+                // leave the remaining cross for operators.
+                self.orderbook.add_order(taker);
+                break;
+            };
+            trades.push(trade);
+            if taker.remaining_quantity > Decimal::ZERO {
+                taker.status = OrderStatus::PartiallyFilled;
+                self.orderbook.add_order(taker);
+            }
+        }
+        if !trades.is_empty() {
+            self.publish_book_update();
+        }
+        trades
+    }
+
+    /// Current top-of-book, read straight from the book.
+    pub fn current_bbo(&self) -> Bbo {
+        Bbo {
+            bid: self
+                .orderbook
+                .best_bid()
+                .map(|l| (l.price, l.total_quantity())),
+            ask: self
+                .orderbook
+                .best_ask()
+                .map(|l| (l.price, l.total_quantity())),
+        }
+    }
+
+    /// Current book stream position: the sequence on the last published
+    /// [`BookUpdate`], for anchoring resyncs.
+    pub fn book_stream_sequence(&self) -> u64 {
+        self.book_stream_sequence
+    }
+
+    /// Current trade-tape stream position.
+    pub fn trade_stream_sequence(&self) -> u64 {
+        self.trade_stream_sequence
+    }
+
+    pub fn subscribe_trades(&self) -> broadcast::Receiver<TradePrint> {
+        self.trade_tx.subscribe()
+    }
+
+    pub fn subscribe_orders(&self) -> broadcast::Receiver<OrderUpdate> {
+        self.order_tx.subscribe()
+    }
+
+    fn publish_order_update(&self, order: &Order) {
+        let _ = self.order_tx.send(OrderUpdate {
+            market_id: self.market_id.clone(),
+            order: order.clone(),
+            timestamp: now_ns(),
+        });
+    }
+
+    /// Registers a sink to receive every trade this engine executes.
+    pub fn register_sink(&mut self, sink: Box<dyn TradeSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Installs the destination for trades evicted from the recent-trades
+    /// ring; without one, evicted trades are dropped.
+    pub fn set_trade_spill(&mut self, spill: Box<dyn TradeSpill>) {
+        self.spill = Some(spill);
+    }
+
+    /// Installs the pre-trade risk check; see [`PreTradeCheck`].
+    pub fn set_pre_trade_check(&mut self, check: Box<dyn PreTradeCheck>) {
+        self.pre_trade = Some(check);
+    }
+
+    /// Drains the makers fully filled since the last call. Each order
+    /// appears at most once: it is recorded at the moment it leaves the
+    /// book, which happens once per order.
+    pub fn take_filled_makers(&mut self) -> Vec<(u64, Decimal)> {
+        std::mem::take(&mut self.filled_makers)
+    }
+
+    /// Seeds the deterministic matching RNG; identical seeds and event
+    /// streams yield identical draw sequences.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng_state = seed;
+    }
+
+    /// Current RNG state, persisted in snapshots so a restore resumes the
+    /// draw sequence exactly where the snapshot left it.
+    pub fn rng_state(&self) -> u64 {
+        self.rng_state
+    }
+
+    pub fn set_rng_state(&mut self, state: u64) {
+        self.rng_state = state;
+    }
+
+    /// Next draw from the deterministic matching RNG (SplitMix64). Every
+    /// randomized matching decision must come from here and nowhere else.
+    pub fn next_random(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn set_no_locked_quotes(&mut self, enabled: bool) {
+        self.no_locked_quotes = enabled;
+    }
+
+    pub fn set_last_look_window(&mut self, window_ns: i64) {
+        self.last_look_window_ns = window_ns;
+    }
+
+    pub fn set_max_order_age(&mut self, max_age_ns: i64) {
+        self.max_order_age_ns = max_age_ns;
+    }
+
+    /// Installs the reference feed used for trade-through checks.
+    pub fn set_reference_provider(&mut self, provider: Box<dyn ReferencePriceProvider>) {
+        self.reference = Some(provider);
+    }
+
+    pub fn set_trade_through_tolerance(&mut self, tolerance: Decimal) {
+        self.trade_through_tolerance = tolerance;
+    }
+
+    pub fn set_trade_report_delay(&mut self, delay_ns: i64) {
+        self.trade_report_delay_ns = delay_ns;
+    }
+
+    /// Releases held trade prints whose reporting delay has elapsed, in
+    /// execution order: each lands on the recent-trades backfill and the
+    /// live tape at once. Driven by the reaper tick; a no-op for markets
+    /// without a delay.
+    pub fn flush_trade_prints(&mut self, now: i64) {
+        while let Some(held) = self.held_prints.front() {
+            if held.trade.timestamp + self.trade_report_delay_ns > now {
+                break;
+            }
+            let mut print = self.held_prints.pop_front().expect("peeked entry");
+            self.push_recent_trade(print.trade.clone());
+            self.trade_stream_sequence += 1;
+            print.stream_sequence = self.trade_stream_sequence;
+            let _ = self.trade_tx.send(print);
+        }
+    }
+
+    /// Midpoint of the installed reference feed's current quote, or `None`
+    /// when no feed is installed or it has no quote for this market.
+    pub fn reference_mid(&self) -> Option<Decimal> {
+        let (bid, ask) = self.reference.as_ref()?.reference(&self.market_id)?;
+        Some((bid + ask) / Decimal::TWO)
+    }
+
+    /// Total trades that executed through the reference by more than the
+    /// tolerance since startup.
+    pub fn trade_through_count(&self) -> u64 {
+        self.trade_through_count
+    }
+
+    /// Drains the trade-through flags raised since the last call, as
+    /// `(trade_id, amount through the reference)`.
+    pub fn take_trade_through_flags(&mut self) -> Vec<(u64, Decimal)> {
+        std::mem::take(&mut self.trade_through_flags)
+    }
+
+    /// Age of the oldest resting order, or `None` for an empty book. Pops
+    /// stale heap entries (orders gone or re-rested with a newer timestamp)
+    /// on the way, so repeated calls stay cheap.
+    /// Execution-quality counters accumulated since engine start.
+    pub fn match_quality(&self) -> &MatchQualityStats {
+        &self.quality
+    }
+
+    pub fn oldest_resting_age_ns(&mut self, now: i64) -> Option<i64> {
+        while let Some(&Reverse((ts, order_id))) = self.age_heap.peek() {
+            let live = self
+                .orderbook
+                .get_order(order_id)
+                .is_some_and(|o| o.timestamp == ts);
+            if live {
+                return Some(now - ts);
+            }
+            self.age_heap.pop();
+        }
+        None
+    }
+
+    /// Cancels every resting order older than the market's max age, popping
+    /// only the due entries like [`MatchingEngine::reap_expired`]. Unlike
+    /// GTD expiry this is a rolling age from placement; an amend re-rests
+    /// the order and restarts its clock. Returns the evicted orders.
+    pub fn evict_stale(&mut self, now: i64) -> Vec<Order> {
+        let mut evicted = Vec::new();
+        if self.max_order_age_ns <= 0 {
+            return evicted;
+        }
+        let cutoff = now - self.max_order_age_ns;
+        while let Some(&Reverse((ts, order_id))) = self.age_heap.peek() {
+            if ts > cutoff {
+                break;
+            }
+            self.age_heap.pop();
+            // Lazy deletion: skip entries whose order is gone or re-rested.
+            let still_due = self
+                .orderbook
+                .get_order(order_id)
+                .is_some_and(|o| o.timestamp == ts);
+            if !still_due {
+                continue;
+            }
+            if let Some(mut order) = self.orderbook.remove_order(order_id) {
+                order.status = OrderStatus::Cancelled;
+                evicted.push(order);
+            }
+        }
+        if !evicted.is_empty() {
+            self.publish_book_update();
+        }
+        evicted
+    }
+
+    /// Cancels resting orders whose remainder has fallen below the market
+    /// lot size. Matching already trims sub-lot maker dust as it forms, but
+    /// residuals placed before a lot size was configured — or stranded by a
+    /// lot-size change — stay on the book until swept. A maintenance pass
+    /// meant for a periodic schedule. Returns the swept orders.
+    pub fn sweep_dust(&mut self) -> Vec<Order> {
+        let mut swept = Vec::new();
+        if self.lot_size <= Decimal::ZERO {
+            return swept;
+        }
+        let dust: Vec<OrderId> = self
+            .orderbook
+            .orders
+            .values()
+            .filter(|o| o.remaining_quantity < self.lot_size)
+            .map(|o| o.id)
+            .collect();
+        for order_id in dust {
+            if let Some(mut order) = self.orderbook.remove_order(order_id) {
+                order.status = OrderStatus::Cancelled;
+                swept.push(order);
+            }
+        }
+        if !swept.is_empty() {
+            self.publish_book_update();
+        }
+        swept
+    }
+
+    /// Drains the makers cancelled by last look since the last call, so the
+    /// exchange can journal the cancels and notify owners.
+    pub fn take_last_look_cancels(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.last_look_cancels)
+    }
+
+    pub fn set_lot_size(&mut self, lot_size: Decimal) {
+        self.lot_size = lot_size;
+    }
+
+    pub fn set_tick_size(&mut self, tick_size: Decimal) {
+        self.tick_size = tick_size;
+    }
+
+    /// Drains the peg re-prices since the last call, as
+    /// `(order_id, new_price)`; journaled by the exchange as audit records.
+    pub fn take_repriced(&mut self) -> Vec<(u64, Decimal)> {
+        std::mem::take(&mut self.repriced)
+    }
+
+    /// Re-seeds the pegged-order index by scanning the book; called after a
+    /// snapshot restore replaces the orderbook wholesale.
+    pub fn rebuild_pegged_index(&mut self) {
+        self.pegged_orders = self
+            .orderbook
+            .orders
+            .values()
+            .filter(|o| o.peg.is_some())
+            .map(|o| o.id)
+            .collect();
+        self.pegged_orders.sort_unstable();
+    }
+
+    /// Drains the sub-lot maker remainders cancelled since the last call,
+    /// so the exchange can journal the cancels; same contract as
+    /// [`MatchingEngine::take_last_look_cancels`].
+    pub fn take_dust_cancels(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.dust_cancels)
+    }
+
+    pub fn set_pricing_policy(&mut self, pricing: PricingPolicy) {
+        self.pricing = pricing;
+    }
+
+    pub fn set_fee_schedule(&mut self, maker_fee_bps: Decimal, taker_fee_bps: Decimal) {
+        self.maker_fee_bps = maker_fee_bps;
+        self.taker_fee_bps = taker_fee_bps;
+    }
+
+    /// Installs the volume-tiered fee schedule; tiers are kept sorted by
+    /// threshold so the highest tier a user qualifies for wins.
+    pub fn set_fee_tiers(&mut self, mut tiers: Vec<FeeTier>) {
+        tiers.sort_by_key(|tier| tier.min_volume);
+        self.fee_tiers = tiers;
+    }
+
+    /// Maker and taker rates for this user under the tier schedule, falling
+    /// back to the market's flat rates below the lowest threshold.
+    fn fee_bps_for(&self, user_id: u64) -> (Decimal, Decimal) {
+        let volume = self.user_volume(user_id);
+        let mut rates = (self.maker_fee_bps, self.taker_fee_bps);
+        for tier in &self.fee_tiers {
+            if volume >= tier.min_volume {
+                rates = (tier.maker_fee_bps, tier.taker_fee_bps);
+            }
+        }
+        rates
+    }
+
+    /// Cumulative traded base quantity for a user on this market.
+    pub fn user_volume(&self, user_id: u64) -> Decimal {
+        self.user_volumes.get(&user_id).copied().unwrap_or_default()
+    }
+
+    pub fn user_volumes(&self) -> &HashMap<u64, Decimal> {
+        &self.user_volumes
+    }
+
+    /// Restores the per-user volume counters from a snapshot.
+    pub fn restore_user_volumes(&mut self, volumes: HashMap<u64, Decimal>) {
+        self.user_volumes = volumes;
+    }
+
+    /// Net fees this user has accrued; negative means rebates exceed fees.
+    pub fn fees_accrued(&self, user_id: u64) -> Decimal {
+        self.fee_ledger.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// Re-prices resting pegged orders against their references; runs before
+    /// every published book update so pegs track each mutation. Each order
+    /// is re-priced at most once per pass, with the order itself lifted out
+    /// of the book first so it never chases its own quote; moving loses
+    /// queue priority, as an amend would. A move that would lock or cross
+    /// the opposite touch is skipped until the book allows it. Iteration is
+    /// in ascending id order so replay reproduces the same moves.
+    fn reprice_pegged(&mut self) {
+        if self.pegged_orders.is_empty() {
+            return;
+        }
+        let book = &self.orderbook;
+        self.pegged_orders.retain(|id| book.get_order(*id).is_some());
+        for id in self.pegged_orders.clone() {
+            // First pass with the order still resting: most updates move no
+            // peg, and an order whose level is already right keeps its queue
+            // position untouched.
+            let Some(order) = self.orderbook.get_order(id) else {
+                continue;
+            };
+            let current = order.price;
+            if self.peg_target(order).is_none_or(|t| t == current) {
+                continue;
+            }
+            // Lift the order out and re-derive the target without it, so a
+            // peg never chases its own quote.
+            let Some(order) = self.orderbook.remove_order(id) else {
+                continue;
+            };
+            let locks_or_crosses = |price: Decimal| match order.side {
+                Side::Buy => self.orderbook.best_ask().is_some_and(|l| price >= l.price),
+                Side::Sell => self.orderbook.best_bid().is_some_and(|l| price <= l.price),
+            };
+            match self.peg_target(&order) {
+                Some(target)
+                    if target > Decimal::ZERO
+                        && target != order.price
+                        && !locks_or_crosses(target) =>
+                {
+                    let mut moved = order;
+                    moved.price = target;
+                    self.orderbook.add_order(moved);
+                    self.repriced.push((id, target));
+                }
+                // No usable reference, already at its level, or the move
+                // would lock or cross: put the order back untouched.
+                _ => self.orderbook.add_order(order),
+            }
+        }
+    }
+
+    /// The level a pegged order should rest at given the current book:
+    /// reference price plus the bps offset, snapped to the tick grid
+    /// (conservatively — down for bids, up for asks). `None` when the order
+    /// is not pegged or its reference is undefined.
+    fn peg_target(&self, order: &Order) -> Option<Decimal> {
+        let reference = match order.peg? {
+            PegReference::Bid => self.orderbook.best_bid().map(|l| l.price)?,
+            PegReference::Ask => self.orderbook.best_ask().map(|l| l.price)?,
+            PegReference::Mid => {
+                let bid = self.orderbook.best_bid()?.price;
+                let ask = self.orderbook.best_ask()?.price;
+                (bid + ask) / Decimal::TWO
+            }
+        };
+        let raw = reference + reference * order.peg_offset_bps / Decimal::from(10_000u32);
+        Some(match (self.tick_size > Decimal::ZERO, order.side) {
+            (true, Side::Buy) => (raw / self.tick_size).floor() * self.tick_size,
+            (true, Side::Sell) => (raw / self.tick_size).ceil() * self.tick_size,
+            (false, _) => raw,
+        })
+    }
+
+    pub(crate) fn publish_book_update(&mut self) {
+        self.reprice_pegged();
+        // Nobody listening is fine; subscribers re-pull depth on each update.
+        self.book_stream_sequence += 1;
+        let _ = self.book_tx.send(BookUpdate {
+            market_id: self.market_id.clone(),
+            stream_sequence: self.book_stream_sequence,
+            timestamp: now_ns(),
+        });
+        // BBO notifications only fire when the touch actually moved.
+        let bbo = self.current_bbo();
+        if bbo != self.last_bbo {
+            let _ = self.bbo_tx.send(BboUpdate {
+                market_id: self.market_id.clone(),
+                bbo: bbo.clone(),
+                timestamp: now_ns(),
+            });
+            self.last_bbo = bbo;
+        }
+    }
+
+    /// Runs the matching loop for an accepted order, mutating the book.
+    /// Returns the order in its final state and any trades produced. In an
+    /// auction-call phase (see [`MarketPhase`]) the matching loop is
+    /// skipped: limit orders rest — possibly crossed — until the next
+    /// uncross, and market orders cancel for want of a match.
+    pub fn place_order(&mut self, mut order: Order) -> (Order, Vec<Trade>) {
+        let mut trades = Vec::new();
+        let mut stp_blocked = false;
+        let mut risk_blocked = false;
+        while self.phase.is_matching() && order.remaining_quantity > Decimal::ZERO {
+            let maker = match self.get_next_maker(order.side, order.remaining_quantity) {
+                Some(m) => m,
+                None => break,
+            };
+            if !Self::crosses(&order, &maker) {
+                break;
+            }
+            // Self-trade prevention, decline-taker policy: rather than match
+            // (or rest crossed against) its own firm's order, the incoming
+            // order stops matching and its remainder is cancelled below.
+            if Self::same_ownership(&order, &maker) {
+                stp_blocked = true;
+                break;
+            }
+            // Last look: a protected maker quoted within the window declines
+            // the match. The stale quote is cancelled and the taker carries
+            // on to the next maker.
+            if maker.last_look
+                && self.last_look_window_ns > 0
+                && order.timestamp - maker.timestamp < self.last_look_window_ns
+            {
+                if let Some(mut declined) = self.orderbook.remove_order(maker.id) {
+                    declined.status = OrderStatus::Cancelled;
+                    self.last_look_cancels.push(declined);
+                }
+                continue;
+            }
+            let mut quantity = if order.quantity_in_quote {
+                // Remaining quantity is quote notional: buy as much base as
+                // it affords at this level.
+                (order.remaining_quantity / maker.price).min(maker.remaining_quantity)
+            } else {
+                order.remaining_quantity.min(maker.remaining_quantity)
+            };
+            // Clamp to the lot grid: quote-notional sweeps (and resting
+            // remainders of them) can be sub-lot even though placements are
+            // validated, and a sub-lot fill would strand dust on the maker.
+            if self.lot_size > Decimal::ZERO {
+                quantity = (quantity / self.lot_size).floor() * self.lot_size;
+            }
+            if quantity <= Decimal::ZERO {
+                break;
+            }
+            let Some(trade) = self.execute_trade(&mut order, &maker, quantity) else {
+                // The risk check vetoed the fill: nothing changed, and the
+                // taker's remainder is cancelled below rather than rested.
+                risk_blocked = true;
+                break;
+            };
+            trades.push(trade);
+        }
+
+        if order.remaining_quantity > Decimal::ZERO {
+            // A base-quantity remainder below one lot can never trade;
+            // cancel it rather than let it rest as dust.
+            let dust = !order.quantity_in_quote
+                && self.lot_size > Decimal::ZERO
+                && order.remaining_quantity < self.lot_size;
+            // Under the no-lock policy a remainder the matching loop could
+            // not trade out of the opposite touch (a skipped all-or-none
+            // maker, say) must not rest as a locked or crossed quote.
+            // (In an auction call, crossed resting is the whole point, so
+            // the no-lock policy only applies while matching is live.)
+            let locks = self.no_locked_quotes
+                && self.phase.is_matching()
+                && match order.side {
+                    Side::Buy => self
+                        .orderbook
+                        .best_ask()
+                        .is_some_and(|l| order.price >= l.price),
+                    Side::Sell => self
+                        .orderbook
+                        .best_bid()
+                        .is_some_and(|l| order.price <= l.price),
+                };
+            let rests = order.order_type == OrderType::Limit
+                && order.time_in_force != TimeInForce::Ioc
+                && !stp_blocked
+                && !risk_blocked
+                && !dust
+                && !locks;
+            if rests {
+                order.status = if trades.is_empty() {
+                    OrderStatus::New
+                } else {
+                    OrderStatus::PartiallyFilled
+                };
+                self.orderbook.add_order(order.clone());
+                if order.peg.is_some() && !self.pegged_orders.contains(&order.id) {
+                    self.pegged_orders.push(order.id);
+                    self.pegged_orders.sort_unstable();
+                }
+                self.age_heap.push(Reverse((order.timestamp, order.id)));
+                if let Some(at) = order.expires_at {
+                    self.expiry_heap.push(Reverse((at, order.id)));
+                }
+            } else {
+                order.status = OrderStatus::Cancelled;
+            }
+        } else {
+            order.status = OrderStatus::Filled;
+        }
+
+        self.quality.incoming_quantity += order.quantity;
+        self.quality.incoming_filled += order.quantity - order.remaining_quantity;
+        self.publish_book_update();
+        (order, trades)
+    }
+
+    /// Best opposite-side resting order the taker is allowed to match:
+    /// all-or-none makers whose remaining quantity exceeds the taker's are
+    /// skipped (each loop iteration re-selects, so a skipped AON maker is
+    /// simply passed over rather than revisited). Note a skipped AON maker
+    /// does not block trading at worse prices behind it.
+    fn get_next_maker(&self, taker_side: Side, taker_remaining: Decimal) -> Option<Order> {
+        self.orderbook
+            .orders_in_priority(taker_side.opposite())
+            .find(|maker| !maker.all_or_none || maker.remaining_quantity <= taker_remaining)
+            .cloned()
+    }
+
+    /// Whether two orders belong to the same owner for self-trade
+    /// prevention: a shared account group, or the same user when either
+    /// side has no group.
+    fn same_ownership(taker: &Order, maker: &Order) -> bool {
+        match (&taker.account_group, &maker.account_group) {
+            (Some(t), Some(m)) => t == m,
+            _ => taker.user_id == maker.user_id,
+        }
+    }
+
+    fn crosses(taker: &Order, maker: &Order) -> bool {
+        match taker.order_type {
+            OrderType::Market => true,
+            OrderType::Limit => match taker.side {
+                Side::Buy => taker.price >= maker.price,
+                Side::Sell => taker.price <= maker.price,
+            },
+        }
+    }
+
+    /// Executes a single fill at the maker's price, updating both sides and
+    /// recording the trade. Returns `None` when the pre-trade check vetoes
+    /// the fill, in which case nothing — orders, book, trade id — has
+    /// changed.
+    fn execute_trade(
+        &mut self,
+        taker: &mut Order,
+        maker: &Order,
+        quantity: Decimal,
+    ) -> Option<Trade> {
+        let trade = Trade {
+            id: self.next_trade_id,
+            market_id: self.market_id.clone(),
+            price: maker.price,
+            quantity,
+            maker_order_id: maker.id,
+            taker_order_id: taker.id,
+            maker_user_id: maker.user_id,
+            taker_user_id: taker.user_id,
+            timestamp: now_ns(),
+            aggressor: Some(taker.side),
+        };
+        if let Some(check) = &mut self.pre_trade {
+            if !check.approve(&trade) {
+                return None;
+            }
+        }
+
+        taker.remaining_quantity -= if taker.quantity_in_quote {
+            quantity * maker.price
+        } else {
+            quantity
+        };
+
+        let mut maker = maker.clone();
+        // Queue-quality timing: a maker's first fill ends its wait at the
+        // front of the queue; a full fill ends its life on the book.
+        if maker.status == OrderStatus::New {
+            self.quality.first_fills += 1;
+            self.quality.time_to_first_fill_ns = self
+                .quality
+                .time_to_first_fill_ns
+                .saturating_add(trade.timestamp.saturating_sub(maker.timestamp));
+        }
+        maker.remaining_quantity -= quantity;
+        if maker.remaining_quantity <= Decimal::ZERO {
+            maker.status = OrderStatus::Filled;
+            self.quality.full_fills += 1;
+            self.quality.time_to_full_fill_ns = self
+                .quality
+                .time_to_full_fill_ns
+                .saturating_add(trade.timestamp.saturating_sub(maker.timestamp));
+            self.orderbook.remove_order(maker.id);
+            self.filled_makers.push((maker.id, maker.quantity));
+        } else if self.lot_size > Decimal::ZERO && maker.remaining_quantity < self.lot_size {
+            // Sub-lot maker remainder: cancel the dust instead of leaving a
+            // resting order that can never fully trade.
+            maker.status = OrderStatus::Cancelled;
+            self.orderbook.remove_order(maker.id);
+            self.dust_cancels.push(maker.clone());
+        } else {
+            maker.status = OrderStatus::PartiallyFilled;
+            self.orderbook.update_order(&maker);
+        }
+        self.publish_order_update(&maker);
+
+        self.next_trade_id += 1;
+        self.record_trade(trade.clone(), taker.public);
+
+        // Derived quantities go through the pricing policy so rounding is
+        // deterministic instead of whatever fits in 28 significant digits.
+        let notional = self.pricing.round(trade.price * trade.quantity);
+        let bps = Decimal::from(10_000u32);
+        let (maker_bps, _) = self.fee_bps_for(maker.user_id);
+        let (_, taker_bps) = self.fee_bps_for(taker.user_id);
+        let maker_fee = self.pricing.round(notional * maker_bps / bps);
+        let taker_fee = self.pricing.round(notional * taker_bps / bps);
+        *self.fee_ledger.entry(maker.user_id).or_default() += maker_fee;
+        *self.fee_ledger.entry(taker.user_id).or_default() += taker_fee;
+        // Volume accrues after rate selection: the fill that crosses a tier
+        // boundary still pays the old rate; the discount starts on the next.
+        *self.user_volumes.entry(maker.user_id).or_default() += trade.quantity;
+        *self.user_volumes.entry(taker.user_id).or_default() += trade.quantity;
+
+        // Best-execution check: flag fills through the reference price by
+        // more than the tolerance. Matching is never blocked here; the fill
+        // already happened at the maker's price, so this is surveillance,
+        // not prevention.
+        if let Some(provider) = &self.reference {
+            if let Some((ref_bid, ref_ask)) = provider.reference(&self.market_id) {
+                let through = match taker.side {
+                    Side::Buy => trade.price - ref_ask,
+                    Side::Sell => ref_bid - trade.price,
+                };
+                if through > self.trade_through_tolerance {
+                    self.trade_through_count += 1;
+                    self.trade_through_flags.push((trade.id, through));
+                }
+            }
+        }
+
+        if taker.public {
+            let print = TradePrint {
+                trade: trade.clone(),
+                aggressor: taker.side,
+                maker_fee,
+                taker_fee,
+                stream_sequence: 0,
+            };
+            if self.trade_report_delay_ns > 0 {
+                // Dark-pool-style reporting: the print (and its backfill
+                // entry) waits out the market's delay; see
+                // [`MatchingEngine::flush_trade_prints`].
+                self.held_prints.push_back(print);
+            } else {
+                self.trade_stream_sequence += 1;
+                let _ = self.trade_tx.send(TradePrint {
+                    stream_sequence: self.trade_stream_sequence,
+                    ..print
+                });
+            }
+        }
+        for sink in &mut self.sinks {
+            sink.on_trade(&trade, taker.side);
+        }
+        Some(trade)
+    }
+
+    /// Records a trade for VWAP and, when `public`, for the recent-trades
+    /// buffer that backfills the tape. Hidden flow only feeds aggregates.
+    pub(crate) fn record_trade(&mut self, trade: Trade, public: bool) {
+        let notional = self.pricing.round(trade.price * trade.quantity);
+        self.vwap_trades
+            .push_back((trade.timestamp, notional, trade.quantity));
+        self.evict_vwap_before(trade.timestamp - MAX_VWAP_WINDOW_NS);
+
+        if !public {
+            return;
+        }
+        if self.trade_report_delay_ns > 0 {
+            // The held print carries the trade; it reaches the backfill
+            // buffer when the reporting delay elapses.
+            return;
+        }
+        self.push_recent_trade(trade);
+    }
+
+    fn push_recent_trade(&mut self, trade: Trade) {
+        if self.recent_trades.len() >= self.recent_trades_capacity {
+            if let Some(evicted) = self.recent_trades.pop_front() {
+                if let Some(spill) = &mut self.spill {
+                    spill.on_evict(&evicted);
+                }
+            }
+        }
+        self.recent_trades.push_back(trade);
+    }
+
+    fn evict_vwap_before(&mut self, cutoff: i64) {
+        while let Some(&(ts, _, _)) = self.vwap_trades.front() {
+            if ts >= cutoff {
+                break;
+            }
+            self.vwap_trades.pop_front();
+        }
+    }
+
+    /// Volume-weighted average price of trades within `window_ns` of `now`,
+    /// returned as `(vwap, volume, notional)`. Returns `None` when no trades
+    /// fall inside the window.
+    pub fn vwap(&mut self, window_ns: i64, now: i64) -> Option<(Decimal, Decimal, Decimal)> {
+        let cutoff = now - window_ns;
+        let mut notional = Decimal::ZERO;
+        let mut volume = Decimal::ZERO;
+        for &(ts, n, v) in self.vwap_trades.iter().rev() {
+            if ts < cutoff {
+                break;
+            }
+            notional += n;
+            volume += v;
+        }
+        if volume <= Decimal::ZERO {
+            return None;
+        }
+        Some((self.pricing.round(notional / volume), volume, notional))
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
+        let mut order = self.orderbook.remove_order(order_id)?;
+        order.status = OrderStatus::Cancelled;
+        self.publish_order_update(&order);
+        self.publish_book_update();
+        Some(order)
+    }
+
+    /// Cancel-and-replace: the order keeps its id but loses queue priority.
+    pub fn amend_order(
+        &mut self,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+        sequence: u64,
+    ) -> Option<(Order, Vec<Trade>)> {
+        let existing = self.orderbook.remove_order(order_id)?;
+        let filled = existing.filled_quantity();
+        let mut order = existing;
+        order.price = new_price;
+        order.quantity = new_quantity;
+        order.remaining_quantity = (new_quantity - filled).max(Decimal::ZERO);
+        order.sequence = sequence;
+        order.timestamp = now_ns();
+        if order.remaining_quantity <= Decimal::ZERO {
+            order.status = OrderStatus::Filled;
+            self.publish_book_update();
+            return Some((order, Vec::new()));
+        }
+        Some(self.place_order(order))
+    }
+
+    /// Shrinks a resting order in place, preserving its queue position
+    /// (unlike an amend, which is cancel-and-replace). Reducing the entire
+    /// remainder cancels the order. Returns `None` when not resting.
+    pub fn reduce_order(&mut self, order_id: u64, reduce_by: Decimal) -> Option<Order> {
+        let mut order = self.orderbook.get_order(order_id)?.clone();
+        if reduce_by >= order.remaining_quantity {
+            return self.cancel_order(order_id);
+        }
+        order.quantity -= reduce_by;
+        order.remaining_quantity -= reduce_by;
+        self.orderbook.update_order(&order);
+        self.publish_book_update();
+        Some(order)
+    }
+
+    // ---- Literal replay ----
+    //
+    // The methods below apply journaled operations as recorded instead of
+    // re-running matching; see [`RecoveryReplay::Literal`]. None of them
+    // publish stream updates — during recovery there are no subscribers,
+    // and a publish would re-run peg repricing against a half-rebuilt book.
+
+    /// Inserts a replayed placement without running the matching loop. The
+    /// trades this order took in the original timeline arrive as their own
+    /// records, so matching here would double-count them. Orders that can
+    /// never rest — market orders, IOC — are skipped; their fills and
+    /// remainder cancels are applied entirely from the records.
+    pub fn restore_order(&mut self, order: Order) {
+        if order.order_type != OrderType::Limit || order.time_in_force == TimeInForce::Ioc {
+            return;
+        }
+        self.orderbook.add_order(order.clone());
+        if order.peg.is_some() && !self.pegged_orders.contains(&order.id) {
+            self.pegged_orders.push(order.id);
+            self.pegged_orders.sort_unstable();
+        }
+        self.age_heap.push(Reverse((order.timestamp, order.id)));
+        if let Some(at) = order.expires_at {
+            self.expiry_heap.push(Reverse((at, order.id)));
+        }
+    }
+
+    /// Applies one recorded trade authoritatively: both resting legs are
+    /// reduced by the traded quantity and removed when exhausted, exactly
+    /// as the original execution left them. The public tape is not
+    /// repopulated — visibility is not journaled, and an empty backfill
+    /// after restart is already the snapshot-recovery behavior.
+    pub fn apply_recorded_trade(&mut self, trade: &Trade) {
+        for (order_id, taker_leg) in [(trade.maker_order_id, false), (trade.taker_order_id, true)]
+        {
+            let Some(order) = self.orderbook.get_order(order_id) else {
+                continue;
+            };
+            let mut order = order.clone();
+            order.remaining_quantity -= if taker_leg && order.quantity_in_quote {
+                trade.quantity * trade.price
+            } else {
+                trade.quantity
+            };
+            if order.remaining_quantity <= Decimal::ZERO {
+                order.status = OrderStatus::Filled;
+                self.orderbook.remove_order(order.id);
+            } else {
+                order.status = OrderStatus::PartiallyFilled;
+                self.orderbook.update_order(&order);
+            }
+        }
+        // Tier volume accrues from recorded trades too, so fee discounts
+        // resume exactly where they left off after a literal replay.
+        *self.user_volumes.entry(trade.maker_user_id).or_default() += trade.quantity;
+        *self.user_volumes.entry(trade.taker_user_id).or_default() += trade.quantity;
+        self.next_trade_id = self.next_trade_id.max(trade.id + 1);
+    }
+
+    /// Removes a replayed cancel's order, if it still rests. Covers every
+    /// journaled cancel: user cancels, expiry reaps, last-look declines,
+    /// dust sweeps and non-resting taker remainders.
+    pub fn restore_cancel(&mut self, order_id: u64) {
+        self.orderbook.remove_order(order_id);
+    }
+
+    /// Applies a replayed amend as the recorded mutation only: the fills an
+    /// amend produced in the original timeline arrive as trade records.
+    pub fn restore_amend(
+        &mut self,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+        sequence: u64,
+    ) {
+        let Some(existing) = self.orderbook.remove_order(order_id) else {
+            return;
+        };
+        let filled = existing.filled_quantity();
+        let mut order = existing;
+        order.price = new_price;
+        order.quantity = new_quantity;
+        order.remaining_quantity = (new_quantity - filled).max(Decimal::ZERO);
+        order.sequence = sequence;
+        if order.remaining_quantity > Decimal::ZERO {
+            self.orderbook.add_order(order);
+        }
+    }
+
+    /// Applies a replayed in-place reduction.
+    pub fn restore_reduce(&mut self, order_id: u64, reduce_by: Decimal) {
+        let Some(order) = self.orderbook.get_order(order_id) else {
+            return;
+        };
+        let mut order = order.clone();
+        if reduce_by >= order.remaining_quantity {
+            self.orderbook.remove_order(order_id);
+            return;
+        }
+        order.quantity -= reduce_by;
+        order.remaining_quantity -= reduce_by;
+        self.orderbook.update_order(&order);
+    }
+
+    /// Moves a replayed peg reprice to its recorded level; queue priority
+    /// at the new level follows insertion, as the original move's did.
+    pub fn apply_reprice(&mut self, order_id: u64, new_price: Decimal) {
+        let Some(mut order) = self.orderbook.remove_order(order_id) else {
+            return;
+        };
+        order.price = new_price;
+        self.orderbook.add_order(order);
+    }
+
+    /// Cancels every resting GTD order whose expiry has passed, popping only
+    /// the due entries off the expiry heap (O(due · log n), not O(book)).
+    /// Returns the expired orders.
+    pub fn reap_expired(&mut self, now: i64) -> Vec<Order> {
+        let mut expired = Vec::new();
+        while let Some(&Reverse((at, order_id))) = self.expiry_heap.peek() {
+            if at > now {
+                break;
+            }
+            self.expiry_heap.pop();
+            // Lazy deletion: skip entries whose order is gone (filled or
+            // cancelled) or was re-rested with a different expiry.
+            let still_due = self
+                .orderbook
+                .get_order(order_id)
+                .is_some_and(|o| o.expires_at == Some(at));
+            if !still_due {
+                continue;
+            }
+            if let Some(mut order) = self.orderbook.remove_order(order_id) {
+                order.status = OrderStatus::Expired;
+                expired.push(order);
+            }
+        }
+        if !expired.is_empty() {
+            self.publish_book_update();
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::{Liquidity, Order, OrderStatus, OrderType, PegReference, Side, TimeInForce};
+    use rust_decimal_macros::dec;
+
+    pub(crate) fn limit(id: u64, side: Side, price: Decimal, qty: Decimal) -> Order {
+        Order {
+            id,
+            user_id: id,
+            market_id: "BTC-USD".into(),
+            side,
+            order_type: OrderType::Limit,
+            price,
+            quantity: qty,
+            remaining_quantity: qty,
+            status: OrderStatus::New,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: None,
+            session_id: None,
+            sequence: id,
+            timestamp: id as i64,
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn crossing_limit_orders_trade_at_maker_price() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+        let (taker, trades) = engine.place_order(limit(2, Side::Buy, dec!(101), dec!(1)));
+
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100));
+        assert_eq!(trades[0].quantity, dec!(1));
+        // Maker rests with the remainder.
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(1)
+        );
+    }
+
+    #[test]
+    fn size_time_priority_fills_the_larger_later_maker_first() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.orderbook.level_ordering = crate::orderbook::LevelOrdering::PriceTimeSize;
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        engine.place_order(limit(2, Side::Sell, dec!(100), dec!(5)));
+
+        let (_, trades) = engine.place_order(limit(3, Side::Buy, dec!(100), dec!(1)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 2);
+        // The smaller, earlier quote is still resting untouched.
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(1)
+        );
+    }
+
+    #[test]
+    fn lot_grid_clamps_trades_and_cancels_sub_lot_dust() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_lot_size(dec!(1));
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(10)));
+
+        // A 10.3 taker against a 10 maker trades exactly 10; the 0.3
+        // base-quantity remainder is sub-lot dust and is cancelled, not
+        // rested.
+        let (taker, trades) = engine.place_order(limit(2, Side::Buy, dec!(100), dec!(10.3)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(10));
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+        assert_eq!(taker.remaining_quantity, dec!(0.3));
+        assert!(engine.orderbook.get_order(2).is_none());
+    }
+
+    #[test]
+    fn sub_lot_maker_remainder_is_cancelled_as_dust() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_lot_size(dec!(1));
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(10.5)));
+
+        let (_, trades) = engine.place_order(limit(2, Side::Buy, dec!(100), dec!(10)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].quantity, dec!(10));
+        // The maker's 0.5 remainder left the book as a dust cancel.
+        assert!(engine.orderbook.get_order(1).is_none());
+        let dust = engine.take_dust_cancels();
+        assert_eq!(dust.len(), 1);
+        assert_eq!(dust[0].id, 1);
+        assert_eq!(dust[0].status, OrderStatus::Cancelled);
+        assert_eq!(dust[0].remaining_quantity, dec!(0.5));
+    }
+
+    #[test]
+    fn non_crossing_limit_rests() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        let (order, trades) = engine.place_order(limit(1, Side::Buy, dec!(99), dec!(1)));
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::New);
+        assert_eq!(engine.orderbook.best_bid().unwrap().price, dec!(99));
+    }
+
+    #[test]
+    fn ioc_remainder_is_cancelled() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        let mut taker = limit(2, Side::Buy, dec!(100), dec!(3));
+        taker.time_in_force = TimeInForce::Ioc;
+        let (taker, trades) = engine.place_order(taker);
+
+        assert_eq!(trades.len(), 1);
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+        assert_eq!(taker.remaining_quantity, dec!(2));
+        assert!(engine.orderbook.get_order(2).is_none());
+    }
+
+    #[test]
+    fn partially_filled_maker_keeps_time_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        // Maker gets partially filled, then a newer maker joins at the same
+        // price. The partially-filled maker must keep the front of the queue.
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(3)));
+        let (_, trades) = engine.place_order(limit(2, Side::Buy, dec!(100), dec!(1)));
+        assert_eq!(trades.len(), 1);
+
+        // Index and level agree on the remaining quantity.
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(2)
+        );
+        let level = engine.orderbook.asks.get(&dec!(100)).unwrap().clone();
+        assert_eq!(level.orders.front().unwrap().id, 1);
+        assert_eq!(level.orders.front().unwrap().remaining_quantity, dec!(2));
+
+        engine.place_order(limit(3, Side::Sell, dec!(100), dec!(5)));
+        let (_, trades) = engine.place_order(limit(4, Side::Buy, dec!(100), dec!(2)));
+        // A later taker fills the rest of the original maker before the
+        // newer same-price maker trades at all.
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 1);
+        assert!(engine.orderbook.get_order(1).is_none());
+        assert_eq!(
+            engine.orderbook.get_order(3).unwrap().remaining_quantity,
+            dec!(5)
+        );
+    }
+
+    #[test]
+    fn aon_maker_skipped_until_fully_consumable() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        let mut aon = limit(1, Side::Sell, dec!(100), dec!(10));
+        aon.all_or_none = true;
+        engine.place_order(aon);
+        engine.place_order(limit(2, Side::Sell, dec!(101), dec!(4)));
+
+        // Too small for the AON maker: it trades through to the worse level.
+        let (taker, trades) = engine.place_order(limit(3, Side::Buy, dec!(101), dec!(4)));
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 2);
+        assert_eq!(trades[0].price, dec!(101));
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(10)
+        );
+
+        // Big enough to consume the AON maker whole: it fills normally.
+        let (taker, trades) = engine.place_order(limit(4, Side::Buy, dec!(100), dec!(12)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 1);
+        assert_eq!(trades[0].quantity, dec!(10));
+        assert!(engine.orderbook.get_order(1).is_none());
+        assert_eq!(taker.remaining_quantity, dec!(2));
+    }
+
+    #[test]
+    fn no_lock_policy_never_rests_a_locked_quote() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_no_locked_quotes(true);
+        engine.place_order(limit(1, Side::Buy, dec!(100), dec!(3)));
+
+        // A sell at the best bid matches instead of resting locked.
+        let (taker, trades) = engine.place_order(limit(2, Side::Sell, dec!(100), dec!(3)));
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+
+        // Against an AON bid the taker cannot consume, the remainder would
+        // lock the book; the policy cancels it instead.
+        let mut aon = limit(3, Side::Buy, dec!(100), dec!(10));
+        aon.all_or_none = true;
+        engine.place_order(aon);
+        let (taker, trades) = engine.place_order(limit(4, Side::Sell, dec!(99), dec!(2)));
+        assert!(trades.is_empty());
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+        assert!(engine.orderbook.get_order(4).is_none());
+    }
+
+    #[test]
+    fn trades_record_the_aggressor_and_liquidity_roles() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+        let (_, trades) = engine.place_order(limit(2, Side::Buy, dec!(100), dec!(2)));
+        assert_eq!(trades.len(), 1);
+        let trade = &trades[0];
+        assert_eq!(trade.aggressor, Some(Side::Buy));
+        assert_eq!(trade.taker_is_buyer(), Some(true));
+        assert_eq!(trade.liquidity_of(1), Some(Liquidity::Maker));
+        assert_eq!(trade.liquidity_of(2), Some(Liquidity::Taker));
+        assert_eq!(trade.liquidity_of(99), None);
+    }
+
+    #[test]
+    fn vetoed_trades_leave_both_orders_and_the_trade_id_untouched() {
+        struct VetoAll;
+        impl PreTradeCheck for VetoAll {
+            fn approve(&mut self, _trade: &Trade) -> bool {
+                false
+            }
+        }
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_pre_trade_check(Box::new(VetoAll));
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+
+        let (taker, trades) = engine.place_order(limit(2, Side::Buy, dec!(100), dec!(2)));
+        assert!(trades.is_empty());
+        // The maker rests exactly as placed...
+        let maker = engine.orderbook.get_order(1).unwrap();
+        assert_eq!(maker.remaining_quantity, dec!(2));
+        assert_eq!(maker.status, OrderStatus::New);
+        // ...and the taker's remainder is cancelled rather than rested
+        // crossed against it.
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+        assert_eq!(taker.remaining_quantity, dec!(2));
+        assert!(engine.recent_trades.is_empty());
+
+        // The vetoed fill consumed no trade id: the next approved trade
+        // starts the tape at 1.
+        engine.pre_trade = None;
+        let (_, trades) = engine.place_order(limit(3, Side::Buy, dec!(100), dec!(2)));
+        assert_eq!(trades[0].id, 1);
+    }
+
+    #[test]
+    fn mid_pegged_order_follows_the_mid_and_fills_at_its_new_level() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_tick_size(dec!(1));
+        engine.place_order(limit(1, Side::Buy, dec!(100), dec!(1)));
+        engine.place_order(limit(2, Side::Sell, dec!(102), dec!(1)));
+
+        let mut pegged = limit(3, Side::Buy, dec!(100), dec!(1));
+        pegged.peg = Some(PegReference::Mid);
+        engine.place_order(pegged);
+        // Mid of 100/102 is 101; the peg moves there on the next book update.
+        assert_eq!(engine.orderbook.get_order(3).unwrap().price, dec!(101));
+
+        // Shift the ask to 105: the mid (without the peg's own quote) is
+        // 102.5, snapped down to 102 for a bid.
+        engine.cancel_order(2);
+        engine.place_order(limit(4, Side::Sell, dec!(105), dec!(1)));
+        assert_eq!(engine.orderbook.get_order(3).unwrap().price, dec!(102));
+        assert_eq!(engine.take_repriced().last(), Some(&(3, dec!(102))));
+
+        // A sell at the repriced level fills against the peg there.
+        let (_, trades) = engine.place_order(limit(5, Side::Sell, dec!(102), dec!(1)));
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 3);
+        assert_eq!(trades[0].price, dec!(102));
+    }
+
+    #[test]
+    fn market_order_walks_levels() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        engine.place_order(limit(2, Side::Sell, dec!(101), dec!(1)));
+        let mut taker = limit(3, Side::Buy, Decimal::ZERO, dec!(2));
+        taker.order_type = OrderType::Market;
+        let (taker, trades) = engine.place_order(taker);
+
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, dec!(100));
+        assert_eq!(trades[1].price, dec!(101));
+    }
+
+    #[test]
+    fn registered_sink_sees_exactly_the_trades_of_a_placement() {
+        struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<(u64, Side)>>>);
+        impl TradeSink for Recorder {
+            fn on_trade(&mut self, trade: &Trade, aggressor: Side) {
+                self.0.lock().unwrap().push((trade.id, aggressor));
+            }
+        }
+
+        let recorded = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.register_sink(Box::new(Recorder(recorded.clone())));
+
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        engine.place_order(limit(2, Side::Sell, dec!(101), dec!(1)));
+        let (_, trades) = engine.place_order(limit(3, Side::Buy, dec!(101), dec!(2)));
+
+        let expected: Vec<(u64, Side)> = trades.iter().map(|t| (t.id, Side::Buy)).collect();
+        assert_eq!(expected.len(), 2);
+        assert_eq!(*recorded.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn evicted_trades_spill_while_aggregates_stay_complete() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("spill").join("BTC-USD.trades");
+        let mut engine = MatchingEngine::new("BTC-USD", 2);
+        engine.set_trade_spill(Box::new(FileTradeSpill::open(&path).unwrap()));
+
+        for price in [dec!(100), dec!(101), dec!(102)] {
+            engine.place_order(limit(price.mantissa() as u64, Side::Sell, price, dec!(1)));
+            engine.place_order(limit(price.mantissa() as u64 + 10, Side::Buy, price, dec!(1)));
+        }
+
+        // The ring holds the newest two; the oldest spilled to disk.
+        let in_memory: Vec<Decimal> = engine.recent_trades.iter().map(|t| t.price).collect();
+        assert_eq!(in_memory, vec![dec!(101), dec!(102)]);
+        let spilled = FileTradeSpill::read_all(&path).unwrap();
+        assert_eq!(spilled.len(), 1);
+        assert_eq!(spilled[0].price, dec!(100));
+
+        // VWAP still covers all three trades.
+        let now = now_ns();
+        let (_, volume, notional) = engine.vwap(MAX_VWAP_WINDOW_NS, now).unwrap();
+        assert_eq!(volume, dec!(3));
+        assert_eq!(notional, dec!(303));
+    }
+
+    #[test]
+    fn seeded_rng_draws_identically_across_engines_and_restores() {
+        let mut a = MatchingEngine::new("BTC-USD", 16);
+        let mut b = MatchingEngine::new("BTC-USD", 16);
+        a.set_rng_seed(7);
+        b.set_rng_seed(7);
+        let draws: Vec<u64> = (0..4).map(|_| a.next_random()).collect();
+        assert_eq!(draws, (0..4).map(|_| b.next_random()).collect::<Vec<_>>());
+
+        // Restoring mid-stream state resumes the same sequence.
+        let mut c = MatchingEngine::new("BTC-USD", 16);
+        c.set_rng_state(b.rng_state());
+        assert_eq!(a.next_random(), c.next_random());
+
+        // A different seed diverges.
+        let mut d = MatchingEngine::new("BTC-USD", 16);
+        d.set_rng_seed(8);
+        assert_ne!(draws[0], d.next_random());
+    }
+
+    #[test]
+    fn uncross_clears_an_injected_crossed_state_at_resting_prices() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        // Inject corruption straight into the book: a bid through the ask,
+        // bypassing the matching loop.
+        let mut crossed = limit(2, Side::Buy, dec!(101), dec!(3));
+        crossed.sequence = 2;
+        engine.orderbook.add_order(crossed);
+        assert!(engine.book_is_crossed());
+
+        let trades = engine.uncross();
+        assert_eq!(trades.len(), 1);
+        // The younger bid took; the trade printed at the resting ask.
+        assert_eq!(trades[0].price, dec!(100));
+        assert_eq!(trades[0].quantity, dec!(1));
+        assert!(!engine.book_is_crossed());
+        assert_eq!(
+            engine.orderbook.get_order(2).unwrap().remaining_quantity,
+            dec!(2)
+        );
+    }
+
+    #[test]
+    fn quote_denominated_market_buy_spends_exactly_its_notional() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(10), dec!(6)));
+        engine.place_order(limit(2, Side::Sell, dec!(10), dec!(6)));
+
+        let mut taker = limit(3, Side::Buy, Decimal::ZERO, dec!(100));
+        taker.order_type = OrderType::Market;
+        taker.quantity_in_quote = true;
+        let (order, trades) = engine.place_order(taker);
+
+        // 100 quote at 10/unit: the whole first maker plus 4 of the second.
+        let bought: Decimal = trades.iter().map(|t| t.quantity).sum();
+        assert_eq!(bought, dec!(10));
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!(order.remaining_quantity, Decimal::ZERO);
+        assert_eq!(
+            engine.orderbook.get_order(2).unwrap().remaining_quantity,
+            dec!(2)
+        );
+    }
+
+    #[test]
+    fn reduced_order_keeps_time_priority() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(5)));
+        engine.place_order(limit(2, Side::Sell, dec!(100), dec!(5)));
+
+        let reduced = engine.reduce_order(1, dec!(3)).unwrap();
+        assert_eq!(reduced.remaining_quantity, dec!(2));
+
+        // Still ahead of the newer same-price order.
+        let (_, trades) = engine.place_order(limit(3, Side::Buy, dec!(100), dec!(1)));
+        assert_eq!(trades[0].maker_order_id, 1);
+
+        // Reducing the whole remainder cancels.
+        let cancelled = engine.reduce_order(1, dec!(1)).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert!(engine.orderbook.get_order(1).is_none());
+    }
+
+    #[test]
+    fn fresh_last_look_quote_declines_and_the_taker_matches_behind_it() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_last_look_window(100);
+
+        // Protected quote at the touch (timestamp 1), plain maker behind.
+        let mut protected = limit(1, Side::Sell, dec!(100), dec!(1));
+        protected.last_look = true;
+        engine.place_order(protected);
+        engine.place_order(limit(2, Side::Sell, dec!(101), dec!(1)));
+
+        // Taker arrives at timestamp 3, inside the window: the protected
+        // maker is cancelled, the taker fills against the next maker.
+        let (taker, trades) = engine.place_order(limit(3, Side::Buy, dec!(101), dec!(1)));
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 2);
+        assert!(engine.orderbook.get_order(1).is_none());
+        let declined = engine.take_last_look_cancels();
+        assert_eq!(declined.len(), 1);
+        assert_eq!(declined[0].id, 1);
+        assert_eq!(declined[0].status, OrderStatus::Cancelled);
+
+        // A quote older than the window trades normally.
+        let mut seasoned = limit(4, Side::Sell, dec!(100), dec!(1));
+        seasoned.last_look = true;
+        seasoned.timestamp = 4;
+        engine.place_order(seasoned);
+        let mut taker = limit(5, Side::Buy, dec!(100), dec!(1));
+        taker.timestamp = 500;
+        let (_, trades) = engine.place_order(taker);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, 4);
+        assert!(engine.take_last_look_cancels().is_empty());
+    }
+
+    #[test]
+    fn same_account_group_never_self_trades() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        let mut maker = limit(1, Side::Sell, dec!(100), dec!(1));
+        maker.account_group = Some("firm-a".into());
+        engine.place_order(maker);
+
+        // Different user, same firm: STP cancels the taker instead.
+        let mut taker = limit(2, Side::Buy, dec!(100), dec!(1));
+        taker.account_group = Some("firm-a".into());
+        let (taker, trades) = engine.place_order(taker);
+        assert!(trades.is_empty());
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+        assert!(engine.orderbook.get_order(1).is_some());
+
+        // A different firm trades normally.
+        let mut taker = limit(3, Side::Buy, dec!(100), dec!(1));
+        taker.account_group = Some("firm-b".into());
+        let (taker, trades) = engine.place_order(taker);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(taker.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn ungrouped_orders_fall_back_to_user_id_stp() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(1)));
+        let mut taker = limit(2, Side::Buy, dec!(100), dec!(1));
+        taker.user_id = 1;
+        let (taker, trades) = engine.place_order(taker);
+        assert!(trades.is_empty());
+        assert_eq!(taker.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn bbo_updates_fire_only_when_the_touch_moves() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.place_order(limit(1, Side::Buy, dec!(100), dec!(1)));
+        let mut rx = engine.subscribe_bbo();
+
+        // Deeper bid: the touch is unchanged, no message.
+        engine.place_order(limit(2, Side::Buy, dec!(99), dec!(1)));
+        assert!(rx.try_recv().is_err());
+
+        // New best bid: one message with the new price and level size.
+        engine.place_order(limit(3, Side::Buy, dec!(101), dec!(2)));
+        let update = rx.try_recv().unwrap();
+        assert_eq!(update.bbo.bid, Some((dec!(101), dec!(2))));
+        assert_eq!(update.bbo.ask, None);
+        assert!(rx.try_recv().is_err());
+
+        // Same-price join changes the level size, which is a BBO change.
+        engine.place_order(limit(4, Side::Buy, dec!(101), dec!(1)));
+        let update = rx.try_recv().unwrap();
+        assert_eq!(update.bbo.bid, Some((dec!(101), dec!(3))));
+    }
+
+    #[test]
+    fn maker_rebate_nets_against_taker_fee() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        // Maker rebate of 1 bps, taker pays 2 bps.
+        engine.set_fee_schedule(dec!(-1), dec!(2));
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+        engine.place_order(limit(2, Side::Buy, dec!(100), dec!(2)));
+
+        // Notional 200: maker is owed 0.02, taker pays 0.04.
+        assert_eq!(engine.fees_accrued(1), dec!(-0.02));
+        assert_eq!(engine.fees_accrued(2), dec!(0.04));
+        // The venue stays net positive.
+        assert_eq!(engine.fees_accrued(1) + engine.fees_accrued(2), dec!(0.02));
+    }
+
+    fn trade_at(engine: &mut MatchingEngine, price: Decimal, qty: Decimal, ts: i64) {
+        engine.record_trade(Trade {
+            id: ts as u64,
+            market_id: "BTC-USD".into(),
+            price,
+            quantity: qty,
+            maker_order_id: 1,
+            taker_order_id: 2,
+            maker_user_id: 1,
+            taker_user_id: 2,
+            timestamp: ts,
+            aggressor: Some(Side::Buy),
+        }, true);
+    }
+
+    #[test]
+    fn vwap_is_notional_weighted_mean_of_windowed_trades() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        // Outside the window; must be excluded.
+        trade_at(&mut engine, dec!(50), dec!(10), 1_000);
+        // Inside the window.
+        trade_at(&mut engine, dec!(100), dec!(2), 5_000);
+        trade_at(&mut engine, dec!(110), dec!(1), 6_000);
+
+        let (vwap, volume, notional) = engine.vwap(3_000, 7_000).unwrap();
+        // (100*2 + 110*1) / 3
+        assert_eq!(notional, dec!(310));
+        assert_eq!(volume, dec!(3));
+        // (100*2 + 110*1) / 3, rounded to the pricing policy's scale rather
+        // than Decimal's full 28 digits.
+        assert_eq!(vwap, dec!(103.333333333333));
+
+        // A window covering nothing yields None.
+        assert!(engine.vwap(100, 1_000_000).is_none());
+    }
+
+    #[test]
+    fn reaper_pops_exactly_the_due_orders_each_tick() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        // Hundreds of GTD orders with expiries spread over ten ticks.
+        for i in 0..300u64 {
+            let mut order = limit(i + 1, Side::Buy, dec!(1) + Decimal::from(i), dec!(1));
+            order.time_in_force = TimeInForce::Gtd;
+            order.expires_at = Some(((i % 10) + 1) as i64 * 1_000);
+            engine.place_order(order);
+        }
+        // Manually cancel some orders; their heap entries go stale.
+        for id in [3u64, 13, 23, 250] {
+            engine.cancel_order(id);
+        }
+
+        let mut total = 0usize;
+        for tick in 1..=10i64 {
+            let expired = engine.reap_expired(tick * 1_000);
+            for order in &expired {
+                assert_eq!(order.expires_at, Some(tick * 1_000));
+            }
+            total += expired.len();
+        }
+        // Every order expired except the four cancelled by hand.
+        assert_eq!(total, 296);
+        assert_eq!(engine.orderbook.order_count(), 0);
+        // The heap is fully drained once everything due has been popped.
+        assert!(engine.reap_expired(i64::MAX).is_empty());
+    }
+
+    /// Pseudo-random operation fuzz: thousands of mixed places, cancels,
+    /// amends and reduces must never panic, and the flat index must stay
+    /// consistent with the price levels and the incremental digest. The
+    /// generator is a plain LCG with a fixed seed so failures reproduce.
+    #[test]
+    fn randomized_operations_never_panic_and_keep_the_book_consistent() {
+        let mut state = 0x5eed_cafe_u64;
+        let mut rng = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            state >> 33
+        };
+
+        let mut engine = MatchingEngine::new("BTC-USD", 64);
+        for id in 1..=2_000u64 {
+            let side = if rng() % 2 == 0 { Side::Buy } else { Side::Sell };
+            let price = Decimal::from(90 + rng() % 21);
+            let qty = Decimal::from(1 + rng() % 5);
+            match rng() % 10 {
+                // Mostly placements, with the occasional market order, IOC
+                // or all-or-none thrown in.
+                0..=5 => {
+                    let mut order = limit(id, side, price, qty);
+                    match rng() % 5 {
+                        0 => order.order_type = OrderType::Market,
+                        1 => order.time_in_force = TimeInForce::Ioc,
+                        2 => order.all_or_none = true,
+                        _ => {}
+                    }
+                    engine.place_order(order);
+                }
+                6 | 7 => {
+                    engine.cancel_order(1 + rng() % id);
+                }
+                8 => {
+                    engine.amend_order(1 + rng() % id, price, qty, id);
+                }
+                _ => {
+                    engine.reduce_order(1 + rng() % id, qty);
+                }
+            }
+
+            // Index and levels agree on membership and count.
+            let book = &engine.orderbook;
+            let level_orders: usize = book
+                .bids
+                .values()
+                .chain(book.asks.values())
+                .map(|l| l.orders.len())
+                .sum();
+            assert_eq!(level_orders, book.order_count());
+        }
+
+        // The incrementally maintained digest matches a full rebuild.
+        let before = engine.orderbook.digest();
+        engine.orderbook.rebuild_digest();
+        assert_eq!(engine.orderbook.digest(), before);
+        // The book never crossed itself.
+        if let (Some(bid), Some(ask)) = (engine.orderbook.best_bid(), engine.orderbook.best_ask())
+        {
+            assert!(bid.price < ask.price);
+        }
+    }
+
+    #[test]
+    fn trades_through_the_reference_beyond_tolerance_are_flagged() {
+        struct Fixed(Decimal, Decimal);
+        impl ReferencePriceProvider for Fixed {
+            fn reference(&self, _market_id: &str) -> Option<(Decimal, Decimal)> {
+                Some((self.0, self.1))
+            }
+        }
+
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_reference_provider(Box::new(Fixed(dec!(99), dec!(100))));
+        engine.set_trade_through_tolerance(dec!(0.5));
+
+        // Buy at 100.4: within half a tick of the reference ask, clean.
+        engine.place_order(limit(1, Side::Sell, dec!(100.4), dec!(1)));
+        engine.place_order(limit(2, Side::Buy, dec!(100.4), dec!(1)));
+        assert_eq!(engine.trade_through_count(), 0);
+        assert!(engine.take_trade_through_flags().is_empty());
+
+        // Buy at 101: a whole point through the reference ask, flagged.
+        engine.place_order(limit(3, Side::Sell, dec!(101), dec!(1)));
+        let (_, trades) = engine.place_order(limit(4, Side::Buy, dec!(101), dec!(1)));
+        assert_eq!(engine.trade_through_count(), 1);
+        let flags = engine.take_trade_through_flags();
+        assert_eq!(flags, vec![(trades[0].id, dec!(1))]);
+
+        // Sell a whole point below the reference bid is likewise flagged.
+        engine.place_order(limit(5, Side::Buy, dec!(98), dec!(1)));
+        engine.place_order(limit(6, Side::Sell, dec!(98), dec!(1)));
+        assert_eq!(engine.trade_through_count(), 2);
+    }
+
+    #[test]
+    fn stale_quotes_are_evicted_while_fresh_ones_remain() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_max_order_age(1_000);
+
+        let mut old = limit(1, Side::Buy, dec!(99), dec!(1));
+        old.timestamp = 100;
+        engine.place_order(old);
+        let mut fresh = limit(2, Side::Buy, dec!(98), dec!(1));
+        fresh.timestamp = 1_500;
+        engine.place_order(fresh);
+
+        // Oldest-age metric tracks the front of the heap.
+        assert_eq!(engine.oldest_resting_age_ns(2_000), Some(1_900));
+
+        let evicted = engine.evict_stale(2_000);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id, 1);
+        assert_eq!(evicted[0].status, OrderStatus::Cancelled);
+        assert!(engine.orderbook.get_order(2).is_some());
+        assert_eq!(engine.oldest_resting_age_ns(2_000), Some(500));
+
+        // With the sweep disabled nothing is ever evicted.
+        engine.set_max_order_age(0);
+        assert!(engine.evict_stale(i64::MAX).is_empty());
+    }
+
+    #[test]
+    fn reap_expired_cancels_due_gtd_orders() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        let mut gtd = limit(1, Side::Buy, dec!(99), dec!(1));
+        gtd.time_in_force = TimeInForce::Gtd;
+        gtd.expires_at = Some(1_000);
+        engine.place_order(gtd);
+        engine.place_order(limit(2, Side::Buy, dec!(98), dec!(1)));
+
+        let expired = engine.reap_expired(2_000);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, 1);
+        assert_eq!(expired[0].status, OrderStatus::Expired);
+        assert!(engine.orderbook.get_order(2).is_some());
+    }
+
+    #[test]
+    fn crossing_a_volume_tier_lowers_the_fee_on_subsequent_trades() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        engine.set_fee_schedule(dec!(1), dec!(10));
+        engine.set_fee_tiers(vec![FeeTier {
+            min_volume: dec!(2),
+            maker_fee_bps: dec!(1),
+            taker_fee_bps: dec!(5),
+        }]);
+
+        // First fill: taker 2 has no volume yet and pays the flat 10 bps
+        // on a 200 notional, even though this fill reaches the threshold.
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+        engine.place_order(limit(2, Side::Buy, dec!(100), dec!(2)));
+        assert_eq!(engine.fees_accrued(2), dec!(0.2));
+        assert_eq!(engine.user_volume(2), dec!(2));
+
+        // Second fill: 2 units of volume put the taker in the 5 bps tier,
+        // so 100 notional costs 0.05 instead of 0.1.
+        engine.place_order(limit(3, Side::Sell, dec!(100), dec!(1)));
+        let mut taker = limit(4, Side::Buy, dec!(100), dec!(1));
+        taker.user_id = 2;
+        engine.place_order(taker);
+        assert_eq!(engine.fees_accrued(2), dec!(0.2) + dec!(0.05));
+    }
+
+    #[test]
+    fn dust_sweep_cancels_sub_lot_remainders_only() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        // No lot size yet, so the partial fill leaves a 0.5 residual resting.
+        engine.place_order(limit(1, Side::Sell, dec!(100), dec!(2)));
+        engine.place_order(limit(2, Side::Sell, dec!(101), dec!(2)));
+        engine.place_order(limit(3, Side::Buy, dec!(100), dec!(1.5)));
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(0.5)
+        );
+
+        engine.set_lot_size(dec!(1));
+        let swept = engine.sweep_dust();
+        assert_eq!(swept.len(), 1);
+        assert_eq!(swept[0].id, 1);
+        assert_eq!(swept[0].status, OrderStatus::Cancelled);
+        // The whole-lot order is untouched, and a re-sweep finds nothing.
+        assert!(engine.orderbook.get_order(2).is_some());
+        assert!(engine.sweep_dust().is_empty());
+    }
+
+    #[test]
+    fn time_to_fill_reflects_how_long_the_maker_rested() {
+        let mut engine = MatchingEngine::new("BTC-USD", 16);
+        let mut maker = limit(1, Side::Sell, dec!(100), dec!(1));
+        maker.timestamp = crate::types::now_ns();
+        engine.place_order(maker);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        engine.place_order(limit(2, Side::Buy, dec!(100), dec!(1)));
+
+        let quality = engine.match_quality();
+        let first = quality.avg_time_to_first_fill_ns().unwrap();
+        // The maker rested ~50ms before its single, full fill.
+        assert!(first >= 50_000_000, "rested {first}ns");
+        assert!(first < 5_000_000_000, "rested {first}ns");
+        assert_eq!(quality.avg_time_to_full_fill_ns(), Some(first));
+        // The maker arrived unfilled, the taker filled completely: half the
+        // incoming quantity traded on arrival.
+        assert_eq!(quality.fill_ratio(), Some(dec!(0.5)));
+    }
+}
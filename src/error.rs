@@ -0,0 +1,220 @@
+//! Engine-level error type and its single mapping onto gRPC statuses.
+//!
+//! The order path (place, cancel, amend, reap) returns [`EngineError`] so
+//! callers can distinguish validation failures, the WAL-failure halt and
+//! journaling errors without inspecting `io::ErrorKind`s. The storage/boot
+//! paths (`Exchange::new`, recovery, snapshots) stay on `io::Result` and are
+//! converted at the service boundary via `From<io::Error>`.
+
+use std::fmt;
+use std::io;
+use tonic::Status;
+
+/// Machine-readable code for a rejected request, carried alongside the
+/// human-readable message so clients can branch without parsing strings.
+/// On the wire it travels as the `x-reject-reason` response metadata entry,
+/// holding [`RejectReason::as_str`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Market id is empty, too long, or contains disallowed characters.
+    InvalidMarketId,
+    /// The market is not in the configured allowlist (strict mode only).
+    UnknownMarket,
+    /// Quantity is zero, negative, or otherwise unusable.
+    InvalidQuantity,
+    /// Limit price is zero or negative.
+    InvalidPrice,
+    /// Price is not a multiple of the market's tick size.
+    TickSize,
+    /// Quantity is not a multiple of the market's lot size.
+    LotSize,
+    /// `price * quantity` is below the market's minimum notional.
+    MinNotional,
+    /// The order would add a price level beyond `max_price_levels`.
+    LevelCap,
+    /// The order would join a price level already holding
+    /// `max_orders_per_level` resting orders.
+    LevelOrderCap,
+    /// The limit price deviates from the reference mid by more than the
+    /// market's collar.
+    PriceCollar,
+    /// The cancel arrived before the market's minimum resting time
+    /// elapsed.
+    MinRestingTime,
+    /// `quantity_in_quote` was set on something other than a market order.
+    QuoteQuantityRequiresMarket,
+    /// The order would push the user's open resting notional past the
+    /// market's risk cap.
+    NotionalCap,
+    /// The user already has a live order under this client order id.
+    DuplicateClientOrderId,
+    /// The book advanced past the sequence the client asserted acting on.
+    StaleSequence,
+    /// The requester does not own the order it tried to mutate.
+    NotOwner,
+    /// Order entry is halted by the WAL failure circuit.
+    MarketHalted,
+    /// The engine is at its concurrent-request limit.
+    RateLimited,
+    /// A rejection without a more specific code (e.g. converted from a
+    /// generic `io::Error`).
+    Other,
+}
+
+impl RejectReason {
+    /// Stable wire identifier; clients branch on this, so existing values
+    /// must never change meaning.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RejectReason::InvalidMarketId => "INVALID_MARKET_ID",
+            RejectReason::UnknownMarket => "UNKNOWN_MARKET",
+            RejectReason::InvalidQuantity => "INVALID_QUANTITY",
+            RejectReason::InvalidPrice => "INVALID_PRICE",
+            RejectReason::TickSize => "TICK_SIZE",
+            RejectReason::LotSize => "LOT_SIZE",
+            RejectReason::MinNotional => "MIN_NOTIONAL",
+            RejectReason::LevelCap => "LEVEL_CAP",
+            RejectReason::LevelOrderCap => "LEVEL_ORDER_CAP",
+            RejectReason::PriceCollar => "PRICE_COLLAR",
+            RejectReason::MinRestingTime => "MIN_RESTING_TIME",
+            RejectReason::QuoteQuantityRequiresMarket => "QUOTE_QUANTITY_REQUIRES_MARKET",
+            RejectReason::NotionalCap => "NOTIONAL_CAP",
+            RejectReason::DuplicateClientOrderId => "DUPLICATE_CLIENT_ORDER_ID",
+            RejectReason::StaleSequence => "STALE_SEQUENCE",
+            RejectReason::NotOwner => "NOT_OWNER",
+            RejectReason::MarketHalted => "MARKET_HALTED",
+            RejectReason::RateLimited => "RATE_LIMITED",
+            RejectReason::Other => "OTHER",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// The order failed validation (tick/lot/notional, bad price, ...).
+    InvalidOrder(RejectReason, String),
+    /// A market config update was rejected (e.g. fee floor violation).
+    Config(String),
+    /// The market is not in the configured allowlist and auto-create is
+    /// disabled.
+    UnknownMarket(String),
+    /// An optimistic-concurrency assertion failed: the book advanced past
+    /// the client's expected sequence.
+    SequenceConflict(String),
+    /// The requester does not own the order it tried to mutate.
+    PermissionDenied(String),
+    /// A cancel arrived before the market's minimum resting time elapsed.
+    TooEarlyToCancel(String),
+    /// Order entry is halted by the WAL failure circuit.
+    Halted,
+    /// The WAL append failed; in-memory state is unchanged.
+    Wal(io::Error),
+    /// Snapshot or other storage failure.
+    Storage(io::Error),
+}
+
+impl EngineError {
+    /// The machine-readable code for this error, when it is a rejection the
+    /// client can act on. WAL and storage failures carry none: they are
+    /// server faults, not something a resubmitted order can fix.
+    pub fn reject_reason(&self) -> Option<RejectReason> {
+        match self {
+            EngineError::InvalidOrder(reason, _) => Some(*reason),
+            EngineError::UnknownMarket(_) => Some(RejectReason::UnknownMarket),
+            EngineError::SequenceConflict(_) => Some(RejectReason::StaleSequence),
+            EngineError::PermissionDenied(_) => Some(RejectReason::NotOwner),
+            EngineError::TooEarlyToCancel(_) => Some(RejectReason::MinRestingTime),
+            EngineError::Halted => Some(RejectReason::MarketHalted),
+            EngineError::Config(_) | EngineError::Wal(_) | EngineError::Storage(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::InvalidOrder(_, msg) => write!(f, "invalid order: {msg}"),
+            EngineError::Config(msg) => write!(f, "invalid config: {msg}"),
+            EngineError::UnknownMarket(msg) => write!(f, "unknown market: {msg}"),
+            EngineError::SequenceConflict(msg) => write!(f, "sequence conflict: {msg}"),
+            EngineError::PermissionDenied(msg) => write!(f, "permission denied: {msg}"),
+            EngineError::TooEarlyToCancel(msg) => write!(f, "cancel too early: {msg}"),
+            EngineError::Halted => write!(f, "order entry halted: WAL writes are failing"),
+            EngineError::Wal(e) => write!(f, "wal append failed: {e}"),
+            EngineError::Storage(e) => write!(f, "storage failure: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Wal(e) | EngineError::Storage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidInput => {
+                EngineError::InvalidOrder(RejectReason::Other, e.to_string())
+            }
+            io::ErrorKind::ResourceBusy => EngineError::Halted,
+            _ => EngineError::Storage(e),
+        }
+    }
+}
+
+/// The one place engine errors become gRPC statuses. Rejections additionally
+/// carry their [`RejectReason`] code in `x-reject-reason` metadata.
+impl From<EngineError> for Status {
+    fn from(e: EngineError) -> Self {
+        let reason = e.reject_reason();
+        let mut status = match &e {
+            EngineError::InvalidOrder(..) | EngineError::Config(_) => {
+                Status::invalid_argument(e.to_string())
+            }
+            EngineError::UnknownMarket(_) => Status::not_found(e.to_string()),
+            EngineError::SequenceConflict(_) => Status::aborted(e.to_string()),
+            EngineError::PermissionDenied(_) => Status::permission_denied(e.to_string()),
+            EngineError::TooEarlyToCancel(_) | EngineError::Halted => {
+                Status::failed_precondition(e.to_string())
+            }
+            EngineError::Wal(_) | EngineError::Storage(_) => Status::internal(e.to_string()),
+        };
+        if let Some(reason) = reason {
+            status
+                .metadata_mut()
+                .insert("x-reject-reason", reason.as_str().parse().unwrap());
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejections_carry_their_code_in_status_metadata() {
+        let err = EngineError::InvalidOrder(RejectReason::TickSize, "off tick".into());
+        let status = Status::from(err);
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.metadata().get("x-reject-reason").unwrap(),
+            "TICK_SIZE"
+        );
+
+        // Ownership and halt rejections are coded too...
+        let status = Status::from(EngineError::Halted);
+        assert_eq!(
+            status.metadata().get("x-reject-reason").unwrap(),
+            "MARKET_HALTED"
+        );
+        // ...but server faults are not: resubmitting cannot fix them.
+        let status = Status::from(EngineError::Storage(io::Error::other("disk gone")));
+        assert!(status.metadata().get("x-reject-reason").is_none());
+    }
+}
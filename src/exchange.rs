@@ -0,0 +1,3111 @@
+//! Exchange coordination layer.
+//!
+//! The [`Exchange`] owns one [`MatchingEngine`] per market plus the shared
+//! WAL and snapshot manager. It assigns order ids and engine sequences,
+//! journals accepted commands, and routes them to the right engine. The gRPC
+//! service holds it behind a mutex and calls into it synchronously.
+
+use crate::audit::{AuditAction, AuditRecord, AuditSink, FileAuditSink, StdoutAuditSink};
+use crate::config::{load_market_configs, EngineConfig, MarketConfig};
+use crate::error::{EngineError, RejectReason};
+use crate::engine::{CrossedBookPolicy, FileTradeSpill, MarketPhase, MatchingEngine, RecoveryReplay, TradeSpill};
+use crate::pricing::PricingPolicy;
+use crate::snapshot::{Snapshot, SnapshotManager};
+use crate::types::{
+    now_ns, MonotonicClock, Order, OrderId, OrderStatus, OrderType, PegReference, Side,
+    TimeInForce, Trade, UserId,
+};
+use crate::wal::{WalOperation, WAL};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Longest accepted market id, in bytes; see [`Exchange::valid_market_id`].
+pub const MAX_MARKET_ID_LEN: usize = 64;
+
+/// How an order placement is acknowledged relative to WAL durability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckMode {
+    /// Respond only after the WAL entry is synced (the default).
+    #[default]
+    Durable,
+    /// Respond as soon as matching completes; the WAL write is buffered in
+    /// order and synced shortly after (the reaper tick, the next durable
+    /// append, or shutdown). A crash inside that window loses the order,
+    /// which fast-ack callers accept for latency.
+    Fast,
+}
+
+/// Histogram bucket upper bounds, in basis points, for
+/// [`CollarStats::deviation_buckets`]; the final bucket is unbounded.
+pub const COLLAR_BUCKET_BPS: [u32; 5] = [50, 100, 250, 500, 1000];
+
+/// Telemetry for one market's price-collar rejections: how often clients
+/// hit the collar and by how much, for tuning band widths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CollarStats {
+    /// Orders rejected by the collar since startup.
+    pub rejections: u64,
+    /// Rejections bucketed by deviation from the reference mid; bounds are
+    /// [`COLLAR_BUCKET_BPS`], with one extra unbounded bucket at the end.
+    pub deviation_buckets: [u64; COLLAR_BUCKET_BPS.len() + 1],
+}
+
+/// Stable reject code for the audit log; server faults without a client
+/// code audit as `OTHER`.
+fn reject_code(error: &EngineError) -> String {
+    error
+        .reject_reason()
+        .unwrap_or(RejectReason::Other)
+        .as_str()
+        .to_string()
+}
+
+/// Parameters for a new order, before the exchange assigns ids.
+#[derive(Debug, Clone)]
+pub struct NewOrder {
+    pub market_id: String,
+    pub user_id: UserId,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub time_in_force: TimeInForce,
+    /// Resting all-or-none flag (see [`crate::types::Order::all_or_none`]).
+    pub all_or_none: bool,
+    /// Self-trade prevention group (see [`crate::types::Order::account_group`]).
+    pub account_group: Option<String>,
+    /// Trade-tape visibility (see [`crate::types::Order::public`]).
+    pub public: bool,
+    /// Quote-notional market order (see
+    /// [`crate::types::Order::quantity_in_quote`]).
+    pub quantity_in_quote: bool,
+    /// Last-look protected quote (see [`crate::types::Order::last_look`]).
+    pub last_look: bool,
+    pub expires_at: Option<i64>,
+    pub client_order_id: Option<String>,
+    pub session_id: Option<String>,
+    /// Sync vs async WAL acknowledgement (see [`AckMode`]).
+    pub ack_mode: AckMode,
+    /// Compare-and-swap order entry: the book stream sequence the client
+    /// acted on; the placement is rejected if the book has advanced past it.
+    pub expected_sequence: Option<u64>,
+    /// Pegged limit reference (see [`crate::types::Order::peg`]).
+    pub peg: Option<PegReference>,
+    /// Peg offset in basis points (see
+    /// [`crate::types::Order::peg_offset_bps`]).
+    pub peg_offset_bps: Decimal,
+}
+
+/// Parameters for a market maker's two-sided quote: a bid and an ask
+/// placed — or replacing the user's previous quote — together under one
+/// lock acquisition.
+#[derive(Debug, Clone)]
+pub struct NewQuote {
+    pub market_id: String,
+    pub user_id: UserId,
+    pub bid_price: Decimal,
+    pub bid_quantity: Decimal,
+    pub ask_price: Decimal,
+    pub ask_quantity: Decimal,
+}
+
+/// Both sides of a placed quote, each with the fills it took on the way in.
+pub type PlacedQuote = ((Order, Vec<Trade>), (Order, Vec<Trade>));
+
+/// Reserved client order ids carried by the two sides of a quote.
+/// Replacement resolves the previous quote through the per-user client-id
+/// index, so it needs no extra state and survives recovery.
+pub const QUOTE_BID_CLIENT_ID: &str = "quote.bid";
+pub const QUOTE_ASK_CLIENT_ID: &str = "quote.ask";
+
+pub struct Exchange {
+    pub config: EngineConfig,
+    engines: HashMap<String, MatchingEngine>,
+    wal: WAL,
+    snapshots: SnapshotManager,
+    next_order_id: OrderId,
+    next_order_sequence: u64,
+    /// Resting orders per cancel-on-disconnect session.
+    sessions: HashMap<String, Vec<(String, OrderId)>>,
+    /// Per-market parameters from the markets file; unlisted markets use
+    /// `MarketConfig::default()`.
+    markets: HashMap<String, MarketConfig>,
+    /// Consecutive WAL append failures; any success resets it.
+    wal_failures: u32,
+    /// Set once `wal_failures` reaches the configured threshold. While
+    /// halted, order entry is rejected so the book cannot diverge from a
+    /// log that is no longer accepting writes; cancels are still allowed
+    /// (and a successful cancel write clears the halt).
+    halted: bool,
+    /// WAL bytes-appended reading at the last checkpoint, for the byte-based
+    /// checkpoint trigger (see [`Exchange::checkpoint_due_by_bytes`]).
+    checkpoint_bytes_mark: u64,
+    /// Issues priority timestamps; never goes backward even if the wall
+    /// clock does, so time priority survives NTP steps.
+    clock: MonotonicClock,
+    /// Per-market collar-rejection telemetry; see [`CollarStats`].
+    collar_stats: HashMap<String, CollarStats>,
+    /// Strict-mode mirror of the WAL's per-market sequence counters; see
+    /// [`Exchange::enforce_sequence_guard`]. Empty unless
+    /// `strict_sequence_checks` is on.
+    expected_wal_sequences: HashMap<String, i64>,
+    /// Compliance audit log, written in parallel with the WAL; `None`
+    /// disables auditing.
+    audit: Option<Box<dyn AuditSink>>,
+    /// Test-only slowdown: sleeps this long per replayed WAL entry so tests
+    /// can exercise the recovery timeout deterministically.
+    #[cfg(test)]
+    pub(crate) recovery_replay_delay: Option<std::time::Duration>,
+}
+
+impl Exchange {
+    pub fn new(config: EngineConfig) -> io::Result<Self> {
+        let mut wal = WAL::open(config.wal_dir(), config.wal_segment_max_bytes)?;
+        wal.set_retention_ns(config.wal_retention_secs as i64 * 1_000_000_000);
+        let snapshots = SnapshotManager::with_format(config.snapshot_dir(), config.snapshot_format)?
+            .with_save_retries(config.snapshot_save_retries);
+        let markets = load_market_configs(&config.markets_file)?;
+        for (market_id, market) in &markets {
+            if market.net_fee_bps() < config.min_net_fee_bps {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{market_id}: net fee {} bps below floor {} bps",
+                        market.net_fee_bps(),
+                        config.min_net_fee_bps
+                    ),
+                ));
+            }
+            for tier in &market.fee_tiers {
+                if tier.maker_fee_bps + tier.taker_fee_bps < config.min_net_fee_bps {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{market_id}: tier at volume {} nets {} bps, below floor {} bps",
+                            tier.min_volume,
+                            tier.maker_fee_bps + tier.taker_fee_bps,
+                            config.min_net_fee_bps
+                        ),
+                    ));
+                }
+            }
+        }
+        let audit: Option<Box<dyn AuditSink>> = match config.audit_log.as_str() {
+            "" => None,
+            "stdout" => Some(Box::new(StdoutAuditSink)),
+            path => match FileAuditSink::open(path) {
+                Ok(sink) => Some(Box::new(sink)),
+                Err(e) => {
+                    tracing::warn!(error = %e, path, "audit log disabled");
+                    None
+                }
+            },
+        };
+        Ok(Exchange {
+            config,
+            engines: HashMap::new(),
+            wal,
+            snapshots,
+            next_order_id: 1,
+            next_order_sequence: 1,
+            sessions: HashMap::new(),
+            markets,
+            wal_failures: 0,
+            halted: false,
+            checkpoint_bytes_mark: 0,
+            clock: MonotonicClock::default(),
+            collar_stats: HashMap::new(),
+            expected_wal_sequences: HashMap::new(),
+            audit,
+            #[cfg(test)]
+            recovery_replay_delay: None,
+        })
+    }
+
+    /// Parameters for a market, falling back to defaults when unconfigured.
+    pub fn market_config(&self, market_id: &str) -> MarketConfig {
+        self.markets.get(market_id).cloned().unwrap_or_default()
+    }
+
+    /// Whether a market id is safe to use as an engine key and embed in
+    /// snapshot filenames and object-store keys: ASCII alphanumerics, `-`
+    /// and `.`, at most [`MAX_MARKET_ID_LEN`] bytes. Underscores are
+    /// excluded because snapshot filenames use them as field separators,
+    /// and path separators because the id becomes part of a path.
+    pub fn valid_market_id(market_id: &str) -> bool {
+        !market_id.is_empty()
+            && market_id.len() <= MAX_MARKET_ID_LEN
+            && market_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    }
+
+    /// Replaces the per-market config map, e.g. after a SIGHUP reload.
+    /// Rejects the whole map if any market's fee schedule would take the
+    /// venue below the configured net-fee floor.
+    pub fn set_market_configs(
+        &mut self,
+        markets: HashMap<String, MarketConfig>,
+    ) -> Result<(), EngineError> {
+        for (market_id, market) in &markets {
+            if !Self::valid_market_id(market_id) {
+                return Err(EngineError::Config(format!(
+                    "invalid market id {market_id:?}"
+                )));
+            }
+            if market.net_fee_bps() < self.config.min_net_fee_bps {
+                return Err(EngineError::Config(format!(
+                    "{market_id}: net fee {} bps below floor {} bps",
+                    market.net_fee_bps(),
+                    self.config.min_net_fee_bps
+                )));
+            }
+            for tier in &market.fee_tiers {
+                if tier.maker_fee_bps + tier.taker_fee_bps < self.config.min_net_fee_bps {
+                    return Err(EngineError::Config(format!(
+                        "{market_id}: tier at volume {} nets {} bps, below floor {} bps",
+                        tier.min_volume,
+                        tier.maker_fee_bps + tier.taker_fee_bps,
+                        self.config.min_net_fee_bps
+                    )));
+                }
+            }
+        }
+        self.markets = markets;
+        for (market_id, engine) in &mut self.engines {
+            let market = self.markets.get(market_id).cloned().unwrap_or_default();
+            engine.set_lot_size(market.lot_size);
+            engine.set_tick_size(market.tick_size);
+            engine.set_fee_schedule(market.maker_fee_bps, market.taker_fee_bps);
+            engine.set_fee_tiers(market.fee_tiers.clone());
+            engine.set_no_locked_quotes(market.no_locked_quotes);
+            engine.set_last_look_window(market.last_look_window_ns);
+            engine.set_max_order_age(market.max_order_age_ns);
+            engine.set_trade_through_tolerance(market.trade_through_tolerance);
+            engine.set_trade_report_delay(market.trade_report_delay_ns);
+        }
+        Ok(())
+    }
+
+    fn validate_against_market_config(&self, new_order: &NewOrder) -> Result<(), EngineError> {
+        let market = self.market_config(&new_order.market_id);
+        let reject = |reason: RejectReason, msg: String| Err(EngineError::InvalidOrder(reason, msg));
+        // Basic sanity first, so pathological orders fail typed instead of
+        // reaching the matching loop. The market id gate also keeps hostile
+        // ids out of engine keys and snapshot filenames.
+        if !Self::valid_market_id(&new_order.market_id) {
+            return reject(
+                RejectReason::InvalidMarketId,
+                format!("invalid market id {:?}", new_order.market_id),
+            );
+        }
+        // In strict mode the markets file is an allowlist; markets that
+        // already have an engine (recovered history) stay addressable.
+        if !self.config.allow_unlisted_markets
+            && !self.markets.contains_key(&new_order.market_id)
+            && !self.engines.contains_key(&new_order.market_id)
+        {
+            return Err(EngineError::UnknownMarket(format!(
+                "market {:?} is not configured",
+                new_order.market_id
+            )));
+        }
+        if new_order.quantity <= Decimal::ZERO {
+            return reject(
+                RejectReason::InvalidQuantity,
+                format!("quantity {} must be positive", new_order.quantity),
+            );
+        }
+        if new_order.order_type == OrderType::Limit && new_order.price <= Decimal::ZERO {
+            return reject(
+                RejectReason::InvalidPrice,
+                format!("limit price {} must be positive", new_order.price),
+            );
+        }
+        if new_order.quantity_in_quote && new_order.order_type != OrderType::Market {
+            return reject(
+                RejectReason::QuoteQuantityRequiresMarket,
+                "quote-denominated quantity requires a market order".to_string(),
+            );
+        }
+        if new_order.order_type == OrderType::Limit {
+            if market.tick_size > Decimal::ZERO
+                && new_order.price % market.tick_size != Decimal::ZERO
+            {
+                return reject(
+                    RejectReason::TickSize,
+                    format!(
+                        "price {} is not a multiple of tick size {}",
+                        new_order.price, market.tick_size
+                    ),
+                );
+            }
+            if market.min_notional > Decimal::ZERO
+                && new_order.price * new_order.quantity < market.min_notional
+            {
+                return reject(
+                    RejectReason::MinNotional,
+                    format!(
+                        "notional {} below minimum {}",
+                        new_order.price * new_order.quantity,
+                        market.min_notional
+                    ),
+                );
+            }
+        }
+        // Lot size constrains base quantities; a quote notional is exempt.
+        if !new_order.quantity_in_quote
+            && market.lot_size > Decimal::ZERO
+            && new_order.quantity % market.lot_size != Decimal::ZERO
+        {
+            return reject(
+                RejectReason::LotSize,
+                format!(
+                    "quantity {} is not a multiple of lot size {}",
+                    new_order.quantity, market.lot_size
+                ),
+            );
+        }
+        // Pre-trade risk: the cap is checked against the order's full
+        // notional (the worst case if nothing matches) plus what the user
+        // already has resting.
+        if new_order.order_type == OrderType::Limit && market.max_user_notional > Decimal::ZERO {
+            let open = self
+                .engines
+                .get(&new_order.market_id)
+                .map(|e| e.orderbook.user_open_notional(new_order.user_id))
+                .unwrap_or_default();
+            let notional = new_order.price * new_order.quantity;
+            if open + notional > market.max_user_notional {
+                return reject(
+                    RejectReason::NotionalCap,
+                    format!(
+                        "open notional {} plus order notional {} exceeds cap {}",
+                        open, notional, market.max_user_notional
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the configured eviction spill for a market, or `None` when
+    /// spilling is disabled or the file cannot be opened (history is then
+    /// dropped at the cap, as without the flag).
+    fn trade_spill_for(&self, market_id: &str) -> Option<Box<dyn TradeSpill>> {
+        if !self.config.spill_evicted_trades {
+            return None;
+        }
+        let path = self
+            .config
+            .data_dir
+            .join("spill")
+            .join(format!("{market_id}.trades"));
+        match FileTradeSpill::open(&path) {
+            Ok(spill) => Some(Box::new(spill)),
+            Err(e) => {
+                tracing::warn!(error = %e, path = %path.display(), "trade spill disabled");
+                None
+            }
+        }
+    }
+
+    /// Creates engines for every configured market that does not already
+    /// have one, so first requests hit a warm cache. Recovery of markets
+    /// with history is eager in [`Exchange::recover`]; this covers
+    /// configured markets that have not traded yet. Returns how many
+    /// engines were created.
+    pub fn prewarm(&mut self) -> usize {
+        let missing: Vec<String> = self
+            .markets
+            .keys()
+            .filter(|market_id| !self.engines.contains_key(*market_id))
+            .cloned()
+            .collect();
+        let count = missing.len();
+        for market_id in missing {
+            self.get_or_create_engine(&market_id);
+        }
+        count
+    }
+
+    pub fn get_or_create_engine(&mut self, market_id: &str) -> &mut MatchingEngine {
+        let capacity = self.config.recent_trades_capacity;
+        let level_ordering = self.config.level_ordering;
+        let pricing = PricingPolicy::with_scale(self.config.pricing_scale);
+        let market = self.markets.get(market_id).cloned().unwrap_or_default();
+        let spill = self.trade_spill_for(market_id);
+        let seed = self.config.matching_seed;
+        self.engines.entry(market_id.to_string()).or_insert_with(|| {
+            let mut engine = MatchingEngine::new(market_id, capacity);
+            engine.orderbook.level_ordering = level_ordering;
+            engine.set_pricing_policy(pricing);
+            engine.set_rng_seed(seed);
+            if let Some(spill) = spill {
+                engine.set_trade_spill(spill);
+            }
+            engine.set_lot_size(market.lot_size);
+            engine.set_tick_size(market.tick_size);
+            engine.set_fee_schedule(market.maker_fee_bps, market.taker_fee_bps);
+            engine.set_fee_tiers(market.fee_tiers.clone());
+            engine.set_no_locked_quotes(market.no_locked_quotes);
+            engine.set_last_look_window(market.last_look_window_ns);
+            engine.set_max_order_age(market.max_order_age_ns);
+            engine.set_trade_through_tolerance(market.trade_through_tolerance);
+            engine.set_trade_report_delay(market.trade_report_delay_ns);
+            engine
+        })
+    }
+
+    pub fn engine(&self, market_id: &str) -> Option<&MatchingEngine> {
+        self.engines.get(market_id)
+    }
+
+    pub fn engine_mut(&mut self, market_id: &str) -> Option<&mut MatchingEngine> {
+        self.engines.get_mut(market_id)
+    }
+
+    pub fn market_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.engines.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// WAL entries from `sequence` onward, for replication streams tailing
+    /// the log.
+    pub fn wal_entries_from(&self, sequence: i64) -> std::io::Result<Vec<crate::wal::WalEntry>> {
+        self.wal.read_from(sequence)
+    }
+
+    /// Installs replayed engines wholesale: the promotion path for a warm
+    /// standby taking over as primary. Order-id assignment resumes above
+    /// anything resting in the adopted books.
+    pub fn adopt_engines(&mut self, engines: HashMap<String, MatchingEngine>) {
+        self.engines = engines;
+        let max = self
+            .engines
+            .values()
+            .flat_map(|e| e.orderbook.orders.values())
+            .map(|o| (o.id, o.sequence))
+            .fold((0, 0), |acc, x| (acc.0.max(x.0), acc.1.max(x.1)));
+        self.next_order_id = self.next_order_id.max(max.0 + 1);
+        self.next_order_sequence = self.next_order_sequence.max(max.1 + 1);
+    }
+
+    /// Next WAL sequence to be assigned.
+    pub fn next_sequence(&self) -> i64 {
+        self.wal.next_sequence()
+    }
+
+    /// WAL segment count and total bytes, for health reporting.
+    pub fn wal_stats(&self) -> io::Result<(u64, u64)> {
+        self.wal.segment_stats()
+    }
+
+    /// Syncs any fast-acked appends still buffered; called periodically and
+    /// on shutdown so async acknowledgements become durable promptly.
+    pub fn flush_wal(&mut self) -> io::Result<()> {
+        self.wal.flush()
+    }
+
+    /// Sequence of the newest on-disk snapshot for a market, if any.
+    pub fn latest_snapshot_sequence(&self, market_id: &str) -> io::Result<Option<i64>> {
+        self.snapshots.latest_sequence(market_id)
+    }
+
+    /// Whether order entry is halted by the WAL failure circuit.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Single choke point for WAL writes: counts consecutive failures,
+    /// trips the halt at the configured threshold and clears it on the
+    /// first success.
+    fn journal(&mut self, operation: WalOperation) -> io::Result<i64> {
+        let guard_market = self
+            .config
+            .strict_sequence_checks
+            .then(|| operation.market_id().to_string());
+        match self.wal.append(operation) {
+            Ok(sequence) => {
+                self.wal_failures = 0;
+                self.halted = false;
+                if let Some(market_id) = guard_market {
+                    self.enforce_sequence_guard(&market_id, 1);
+                }
+                Ok(sequence)
+            }
+            Err(e) => {
+                self.wal_failures += 1;
+                let threshold = self.config.wal_failure_halt_threshold;
+                if threshold > 0 && self.wal_failures >= threshold {
+                    self.halted = true;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Group-committed variant of [`Exchange::journal`]: the whole batch is
+    /// appended under a single sync (or none, for fast-ack requests). Same
+    /// failure-circuit accounting.
+    fn journal_batch(
+        &mut self,
+        operations: Vec<WalOperation>,
+        ack_mode: AckMode,
+    ) -> io::Result<Vec<i64>> {
+        if operations.is_empty() {
+            return Ok(Vec::new());
+        }
+        let guard_counts = self.config.strict_sequence_checks.then(|| {
+            let mut counts: HashMap<String, i64> = HashMap::new();
+            for operation in &operations {
+                *counts.entry(operation.market_id().to_string()).or_default() += 1;
+            }
+            counts
+        });
+        let result = match ack_mode {
+            AckMode::Durable => self.wal.append_batch(operations),
+            AckMode::Fast => self.wal.append_batch_async(operations),
+        };
+        match result {
+            Ok(sequences) => {
+                self.wal_failures = 0;
+                self.halted = false;
+                if let Some(counts) = guard_counts {
+                    for (market_id, appended) in counts {
+                        self.enforce_sequence_guard(&market_id, appended);
+                    }
+                }
+                Ok(sequences)
+            }
+            Err(e) => {
+                self.wal_failures += 1;
+                let threshold = self.config.wal_failure_halt_threshold;
+                if threshold > 0 && self.wal_failures >= threshold {
+                    self.halted = true;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Journals the records a matching pass produced *after* the engine
+    /// mutated. A failure here is torn state, not a clean reject: the book
+    /// already holds the fills and their prints are broadcast, but the WAL
+    /// carries only the command — a literal replay would resurrect the
+    /// orders unfilled and silently drop trades counterparties already saw.
+    /// So unlike the pre-mutation command append, this halts order entry
+    /// immediately instead of letting the failure circuit's threshold admit
+    /// more flow onto the diverged book; recovery (or an operator-forced
+    /// snapshot) re-anchors state before the halt lifts.
+    fn journal_post_mutation_batch(
+        &mut self,
+        operations: Vec<WalOperation>,
+        ack_mode: AckMode,
+    ) -> Result<(), EngineError> {
+        if let Err(e) = self.journal_batch(operations, ack_mode) {
+            tracing::error!(
+                error = %e,
+                "matching records failed to journal after the book mutated; halting order entry"
+            );
+            self.halted = true;
+            return Err(EngineError::Wal(e));
+        }
+        Ok(())
+    }
+
+    /// Strict-mode invariant, enabled by `strict_sequence_checks`: after a
+    /// successful WAL write, this exchange's mirror of the market's journal
+    /// position, advanced by the operations just appended, must equal the
+    /// WAL's own per-market counter. Disagreement means operations were
+    /// silently dropped or double-counted — a persistence bug — so order
+    /// entry is halted the same way the WAL failure circuit halts it,
+    /// rather than letting the book and the log drift further apart. The
+    /// first write seen for a market adopts the WAL's counter as the
+    /// baseline.
+    fn enforce_sequence_guard(&mut self, market_id: &str, appended: i64) {
+        let actual = self.wal.market_sequence(market_id);
+        let expected = match self.expected_wal_sequences.get(market_id) {
+            Some(previous) => previous + appended,
+            None => actual,
+        };
+        self.expected_wal_sequences.insert(market_id.to_string(), actual);
+        if expected != actual {
+            tracing::error!(
+                market_id,
+                expected,
+                actual,
+                "WAL and engine sequence counters diverged; halting order entry"
+            );
+            self.halted = true;
+        }
+    }
+
+    fn next_ids(&mut self) -> (OrderId, u64) {
+        let ids = (self.next_order_id, self.next_order_sequence);
+        self.next_order_id += 1;
+        self.next_order_sequence += 1;
+        ids
+    }
+
+    /// Accepts a new order: matches it against the book, then journals the
+    /// command and resulting trades. Every decision — acceptance with its
+    /// fills, or rejection with its reason — also lands on the audit log
+    /// when one is configured.
+    pub fn place_order(
+        &mut self,
+        new_order: NewOrder,
+    ) -> Result<(Order, Vec<Trade>), EngineError> {
+        let (market_id, actor) = (new_order.market_id.clone(), new_order.user_id);
+        let result = self.place_order_inner(new_order);
+        if self.audit.is_some() {
+            match &result {
+                Ok((order, trades)) => {
+                    let actions: Vec<AuditAction> =
+                        std::iter::once(AuditAction::OrderAccepted { order_id: order.id })
+                            .chain(trades.iter().map(|t| AuditAction::OrderMatched {
+                                taker_order_id: t.taker_order_id,
+                                maker_order_id: t.maker_order_id,
+                                trade_id: t.id,
+                                price: t.price,
+                                quantity: t.quantity,
+                            }))
+                            .collect();
+                    for action in actions {
+                        self.audit_record(&market_id, actor, action);
+                    }
+                }
+                Err(e) => {
+                    let action = AuditAction::OrderRejected {
+                        reason: reject_code(e),
+                        detail: e.to_string(),
+                    };
+                    self.audit_record(&market_id, actor, action);
+                }
+            }
+        }
+        result
+    }
+
+    fn place_order_inner(
+        &mut self,
+        new_order: NewOrder,
+    ) -> Result<(Order, Vec<Trade>), EngineError> {
+        if self.halted {
+            return Err(EngineError::Halted);
+        }
+        self.validate_against_market_config(&new_order)?;
+        self.validate_level_capacity(&new_order)?;
+        self.validate_collar(&new_order)?;
+        // Client ids are scoped per user: a reuse while the original order
+        // still rests is a client-side bug, not a new order.
+        if let Some(client_id) = &new_order.client_order_id {
+            let duplicate = self
+                .engines
+                .get(&new_order.market_id)
+                .and_then(|e| e.orderbook.find_client_order(new_order.user_id, client_id))
+                .is_some();
+            if duplicate {
+                return Err(EngineError::InvalidOrder(
+                    RejectReason::DuplicateClientOrderId,
+                    format!("client order id {client_id} is already live"),
+                ));
+            }
+        }
+        // Optimistic concurrency: a client that read the book at stream
+        // sequence N can assert the book is still at N; a conflict means it
+        // is acting on stale state and should re-read rather than trade.
+        if let Some(expected) = new_order.expected_sequence {
+            let current = self
+                .engines
+                .get(&new_order.market_id)
+                .map(|e| e.book_stream_sequence())
+                .unwrap_or(0);
+            if current > expected {
+                return Err(EngineError::SequenceConflict(format!(
+                    "book at stream sequence {current}, client expected {expected}"
+                )));
+            }
+        }
+        let (id, sequence) = self.next_ids();
+        let order = Order {
+            id,
+            user_id: new_order.user_id,
+            market_id: new_order.market_id.clone(),
+            side: new_order.side,
+            order_type: new_order.order_type,
+            price: new_order.price,
+            quantity: new_order.quantity,
+            remaining_quantity: new_order.quantity,
+            status: OrderStatus::New,
+            time_in_force: new_order.time_in_force,
+            expires_at: new_order.expires_at,
+            client_order_id: new_order.client_order_id,
+            all_or_none: new_order.all_or_none,
+            account_group: new_order.account_group,
+            public: new_order.public,
+            quantity_in_quote: new_order.quantity_in_quote,
+            last_look: new_order.last_look,
+            session_id: new_order.session_id,
+            sequence,
+            timestamp: self.clock.now_ns(),
+            peg: new_order.peg,
+            peg_offset_bps: new_order.peg_offset_bps,
+        };
+
+        // Never match new flow into a crossed book: the crossing invariant
+        // failing means corrupted state. Per policy, either halt order
+        // entry for an operator or uncross the resting orders first. In an
+        // auction call a crossed book is expected — accumulated orders
+        // cross by design until the uncross — so the guard only applies
+        // while matching is live.
+        if self
+            .engines
+            .get(&new_order.market_id)
+            .is_some_and(|e| e.phase().is_matching() && e.book_is_crossed())
+        {
+            match self.config.crossed_book_policy {
+                CrossedBookPolicy::Halt => {
+                    tracing::error!(
+                        market_id = %new_order.market_id,
+                        "crossed book detected; halting order entry"
+                    );
+                    self.halted = true;
+                    return Err(EngineError::Halted);
+                }
+                CrossedBookPolicy::Uncross => {
+                    let uncross_trades = self
+                        .engines
+                        .get_mut(&new_order.market_id)
+                        .map(|e| e.uncross())
+                        .unwrap_or_default();
+                    tracing::warn!(
+                        market_id = %new_order.market_id,
+                        trades = uncross_trades.len(),
+                        "uncrossed corrupted book before matching"
+                    );
+                    let operations =
+                        self.audit_operations(&new_order.market_id, &uncross_trades);
+                    self.journal_post_mutation_batch(operations, new_order.ack_mode)?;
+                }
+            }
+        }
+
+        // Journal the command before touching the book: if this append
+        // fails the in-memory state is unchanged, so rejecting the order is
+        // a clean no-op. (That only holds for this batch — the records
+        // journaled after matching below fail as torn state instead; see
+        // [`Exchange::journal_post_mutation_batch`].) If we crash after the
+        // append, recovery replays the command and reproduces the same
+        // matching deterministically. Fast-ack placements skip the sync
+        // here and in the audit batch below.
+        let journaled = self
+            .journal_batch(
+                vec![WalOperation::PlaceOrder(order.clone())],
+                new_order.ack_mode,
+            )
+            .map(|_| ());
+        if let Err(e) = journaled {
+            self.next_order_id -= 1;
+            self.next_order_sequence -= 1;
+            return Err(EngineError::Wal(e));
+        }
+
+        let engine = self.get_or_create_engine(&new_order.market_id);
+        let (order, trades) = engine.place_order(order);
+
+        if order.status == OrderStatus::New || order.status == OrderStatus::PartiallyFilled {
+            if let Some(session_id) = &order.session_id {
+                self.sessions
+                    .entry(session_id.clone())
+                    .or_default()
+                    .push((order.market_id.clone(), order.id));
+            }
+        }
+
+        // Makers that declined via last look left the book during matching;
+        // journal their cancels so a replay without the market's window
+        // still converges on the same book.
+        let mut removed_makers = self
+            .engines
+            .get_mut(&new_order.market_id)
+            .map(|e| {
+                let mut makers = e.take_last_look_cancels();
+                // Sub-lot maker remainders cancelled as dust journal the
+                // same way.
+                makers.extend(e.take_dust_cancels());
+                makers
+            })
+            .unwrap_or_default();
+        // A limit taker whose remainder could not rest (self-trade
+        // prevention, a risk veto, a sub-lot remainder, the no-lock
+        // policy) was cancelled without ever reaching the book. Journal
+        // that cancel: literal replay inserts the command without
+        // re-matching and needs the record to remove the remainder.
+        if order.order_type == OrderType::Limit
+            && order.time_in_force != TimeInForce::Ioc
+            && order.status == OrderStatus::Cancelled
+        {
+            removed_makers.push(order.clone());
+        }
+        // Canonical ordering of one matching pass in the log: the command
+        // itself (journaled above), then every trade in fill order with its
+        // fill markers, then any maker cancels the pass produced. Batch
+        // appends assign sequences in vec order, so both the global and
+        // per-market sequences are strictly increasing along this ordering
+        // and replay consumers never see a cancel ahead of the fills that
+        // motivated it.
+        //
+        // Literal replay (the default) applies the trade records
+        // authoritatively; rematch replay regenerates trades from the
+        // commands and treats them as audit-only. The whole matching pass
+        // is group-committed under one fsync. The book mutated above, so a
+        // failure here is torn state and halts order entry at once.
+        let mut operations = self.audit_operations(&new_order.market_id, &trades);
+        operations.extend(removed_makers.drain(..).map(|maker| WalOperation::CancelOrder {
+            market_id: maker.market_id.clone(),
+            order_id: maker.id,
+        }));
+        self.journal_post_mutation_batch(operations, new_order.ack_mode)?;
+        Ok((order, trades))
+    }
+
+    /// Places a two-sided quote atomically: the user's previous quote in
+    /// the market (if any) is cancelled first, then the new bid and ask go
+    /// in, all under the same lock acquisition — a maker is never exposed
+    /// one-sided between two round trips. The sides carry the reserved
+    /// [`QUOTE_BID_CLIENT_ID`] / [`QUOTE_ASK_CLIENT_ID`] client ids, which
+    /// is how the previous quote is found. If the ask is rejected after
+    /// the bid went in, the bid is cancelled again so the quote is
+    /// all-or-nothing. Returns both sides with any fills they took.
+    pub fn place_quote(
+        &mut self,
+        quote: NewQuote,
+    ) -> Result<PlacedQuote, EngineError> {
+        if quote.bid_price >= quote.ask_price {
+            return Err(EngineError::InvalidOrder(
+                RejectReason::InvalidPrice,
+                format!(
+                    "quote is crossed: bid {} >= ask {}",
+                    quote.bid_price, quote.ask_price
+                ),
+            ));
+        }
+        self.cancel_order_by_client_id(&quote.market_id, QUOTE_BID_CLIENT_ID, quote.user_id)?;
+        self.cancel_order_by_client_id(&quote.market_id, QUOTE_ASK_CLIENT_ID, quote.user_id)?;
+        let side = |side: Side, price: Decimal, quantity: Decimal, client_id: &str| NewOrder {
+            market_id: quote.market_id.clone(),
+            user_id: quote.user_id,
+            side,
+            order_type: OrderType::Limit,
+            price,
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: Some(client_id.to_string()),
+            session_id: None,
+            ack_mode: AckMode::Durable,
+            expected_sequence: None,
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        };
+        let bid = self.place_order(side(
+            Side::Buy,
+            quote.bid_price,
+            quote.bid_quantity,
+            QUOTE_BID_CLIENT_ID,
+        ))?;
+        match self.place_order(side(
+            Side::Sell,
+            quote.ask_price,
+            quote.ask_quantity,
+            QUOTE_ASK_CLIENT_ID,
+        )) {
+            Ok(ask) => Ok((bid, ask)),
+            Err(e) => {
+                // Roll the bid back so a half-placed quote never rests.
+                // Best-effort: the bid may already have filled away.
+                let _ = self.cancel_order(&quote.market_id, bid.0.id, quote.user_id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Drives a market through its session schedule (see [`MarketPhase`]).
+    /// The transition is journaled as a command before it is applied — it
+    /// changes how subsequent placements match, so replay must reproduce it
+    /// at the same point in the stream — and any auction trades from an
+    /// `Open`/`Close` uncross are journaled like a matching pass. Returns
+    /// those trades.
+    pub fn set_phase(
+        &mut self,
+        market_id: &str,
+        phase: MarketPhase,
+    ) -> Result<Vec<Trade>, EngineError> {
+        if self.halted {
+            return Err(EngineError::Halted);
+        }
+        self.journal(WalOperation::SetPhase {
+            market_id: market_id.to_string(),
+            phase,
+        })
+        .map_err(EngineError::Wal)?;
+        let trades = self.get_or_create_engine(market_id).set_phase(phase);
+        let operations = self.audit_operations(market_id, &trades);
+        self.journal_post_mutation_batch(operations, AckMode::Durable)?;
+        Ok(trades)
+    }
+
+    /// Audit records for one matching pass: a `TradeExecuted` per trade plus
+    /// an `OrderFilled` per fully consumed maker (if enabled).
+    fn audit_operations(&mut self, market_id: &str, trades: &[Trade]) -> Vec<WalOperation> {
+        let mut operations: Vec<WalOperation> = if self.config.wal_aggregate_trades {
+            // One record per taker, carrying its fills in order. A single
+            // pass is one taker's trades, but reaps and uncrosses can batch
+            // several takers into one call.
+            let mut grouped: Vec<WalOperation> = Vec::new();
+            for trade in trades {
+                match grouped.last_mut() {
+                    Some(WalOperation::OrderMatched {
+                        taker_order_id,
+                        trades,
+                        ..
+                    }) if *taker_order_id == trade.taker_order_id => {
+                        trades.push(trade.clone());
+                    }
+                    _ => grouped.push(WalOperation::OrderMatched {
+                        market_id: market_id.to_string(),
+                        taker_order_id: trade.taker_order_id,
+                        trades: vec![trade.clone()],
+                    }),
+                }
+            }
+            grouped
+        } else {
+            trades
+                .iter()
+                .map(|t| WalOperation::TradeExecuted(t.clone()))
+                .collect()
+        };
+        let filled = match self.engines.get_mut(market_id) {
+            Some(engine) => engine.take_filled_makers(),
+            None => return operations,
+        };
+        if self.config.wal_filled_orders {
+            operations.extend(filled.into_iter().map(|(order_id, filled_quantity)| {
+                WalOperation::OrderFilled {
+                    market_id: market_id.to_string(),
+                    order_id,
+                    filled_quantity,
+                }
+            }));
+        }
+        if let Some(engine) = self.engines.get_mut(market_id) {
+            operations.extend(engine.take_repriced().into_iter().map(
+                |(order_id, new_price)| WalOperation::OrderRepriced {
+                    market_id: market_id.to_string(),
+                    order_id,
+                    new_price,
+                },
+            ));
+        }
+        operations
+    }
+
+    /// Rejects a limit order that would create a price level beyond the
+    /// market's per-side cap, or join a level already holding the maximum
+    /// number of orders. Crossing the opposite touch is always allowed:
+    /// only genuinely resting additions are refused, protecting matching
+    /// latency on pathological books. Rejected pre-journal, so replay is
+    /// unaffected.
+    fn validate_level_capacity(&self, new_order: &NewOrder) -> Result<(), EngineError> {
+        if new_order.order_type != OrderType::Limit {
+            return Ok(());
+        }
+        let market = self.market_config(&new_order.market_id);
+        let (level_cap, order_cap) = (market.max_price_levels, market.max_orders_per_level);
+        if level_cap == 0 && order_cap == 0 {
+            return Ok(());
+        }
+        let Some(engine) = self.engines.get(&new_order.market_id) else {
+            return Ok(());
+        };
+        let book = &engine.orderbook;
+        let crosses = match new_order.side {
+            Side::Buy => book.best_ask().is_some_and(|l| new_order.price >= l.price),
+            Side::Sell => book.best_bid().is_some_and(|l| new_order.price <= l.price),
+        };
+        if crosses {
+            return Ok(());
+        }
+        let levels = match new_order.side {
+            Side::Buy => &book.bids,
+            Side::Sell => &book.asks,
+        };
+        match levels.get(&new_order.price) {
+            Some(level) => {
+                if order_cap > 0 && level.order_count() >= order_cap as usize {
+                    return Err(EngineError::InvalidOrder(
+                        RejectReason::LevelOrderCap,
+                        format!(
+                            "price level {} already holds the maximum {order_cap} orders",
+                            new_order.price
+                        ),
+                    ));
+                }
+            }
+            None => {
+                if level_cap > 0 && levels.len() >= level_cap as usize {
+                    return Err(EngineError::InvalidOrder(
+                        RejectReason::LevelCap,
+                        format!(
+                            "{:?} side already holds the maximum {level_cap} price levels; order at {} would add another",
+                            new_order.side, new_order.price
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects a limit order priced more than the market's collar away
+    /// from the reference mid. Requires a reference feed on the market's
+    /// engine; without one (or with `collar_bps` 0) everything passes.
+    /// Each rejection is logged and recorded in [`CollarStats`] so band
+    /// widths can be tuned against real traffic.
+    fn validate_collar(&mut self, new_order: &NewOrder) -> Result<(), EngineError> {
+        if new_order.order_type != OrderType::Limit {
+            return Ok(());
+        }
+        let collar_bps = self.market_config(&new_order.market_id).collar_bps;
+        if collar_bps <= Decimal::ZERO {
+            return Ok(());
+        }
+        let Some(mid) = self
+            .engines
+            .get(&new_order.market_id)
+            .and_then(|e| e.reference_mid())
+        else {
+            return Ok(());
+        };
+        if mid <= Decimal::ZERO {
+            return Ok(());
+        }
+        let deviation_bps =
+            (new_order.price - mid).abs() / mid * Decimal::from(10_000u32);
+        if deviation_bps <= collar_bps {
+            return Ok(());
+        }
+        let stats = self
+            .collar_stats
+            .entry(new_order.market_id.clone())
+            .or_default();
+        stats.rejections += 1;
+        let bucket = COLLAR_BUCKET_BPS
+            .iter()
+            .position(|b| deviation_bps <= Decimal::from(*b))
+            .unwrap_or(COLLAR_BUCKET_BPS.len());
+        stats.deviation_buckets[bucket] += 1;
+        tracing::info!(
+            market_id = %new_order.market_id,
+            price = %new_order.price,
+            deviation_bps = %deviation_bps,
+            collar_bps = %collar_bps,
+            "order rejected by price collar"
+        );
+        Err(EngineError::InvalidOrder(
+            RejectReason::PriceCollar,
+            format!(
+                "price {} deviates {deviation_bps} bps from reference mid {mid}, over the {collar_bps} bps collar",
+                new_order.price
+            ),
+        ))
+    }
+
+    /// A market's collar-rejection telemetry so far; zeroes if it has never
+    /// tripped.
+    pub fn collar_stats(&self, market_id: &str) -> CollarStats {
+        self.collar_stats.get(market_id).cloned().unwrap_or_default()
+    }
+
+    /// Rejects the mutation unless `user_id` owns the resting order, or is
+    /// 0 (trusted internal callers: session teardown, replay). Checked under
+    /// the exchange lock, so it cannot race the order being filled away.
+    fn check_ownership(
+        &self,
+        market_id: &str,
+        order_id: OrderId,
+        user_id: UserId,
+    ) -> Result<(), EngineError> {
+        if user_id == 0 {
+            return Ok(());
+        }
+        let owner = self
+            .engines
+            .get(market_id)
+            .and_then(|e| e.orderbook.get_order(order_id))
+            .map(|o| o.user_id);
+        match owner {
+            Some(owner) if owner != user_id => Err(EngineError::PermissionDenied(format!(
+                "order {order_id} is not owned by user {user_id}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Cancels a resting order owned by `user_id` (0 skips the ownership
+    /// check). Returns `None` if the order is not resting. Audited like
+    /// placements: the cancel or its rejection lands on the audit log.
+    pub fn cancel_order(
+        &mut self,
+        market_id: &str,
+        order_id: OrderId,
+        user_id: UserId,
+    ) -> Result<Option<Order>, EngineError> {
+        let result = self.cancel_order_inner(market_id, order_id, user_id);
+        if self.audit.is_some() {
+            match &result {
+                Ok(Some(order)) => {
+                    let action = AuditAction::OrderCancelled { order_id: order.id };
+                    self.audit_record(market_id, user_id, action);
+                }
+                // A cancel for an order that is no longer resting is a
+                // no-op, not a decision.
+                Ok(None) => {}
+                Err(e) => {
+                    let action = AuditAction::CancelRejected {
+                        order_id,
+                        reason: reject_code(e),
+                        detail: e.to_string(),
+                    };
+                    self.audit_record(market_id, user_id, action);
+                }
+            }
+        }
+        result
+    }
+
+    fn cancel_order_inner(
+        &mut self,
+        market_id: &str,
+        order_id: OrderId,
+        user_id: UserId,
+    ) -> Result<Option<Order>, EngineError> {
+        self.check_ownership(market_id, order_id, user_id)?;
+        // Anti-flicker: a market with a minimum resting time refuses the
+        // owner's cancel until the order has rested that long. Fills are
+        // unaffected, and internal cancels (user_id 0: session teardown,
+        // expiry, admin) are exempt.
+        let min_rest = self.market_config(market_id).min_resting_time_ns;
+        if min_rest > 0 && user_id != 0 {
+            let placed_at = self
+                .engines
+                .get(market_id)
+                .and_then(|e| e.orderbook.get_order(order_id))
+                .map(|o| o.timestamp);
+            if let Some(placed_at) = placed_at {
+                let rested = self.clock.now_ns() - placed_at;
+                if rested < min_rest {
+                    return Err(EngineError::TooEarlyToCancel(format!(
+                        "order {order_id} has rested {rested}ns of the market's minimum {min_rest}ns"
+                    )));
+                }
+            }
+        }
+        // Journal before mutating, but only for orders that actually rest.
+        let resting = self
+            .engines
+            .get(market_id)
+            .is_some_and(|e| e.orderbook.get_order(order_id).is_some());
+        if !resting {
+            return Ok(None);
+        }
+        self.journal(WalOperation::CancelOrder {
+            market_id: market_id.to_string(),
+            order_id,
+        })
+        .map_err(EngineError::Wal)?;
+        let order = self
+            .engines
+            .get_mut(market_id)
+            .and_then(|e| e.cancel_order(order_id));
+        // The cancel may have moved pegged orders whose reference it was.
+        let audit = self.audit_operations(market_id, &[]);
+        self.journal_batch(audit, AckMode::Durable)
+            .map_err(EngineError::Wal)?;
+        Ok(order)
+    }
+
+    /// Cancels by the owner's own client order id instead of the engine id.
+    /// Resolution happens against the live book; the journaled operation
+    /// carries the resolved engine id, so replay never depends on the index.
+    pub fn cancel_order_by_client_id(
+        &mut self,
+        market_id: &str,
+        client_order_id: &str,
+        user_id: UserId,
+    ) -> Result<Option<Order>, EngineError> {
+        let order_id = self
+            .engines
+            .get(market_id)
+            .and_then(|e| e.orderbook.find_client_order(user_id, client_order_id));
+        match order_id {
+            Some(order_id) => self.cancel_order(market_id, order_id, user_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Amends a resting order (cancel-and-replace semantics: queue priority
+    /// is lost). Returns `None` if the order is not resting.
+    pub fn amend_order(
+        &mut self,
+        market_id: &str,
+        order_id: OrderId,
+        user_id: UserId,
+        new_price: Decimal,
+        new_quantity: Decimal,
+    ) -> Result<Option<(Order, Vec<Trade>)>, EngineError> {
+        self.check_ownership(market_id, order_id, user_id)?;
+        let resting = self
+            .engines
+            .get(market_id)
+            .is_some_and(|e| e.orderbook.get_order(order_id).is_some());
+        if !resting {
+            return Ok(None);
+        }
+        let sequence = self.next_order_sequence;
+        self.journal(WalOperation::AmendOrder {
+            market_id: market_id.to_string(),
+            order_id,
+            new_price,
+            new_quantity,
+            sequence,
+        })
+        .map_err(EngineError::Wal)?;
+        self.next_order_sequence += 1;
+        let Some((order, trades)) = self
+            .engines
+            .get_mut(market_id)
+            .and_then(|e| e.amend_order(order_id, new_price, new_quantity, sequence))
+        else {
+            return Ok(None);
+        };
+        let audit = self.audit_operations(market_id, &trades);
+        self.journal_post_mutation_batch(audit, AckMode::Durable)?;
+        Ok(Some((order, trades)))
+    }
+
+    /// Reduces a resting order's size in place (queue priority kept),
+    /// journaling the reduction. Reductions larger than the remaining
+    /// quantity are rejected; reducing the exact remainder cancels.
+    /// Returns `None` if the order is not resting.
+    pub fn reduce_order(
+        &mut self,
+        market_id: &str,
+        order_id: OrderId,
+        user_id: UserId,
+        reduce_by: Decimal,
+    ) -> Result<Option<Order>, EngineError> {
+        self.check_ownership(market_id, order_id, user_id)?;
+        let Some(remaining) = self
+            .engines
+            .get(market_id)
+            .and_then(|e| e.orderbook.get_order(order_id))
+            .map(|o| o.remaining_quantity)
+        else {
+            return Ok(None);
+        };
+        if reduce_by <= Decimal::ZERO {
+            return Err(EngineError::InvalidOrder(
+                RejectReason::InvalidQuantity,
+                format!("reduce_by {reduce_by} must be positive"),
+            ));
+        }
+        if reduce_by > remaining {
+            return Err(EngineError::InvalidOrder(
+                RejectReason::InvalidQuantity,
+                format!("reduce_by {reduce_by} exceeds remaining quantity {remaining}"),
+            ));
+        }
+        self.journal(WalOperation::ReduceOrder {
+            market_id: market_id.to_string(),
+            order_id,
+            reduce_by,
+        })
+        .map_err(EngineError::Wal)?;
+        let order = self
+            .engines
+            .get_mut(market_id)
+            .and_then(|e| e.reduce_order(order_id, reduce_by));
+        // The reduction may have moved pegged orders whose reference it was.
+        let audit = self.audit_operations(market_id, &[]);
+        self.journal_post_mutation_batch(audit, AckMode::Durable)?;
+        Ok(order)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn wal_mut(&mut self) -> &mut WAL {
+        &mut self.wal
+    }
+
+    /// Cancels every resting order placed under a session, journaling each
+    /// cancel. Used for cancel-on-disconnect when a session's stream drops.
+    /// Returns the cancelled orders.
+    pub fn cancel_session(&mut self, session_id: &str) -> Result<Vec<Order>, EngineError> {
+        let tracked = self.sessions.remove(session_id).unwrap_or_default();
+        let mut cancelled = Vec::new();
+        for (market_id, order_id) in tracked {
+            // Orders already filled or cancelled are simply gone by now.
+            if let Some(order) = self.cancel_order(&market_id, order_id, 0)? {
+                cancelled.push(order);
+            }
+        }
+        Ok(cancelled)
+    }
+
+    /// Expires due GTD orders across all markets, journaling each cancel.
+    pub fn reap_expired(&mut self, now: i64) -> Result<Vec<Order>, EngineError> {
+        let mut all_expired = Vec::new();
+        let market_ids: Vec<String> = self.engines.keys().cloned().collect();
+        for market_id in market_ids {
+            let mut removed = self
+                .engines
+                .get_mut(&market_id)
+                .map(|e| e.reap_expired(now))
+                .unwrap_or_default();
+            // Stale-quote eviction rides the same tick and journals the
+            // same way: as plain cancels.
+            removed.extend(
+                self.engines
+                    .get_mut(&market_id)
+                    .map(|e| e.evict_stale(now))
+                    .unwrap_or_default(),
+            );
+            for order in &removed {
+                self.journal(WalOperation::CancelOrder {
+                    market_id: market_id.clone(),
+                    order_id: order.id,
+                })
+                .map_err(EngineError::Wal)?;
+            }
+            all_expired.extend(removed);
+        }
+        Ok(all_expired)
+    }
+
+    /// Sweeps sub-lot dust remainders off every market's book, journaling
+    /// each cancel like any other maintenance removal. Meant to run on a
+    /// schedule; see [`MatchingEngine::sweep_dust`] for what counts as dust.
+    pub fn sweep_dust(&mut self) -> Result<Vec<Order>, EngineError> {
+        let mut all_swept = Vec::new();
+        let market_ids: Vec<String> = self.engines.keys().cloned().collect();
+        for market_id in market_ids {
+            let swept = self
+                .engines
+                .get_mut(&market_id)
+                .map(|e| e.sweep_dust())
+                .unwrap_or_default();
+            for order in &swept {
+                self.journal(WalOperation::CancelOrder {
+                    market_id: market_id.clone(),
+                    order_id: order.id,
+                })
+                .map_err(EngineError::Wal)?;
+            }
+            all_swept.extend(swept);
+        }
+        Ok(all_swept)
+    }
+
+    /// Writes a snapshot of one market consistent with the current WAL head.
+    /// Runs under `&mut self` — the same exclusivity matching holds — so the
+    /// book can never be captured mid-mutation; with
+    /// `snapshot_verify_invariants` set the book is additionally
+    /// cross-checked first and an inconsistent state fails the snapshot
+    /// rather than being persisted.
+    pub fn snapshot_market(&mut self, market_id: &str) -> io::Result<Option<(PathBuf, i64)>> {
+        let sequence = self.wal.next_sequence() - 1;
+        let Some(engine) = self.engines.get(market_id) else {
+            return Ok(None);
+        };
+        if self.config.snapshot_verify_invariants {
+            engine.orderbook.verify_invariants().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("refusing to snapshot {market_id}: {e}"),
+                )
+            })?;
+        }
+        let snapshot = Snapshot {
+            market_id: market_id.to_string(),
+            sequence,
+            timestamp: now_ns(),
+            orderbook: engine.orderbook.clone(),
+            next_trade_id: engine.next_trade_id(),
+            rng_state: engine.rng_state(),
+            phase: engine.phase(),
+            user_volumes: engine.user_volumes().clone(),
+        };
+        self.snapshots.save(&snapshot).map(|path| Some((path, sequence)))
+    }
+
+    /// Snapshots every active market in one pass, returning a manifest of
+    /// `(market_id, path, sequence)`. Runs to completion under `&mut self`
+    /// — a brief pause during which no writes can interleave — so every
+    /// entry shares the same WAL boundary and the set is a consistent
+    /// cross-market recovery point. Unlike [`Exchange::checkpoint`] this
+    /// leaves the WAL untouched.
+    pub fn snapshot_all(&mut self) -> io::Result<Vec<(String, PathBuf, i64)>> {
+        let mut manifest = Vec::new();
+        for market_id in self.market_ids() {
+            if let Some((path, sequence)) = self.snapshot_market(&market_id)? {
+                manifest.push((market_id, path, sequence));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Snapshot-and-truncate in one safe operation: snapshots every market
+    /// at the current WAL head, verifies each snapshot loads back with the
+    /// live book's digest, and only then truncates WAL segments below the
+    /// head. The WAL is shared across markets, so a checkpoint always covers
+    /// all of them; truncating behind a single market's snapshot could drop
+    /// entries another market still needs. Returns the checkpointed
+    /// sequence, or `None` when there are no markets.
+    pub fn checkpoint(&mut self) -> io::Result<Option<i64>> {
+        let market_ids = self.market_ids();
+        if market_ids.is_empty() {
+            return Ok(None);
+        }
+        let mut sequence = i64::MAX;
+        for market_id in market_ids {
+            let Some((path, seq)) = self.snapshot_market(&market_id)? else {
+                continue;
+            };
+            // A snapshot that cannot be read back, or that does not match
+            // the live book, is not a recovery point; leave the WAL alone.
+            let loaded = self.snapshots.load(&path)?;
+            let live_digest = self
+                .engines
+                .get(&market_id)
+                .map(|e| e.orderbook.digest());
+            if Some(loaded.orderbook.digest()) != live_digest {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checkpoint verification failed for {market_id}"),
+                ));
+            }
+            sequence = sequence.min(seq);
+        }
+        self.wal.truncate_before(sequence)?;
+        self.checkpoint_bytes_mark = self.wal.bytes_appended();
+        Ok(Some(sequence))
+    }
+
+    /// Installs (or replaces) the compliance audit sink.
+    pub fn set_audit_sink(&mut self, sink: Box<dyn AuditSink>) {
+        self.audit = Some(sink);
+    }
+
+    fn audit_record(&mut self, market_id: &str, actor: UserId, action: AuditAction) {
+        if let Some(sink) = &mut self.audit {
+            sink.record(&AuditRecord {
+                timestamp_ns: now_ns(),
+                market_id: market_id.to_string(),
+                actor,
+                action,
+            });
+        }
+    }
+
+    /// Releases delayed trade prints that are due across every market;
+    /// rides the reaper tick like the other periodic duties. Markets
+    /// without a reporting delay hold nothing, so this is effectively free
+    /// for them.
+    pub fn flush_trade_prints(&mut self, now: i64) {
+        for engine in self.engines.values_mut() {
+            engine.flush_trade_prints(now);
+        }
+    }
+
+    /// Whether the byte-based checkpoint trigger has fired: the configured
+    /// number of WAL bytes has been appended since the last checkpoint.
+    /// This bounds recovery IO by write volume where the interval timer only
+    /// bounds it by wall time; a burst can outrun the clock. Always false
+    /// when `checkpoint_wal_bytes` is 0.
+    pub fn checkpoint_due_by_bytes(&self) -> bool {
+        self.config.checkpoint_wal_bytes > 0
+            && self.wal.bytes_appended() - self.checkpoint_bytes_mark
+                >= self.config.checkpoint_wal_bytes
+    }
+
+    /// Rebuilds a market's engine from a snapshot, replacing any existing
+    /// one. Used for the initial restore in [`Exchange::recover`] and for
+    /// the stale-but-bounded fallback when replay exceeds its budget.
+    fn install_snapshot_engine(&mut self, snapshot: Snapshot) {
+        let capacity = self.config.recent_trades_capacity;
+        let mut engine = MatchingEngine::new(&snapshot.market_id, capacity);
+        engine.set_next_trade_id(snapshot.next_trade_id);
+        engine.orderbook = snapshot.orderbook;
+        engine.rebuild_expiry_heap();
+        engine.rebuild_pegged_index();
+        // Resume the deterministic draw sequence from the snapshot;
+        // pre-v2 snapshots restore 0, which is also the default seed.
+        engine.set_rng_seed(self.config.matching_seed);
+        if snapshot.rng_state != 0 {
+            engine.set_rng_state(snapshot.rng_state);
+        }
+        if let Some(spill) = self.trade_spill_for(&engine.market_id) {
+            engine.set_trade_spill(spill);
+        }
+        engine.restore_phase(snapshot.phase);
+        engine.restore_user_volumes(snapshot.user_volumes);
+        self.engines.insert(snapshot.market_id, engine);
+    }
+
+    /// Restores state from the latest snapshots plus the WAL tail.
+    ///
+    /// When `recovery_timeout_ms` is set and the tail replay exceeds it, the
+    /// market being replayed falls back to its latest snapshot (or an empty
+    /// book if it has none) and the rest of the tail is skipped with a
+    /// warning: the engine comes up slightly stale rather than holding
+    /// startup for an unbounded log. Id assignment still resumes above
+    /// everything journaled, so skipped commands can never have their ids
+    /// reissued.
+    pub fn recover(&mut self) -> io::Result<()> {
+        let started = std::time::Instant::now();
+        let budget = (self.config.recovery_timeout_ms > 0)
+            .then(|| std::time::Duration::from_millis(self.config.recovery_timeout_ms));
+        let mut snapshot_seq: HashMap<String, i64> = HashMap::new();
+        let mut replay_from = i64::MAX;
+
+        for snapshot in self.snapshots.load_latest_all()? {
+            replay_from = replay_from.min(snapshot.sequence + 1);
+            snapshot_seq.insert(snapshot.market_id.clone(), snapshot.sequence);
+            self.install_snapshot_engine(snapshot);
+        }
+        if replay_from == i64::MAX {
+            replay_from = 1;
+        }
+
+        // Group the tail by market and order each market's replay by its
+        // own sequence space. Global offsets tie-break pre-v2 entries, whose
+        // `market_sequence` is 0.
+        let literal = self.config.recovery_replay == RecoveryReplay::Literal;
+        let mut per_market: HashMap<String, Vec<crate::wal::WalEntry>> = HashMap::new();
+        for entry in self.wal.read_from(replay_from)? {
+            // Under rematch replay the trade and reprice records are
+            // redundant; matching regenerates them. Literal replay applies
+            // them as recorded; only the fill markers stay redundant (the
+            // trades themselves exhaust the maker).
+            let skip = if literal {
+                matches!(entry.operation, WalOperation::OrderFilled { .. })
+            } else {
+                matches!(
+                    entry.operation,
+                    WalOperation::TradeExecuted(_)
+                        | WalOperation::OrderFilled { .. }
+                        | WalOperation::OrderRepriced { .. }
+                        | WalOperation::OrderMatched { .. }
+                )
+            };
+            if skip {
+                continue;
+            }
+            let market_id = entry.operation.market_id().to_string();
+            // Skip entries already reflected in this market's snapshot.
+            if let Some(&snap_seq) = snapshot_seq.get(&market_id) {
+                if entry.sequence <= snap_seq {
+                    continue;
+                }
+            }
+            per_market.entry(market_id).or_default().push(entry);
+        }
+        let mut market_ids: Vec<String> = per_market.keys().cloned().collect();
+        market_ids.sort();
+        #[cfg(test)]
+        let replay_delay = self.recovery_replay_delay;
+        let mut timed_out: Option<String> = None;
+        'markets: for market_id in market_ids {
+            let mut entries = per_market.remove(&market_id).unwrap_or_default();
+            entries.sort_by_key(|e| (e.market_sequence, e.sequence));
+            let engine = self.get_or_create_engine(&market_id);
+            for entry in entries {
+                #[cfg(test)]
+                if let Some(delay) = replay_delay {
+                    std::thread::sleep(delay);
+                }
+                match entry.operation {
+                    WalOperation::PlaceOrder(order) => {
+                        if literal {
+                            engine.restore_order(order);
+                        } else {
+                            engine.place_order(order);
+                        }
+                    }
+                    WalOperation::CancelOrder { order_id, .. } => {
+                        if literal {
+                            engine.restore_cancel(order_id);
+                        } else {
+                            engine.cancel_order(order_id);
+                        }
+                    }
+                    WalOperation::AmendOrder {
+                        order_id,
+                        new_price,
+                        new_quantity,
+                        sequence,
+                        ..
+                    } => {
+                        if literal {
+                            engine.restore_amend(order_id, new_price, new_quantity, sequence);
+                        } else {
+                            engine.amend_order(order_id, new_price, new_quantity, sequence);
+                        }
+                    }
+                    WalOperation::ReduceOrder {
+                        order_id, reduce_by, ..
+                    } => {
+                        if literal {
+                            engine.restore_reduce(order_id, reduce_by);
+                        } else {
+                            engine.reduce_order(order_id, reduce_by);
+                        }
+                    }
+                    WalOperation::SetPhase { phase, .. } => {
+                        if literal {
+                            // The uncross this transition ran arrives as
+                            // recorded trades; re-running it would double.
+                            engine.restore_phase(phase);
+                        } else {
+                            engine.set_phase(phase);
+                        }
+                    }
+                    WalOperation::TradeExecuted(trade) => {
+                        engine.apply_recorded_trade(&trade);
+                    }
+                    WalOperation::OrderMatched { trades, .. } => {
+                        for trade in &trades {
+                            engine.apply_recorded_trade(trade);
+                        }
+                    }
+                    WalOperation::OrderRepriced {
+                        order_id, new_price, ..
+                    } => {
+                        engine.apply_reprice(order_id, new_price);
+                    }
+                    WalOperation::OrderFilled { .. } => {}
+                }
+                if budget.is_some_and(|b| started.elapsed() > b) {
+                    timed_out = Some(market_id);
+                    break 'markets;
+                }
+            }
+        }
+        if let Some(market_id) = timed_out {
+            tracing::warn!(
+                market_id = %market_id,
+                timeout_ms = self.config.recovery_timeout_ms,
+                "recovery timed out replaying the WAL tail; serving the latest snapshots"
+            );
+            // Shed the partial replay: the market comes back exactly as
+            // snapshotted, not at some arbitrary mid-tail point. Markets
+            // sorted after it were never touched and are already
+            // snapshot-only.
+            match self.snapshots.load_latest(&market_id)? {
+                Some(snapshot) => self.install_snapshot_engine(snapshot),
+                None => {
+                    self.engines.remove(&market_id);
+                }
+            }
+        }
+
+        // Resume id assignment above anything seen in snapshots or the WAL.
+        let max_resting = self
+            .engines
+            .values()
+            .flat_map(|e| e.orderbook.orders.values())
+            .map(|o| (o.id, o.sequence))
+            .fold((0, 0), |acc, x| (acc.0.max(x.0), acc.1.max(x.1)));
+        let max_replayed = self
+            .wal
+            .read_from(1)?
+            .iter()
+            .filter_map(|e| match &e.operation {
+                WalOperation::PlaceOrder(o) => Some((o.id, o.sequence)),
+                WalOperation::AmendOrder { sequence, .. } => Some((0, *sequence)),
+                _ => None,
+            })
+            .fold((0, 0), |acc, x| (acc.0.max(x.0), acc.1.max(x.1)));
+        self.next_order_id = max_resting.0.max(max_replayed.0) + 1;
+        self.next_order_sequence = max_resting.1.max(max_replayed.1) + 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use tempfile::TempDir;
+
+    fn test_config(dir: &TempDir) -> EngineConfig {
+        EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        }
+    }
+
+    fn limit(market_id: &str, user_id: u64, side: Side, price: Decimal, qty: Decimal) -> NewOrder {
+        NewOrder {
+            market_id: market_id.to_string(),
+            user_id,
+            side,
+            order_type: OrderType::Limit,
+            price,
+            quantity: qty,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: None,
+            session_id: None,
+            ack_mode: AckMode::Durable,
+            expected_sequence: None,
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn orders_match_and_are_journaled() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+
+        let (maker, trades) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(maker.status, OrderStatus::New);
+
+        let (taker, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+    }
+
+    #[test]
+    fn tier_volume_survives_a_checkpoint_and_recovery() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+            exchange
+                .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2)))
+                .unwrap();
+            exchange
+                .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(2)))
+                .unwrap();
+            exchange.checkpoint().unwrap();
+        }
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange.recover().unwrap();
+        // Both sides of the fill resume with their traded volume, so a fee
+        // tier earned before the restart still applies.
+        let engine = exchange.engine("BTC-USD").unwrap();
+        assert_eq!(engine.user_volume(1), dec!(2));
+        assert_eq!(engine.user_volume(2), dec!(2));
+    }
+
+    #[test]
+    fn strict_mode_rejects_orders_for_unlisted_markets() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            allow_unlisted_markets: false,
+            ..test_config(&dir)
+        };
+        let mut exchange = Exchange::new(config).unwrap();
+        exchange
+            .set_market_configs(HashMap::from([(
+                "BTC-USD".to_string(),
+                MarketConfig::default(),
+            )]))
+            .unwrap();
+
+        // A typo'd market id fails typed instead of opening a new market.
+        let err = exchange
+            .place_order(limit("BTC-USDT", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::UnknownMarket(_)));
+        assert!(exchange.engine("BTC-USDT").is_none());
+        // The configured market trades normally.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+
+        // The permissive default keeps the historical auto-create.
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USDT", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert!(exchange.engine("BTC-USDT").is_some());
+    }
+
+    #[test]
+    fn snapshots_under_heavy_mutation_always_pass_the_invariant_check() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::{Arc, Mutex};
+
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            snapshot_verify_invariants: true,
+            ..test_config(&dir)
+        };
+        let exchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        // Seed the market so the first snapshot has a book to verify.
+        exchange
+            .lock()
+            .unwrap()
+            .place_order(limit("BTC-USD", 9, Side::Buy, dec!(90), dec!(1)))
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..2u64)
+            .map(|worker| {
+                let exchange = Arc::clone(&exchange);
+                let stop = Arc::clone(&stop);
+                std::thread::spawn(move || {
+                    let mut resting = Vec::new();
+                    for i in 0u64.. {
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+                        let price = dec!(95) + Decimal::from(i % 11);
+                        let mut exchange = exchange.lock().unwrap();
+                        let (order, _) = exchange
+                            .place_order(limit("BTC-USD", worker + 1, side, price, dec!(1)))
+                            .unwrap();
+                        if order.status == OrderStatus::New {
+                            resting.push(order.id);
+                        }
+                        if i % 3 == 0 {
+                            if let Some(id) = resting.pop() {
+                                let _ = exchange.cancel_order("BTC-USD", id, 0);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // With verification on, an inconsistent capture would fail the
+        // snapshot; every write below must therefore see a coherent book.
+        for _ in 0..25 {
+            let taken = exchange.lock().unwrap().snapshot_market("BTC-USD").unwrap();
+            assert!(taken.is_some());
+            std::thread::yield_now();
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn literal_recovery_reconstructs_the_recorded_book_not_a_rematch() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+            // A protected maker trades because the market has no last-look
+            // window at execution time.
+            let mut maker = limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2));
+            maker.last_look = true;
+            exchange.place_order(maker).unwrap();
+            let (_, trades) = exchange
+                .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+                .unwrap();
+            assert_eq!(trades.len(), 1);
+        }
+
+        // The market gains a last-look window before the restart: a re-run
+        // of matching would now decline the recorded trade and cancel the
+        // maker. Literal replay applies the history as written instead.
+        let restarted = |replay: RecoveryReplay| {
+            let mut exchange = Exchange::new(EngineConfig {
+                recovery_replay: replay,
+                ..test_config(&dir)
+            })
+            .unwrap();
+            let mut markets = HashMap::new();
+            markets.insert(
+                "BTC-USD".to_string(),
+                MarketConfig {
+                    last_look_window_ns: i64::MAX,
+                    ..MarketConfig::default()
+                },
+            );
+            exchange.set_market_configs(markets).unwrap();
+            exchange.recover().unwrap();
+            exchange
+        };
+
+        let literal = restarted(RecoveryReplay::Literal);
+        let book = &literal.engine("BTC-USD").unwrap().orderbook;
+        assert_eq!(book.get_order(1).unwrap().remaining_quantity, dec!(1));
+        assert!(book.best_bid().is_none());
+
+        // Rematch replay diverges under the changed config, which is
+        // exactly what literal replay protects against.
+        let rematched = restarted(RecoveryReplay::Rematch);
+        let book = &rematched.engine("BTC-USD").unwrap().orderbook;
+        assert!(book.get_order(1).is_none());
+    }
+
+    #[test]
+    fn recovery_replays_wal_onto_snapshot() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+            exchange
+                .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2)))
+                .unwrap();
+            exchange.snapshot_market("BTC-USD").unwrap();
+            // Post-snapshot activity lives only in the WAL.
+            exchange
+                .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+                .unwrap();
+            exchange
+                .place_order(limit("BTC-USD", 3, Side::Buy, dec!(99), dec!(1)))
+                .unwrap();
+        }
+
+        let mut recovered = Exchange::new(test_config(&dir)).unwrap();
+        recovered.recover().unwrap();
+        let engine = recovered.engine("BTC-USD").unwrap();
+        // Maker was half-filled by the replayed taker; the 99 bid rests.
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(1)
+        );
+        assert_eq!(engine.orderbook.best_bid().unwrap().price, dec!(99));
+
+        // New ids continue above everything recovered.
+        let (order, _) = recovered
+            .place_order(limit("BTC-USD", 4, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+        assert!(order.id > 3);
+    }
+
+    #[test]
+    fn configured_tick_size_is_enforced_and_defaults_apply_elsewhere() {
+        let dir = TempDir::new().unwrap();
+        let markets_file = dir.path().join("markets.json");
+        std::fs::write(
+            &markets_file,
+            r#"{"BTC-USD": {"tick_size": "0.5", "min_notional": "10"}}"#,
+        )
+        .unwrap();
+        let config = EngineConfig {
+            markets_file,
+            ..test_config(&dir)
+        };
+        let mut exchange = Exchange::new(config).unwrap();
+
+        // Off-tick price is rejected before anything is journaled.
+        let err = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99.25), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::TickSize));
+        // Below min notional.
+        assert!(exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(0.5), dec!(1)))
+            .is_err());
+        // On-tick order above min notional is accepted.
+        assert!(exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99.5), dec!(1)))
+            .is_ok());
+        // An unconfigured market takes any price.
+        assert!(exchange
+            .place_order(limit("ETH-USD", 1, Side::Buy, dec!(0.0001), dec!(1)))
+            .is_ok());
+    }
+
+    #[test]
+    fn failed_wal_append_leaves_book_unchanged() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2)))
+            .unwrap();
+
+        exchange.wal_mut().fail_appends = true;
+        let err = exchange.place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)));
+        assert!(err.is_err());
+        let cancel = exchange.cancel_order("BTC-USD", 1, 0);
+        assert!(cancel.is_err());
+
+        // The maker is untouched: no fill, no cancel, nothing new resting.
+        let engine = exchange.engine("BTC-USD").unwrap();
+        assert_eq!(engine.orderbook.order_count(), 1);
+        assert_eq!(
+            engine.orderbook.get_order(1).unwrap().remaining_quantity,
+            dec!(2)
+        );
+        assert!(engine.recent_trades.is_empty());
+
+        // Once the WAL recovers, the same command goes through cleanly.
+        exchange.wal_mut().fail_appends = false;
+        let (taker, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+    }
+
+    #[test]
+    fn cancel_session_pulls_all_resting_session_orders() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+
+        let mut quoted = limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1));
+        quoted.session_id = Some("mm-1".into());
+        exchange.place_order(quoted.clone()).unwrap();
+        quoted.market_id = "ETH-USD".into();
+        exchange.place_order(quoted).unwrap();
+        // An order outside the session stays put.
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+
+        let cancelled = exchange.cancel_session("mm-1").unwrap();
+        assert_eq!(cancelled.len(), 2);
+        assert_eq!(exchange.engine("BTC-USD").unwrap().orderbook.order_count(), 1);
+        assert_eq!(exchange.engine("ETH-USD").unwrap().orderbook.order_count(), 0);
+        // Idempotent once drained.
+        assert!(exchange.cancel_session("mm-1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn cancel_removes_resting_order() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let (order, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+
+        let cancelled = exchange.cancel_order("BTC-USD", order.id, 0).unwrap().unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert!(exchange.cancel_order("BTC-USD", order.id, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn client_order_ids_cancel_within_their_owners_namespace() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let with_client = |user_id: u64, price: Decimal| NewOrder {
+            client_order_id: Some("algo-7".to_string()),
+            ..limit("BTC-USD", user_id, Side::Buy, price, dec!(1))
+        };
+        // Two users reuse the same client id independently.
+        exchange.place_order(with_client(1, dec!(99))).unwrap();
+        let (other, _) = exchange.place_order(with_client(2, dec!(98))).unwrap();
+
+        // Reusing a live client id within one user's namespace is rejected.
+        let err = exchange.place_order(with_client(1, dec!(97))).unwrap_err();
+        assert_eq!(
+            err.reject_reason(),
+            Some(RejectReason::DuplicateClientOrderId)
+        );
+
+        let cancelled = exchange
+            .cancel_order_by_client_id("BTC-USD", "algo-7", 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        // User 2's order under the same client id is untouched...
+        assert!(exchange
+            .engine("BTC-USD")
+            .unwrap()
+            .orderbook
+            .get_order(other.id)
+            .is_some());
+        // ...and user 1 can now reuse the id.
+        exchange.place_order(with_client(1, dec!(97))).unwrap();
+    }
+
+    #[test]
+    fn one_matching_pass_journals_trades_then_cancels_in_sequence_order() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                last_look_window_ns: i64::MAX,
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+
+        // A protected quote that will decline, and a firm maker behind it.
+        exchange
+            .place_order(NewOrder {
+                last_look: true,
+                ..limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1))
+            })
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Sell, dec!(100), dec!(3)))
+            .unwrap();
+        // The taker skips the declining quote, fills 1 against the firm
+        // maker and rests the remainder.
+        let (taker, trades) = exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(taker.status, OrderStatus::Filled);
+
+        // Canonical order: the command, then trades in fill order, then the
+        // cancels the pass produced — with strictly increasing sequences in
+        // both sequence spaces.
+        let entries = exchange.wal_entries_from(3).unwrap();
+        let kinds: Vec<&str> = entries
+            .iter()
+            .map(|e| match &e.operation {
+                WalOperation::PlaceOrder(_) => "place",
+                WalOperation::TradeExecuted(_) => "trade",
+                WalOperation::CancelOrder { .. } => "cancel",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, vec!["place", "trade", "cancel"]);
+        for pair in entries.windows(2) {
+            assert!(pair[1].sequence > pair[0].sequence);
+            assert!(pair[1].market_sequence > pair[0].market_sequence);
+        }
+    }
+
+    #[test]
+    fn stale_expected_sequence_is_rejected_until_the_client_re_reads() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        let seen = exchange.engine("BTC-USD").unwrap().book_stream_sequence();
+        // The book moves on before the client acts.
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+
+        let stale = NewOrder {
+            expected_sequence: Some(seen),
+            ..limit("BTC-USD", 3, Side::Sell, dec!(99), dec!(1))
+        };
+        let err = exchange.place_order(stale).unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::StaleSequence));
+
+        // Re-reading the current sequence makes the same order valid.
+        let current = exchange.engine("BTC-USD").unwrap().book_stream_sequence();
+        let fresh = NewOrder {
+            expected_sequence: Some(current),
+            ..limit("BTC-USD", 3, Side::Sell, dec!(99), dec!(1))
+        };
+        let (_, trades) = exchange.place_order(fresh).unwrap();
+        assert_eq!(trades.len(), 1);
+    }
+
+    #[test]
+    fn crossed_book_halts_order_entry_under_the_default_policy() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        // Inject corruption: a bid through the ask, bypassing matching.
+        let mut crossed = crate::engine::tests::limit(99, Side::Buy, dec!(101), dec!(1));
+        crossed.market_id = "BTC-USD".into();
+        exchange
+            .engine_mut("BTC-USD")
+            .unwrap()
+            .orderbook
+            .add_order(crossed);
+
+        let err = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::Halted));
+        // No phantom trades were produced and the book is untouched.
+        assert!(exchange.engine("BTC-USD").unwrap().book_is_crossed());
+    }
+
+    #[test]
+    fn crossed_book_is_repaired_first_under_the_uncross_policy() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(EngineConfig {
+            crossed_book_policy: CrossedBookPolicy::Uncross,
+            ..test_config(&dir)
+        })
+        .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        let mut crossed = crate::engine::tests::limit(99, Side::Buy, dec!(101), dec!(1));
+        crossed.market_id = "BTC-USD".into();
+        exchange
+            .engine_mut("BTC-USD")
+            .unwrap()
+            .orderbook
+            .add_order(crossed);
+
+        // The next placement first repairs the book, then matches normally.
+        let (order, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(order.status, OrderStatus::New);
+        assert!(!exchange.engine("BTC-USD").unwrap().book_is_crossed());
+    }
+
+    #[test]
+    fn prewarm_creates_engines_for_configured_markets_only_once() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert("BTC-USD".to_string(), MarketConfig::default());
+        markets.insert("ETH-USD".to_string(), MarketConfig::default());
+        exchange.set_market_configs(markets).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+
+        // Only the market without an engine yet is warmed.
+        assert_eq!(exchange.prewarm(), 1);
+        assert!(exchange.engine("ETH-USD").is_some());
+        assert_eq!(exchange.prewarm(), 0);
+    }
+
+    #[test]
+    fn hostile_market_ids_are_rejected_before_touching_state() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+
+        for market_id in ["BTC/USD", "../../etc", "BTC_USD", "", &"X".repeat(65)] {
+            let err = exchange
+                .place_order(limit(market_id, 1, Side::Buy, dec!(100), dec!(1)))
+                .unwrap_err();
+            assert_eq!(
+                err.reject_reason(),
+                Some(RejectReason::InvalidMarketId),
+                "{market_id:?}"
+            );
+            assert!(exchange.engine(market_id).is_none());
+        }
+        // A config map carrying a bad id is rejected wholesale.
+        let mut markets = HashMap::new();
+        markets.insert("BTC/USD".to_string(), MarketConfig::default());
+        assert!(exchange.set_market_configs(markets).is_err());
+
+        // The ordinary form still works.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+    }
+
+    #[test]
+    fn notional_cap_throttles_a_user_until_they_free_headroom() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                max_user_notional: dec!(250),
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+
+        // Two resting orders bring the user to 200 of the 250 cap.
+        let (first, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+
+        let err = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(98), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::NotionalCap));
+        // Another user is unaffected by this user's exposure.
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+
+        // Cancelling frees headroom and the rejected order now fits.
+        exchange.cancel_order("BTC-USD", first.id, 0).unwrap().unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+    }
+
+    #[test]
+    fn snapshot_all_writes_one_file_per_market_at_one_boundary() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        for market_id in ["BTC-USD", "ETH-USD", "SOL-USD"] {
+            exchange
+                .place_order(limit(market_id, 1, Side::Buy, dec!(99), dec!(1)))
+                .unwrap();
+        }
+
+        let manifest = exchange.snapshot_all().unwrap();
+        assert_eq!(manifest.len(), 3);
+        let mut markets: Vec<&str> = manifest.iter().map(|(m, _, _)| m.as_str()).collect();
+        markets.sort();
+        assert_eq!(markets, ["BTC-USD", "ETH-USD", "SOL-USD"]);
+        for (_, path, sequence) in &manifest {
+            assert!(path.exists());
+            // Every entry shares the same cross-market boundary.
+            assert_eq!(*sequence, manifest[0].2);
+        }
+    }
+
+    #[test]
+    fn desynced_sequence_counters_trip_the_strict_guard() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(EngineConfig {
+            strict_sequence_checks: true,
+            ..test_config(&dir)
+        })
+        .unwrap();
+
+        // Normal flow keeps the mirror and the WAL in lockstep.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+        assert!(!exchange.is_halted());
+
+        // Artificially desync the mirror, as a double-count bug would.
+        *exchange.expected_wal_sequences.get_mut("BTC-USD").unwrap() -= 1;
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(97), dec!(1)))
+            .unwrap();
+        assert!(exchange.is_halted());
+
+        // While halted, subsequent order entry is rejected.
+        let err = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(96), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::Halted));
+    }
+
+    #[test]
+    fn fee_schedule_below_net_floor_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                maker_fee_bps: dec!(-3),
+                taker_fee_bps: dec!(2),
+                ..MarketConfig::default()
+            },
+        );
+        // Net -1 bps is below the default floor of zero.
+        let err = exchange.set_market_configs(markets).unwrap_err();
+        assert!(matches!(err, EngineError::Config(_)));
+
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                maker_fee_bps: dec!(-1),
+                taker_fee_bps: dec!(2),
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+        assert_eq!(exchange.market_config("BTC-USD").net_fee_bps(), dec!(1));
+    }
+
+    #[test]
+    fn consecutive_wal_failures_halt_order_entry_until_a_write_succeeds() {
+        let dir = TempDir::new().unwrap();
+        let mut config = test_config(&dir);
+        config.wal_failure_halt_threshold = 2;
+        let mut exchange = Exchange::new(config).unwrap();
+        let (resting, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+
+        exchange.wal_mut().fail_appends = true;
+        for _ in 0..2 {
+            exchange
+                .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+                .unwrap_err();
+        }
+        assert!(exchange.is_halted());
+
+        // While halted, order entry is rejected before touching the WAL.
+        let err = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::Halted));
+
+        // A successful write (here a cancel) clears the halt.
+        exchange.wal_mut().fail_appends = false;
+        exchange.cancel_order("BTC-USD", resting.id, 0).unwrap();
+        assert!(!exchange.is_halted());
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+    }
+
+    #[test]
+    fn a_torn_trade_batch_halts_order_entry_immediately() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+
+        // The command batch goes through; the trade batch after matching
+        // fails, so the book holds a fill the WAL knows nothing about.
+        exchange.wal_mut().fail_appends_after = Some(1);
+        let err = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::Wal(_)));
+
+        // Torn state halts at once — the failure circuit's threshold
+        // (default 3) never gets a say.
+        assert!(exchange.is_halted());
+        let err = exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(98), dec!(1)))
+            .unwrap_err();
+        assert!(matches!(err, EngineError::Halted));
+    }
+
+    #[test]
+    fn fast_acked_orders_become_durable_at_the_next_flush() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut order = limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1));
+        order.ack_mode = AckMode::Fast;
+        exchange.place_order(order).unwrap();
+        // The placement was acknowledged without a sync; flushing makes it
+        // durable, and a fresh recovery sees it.
+        exchange.flush_wal().unwrap();
+        drop(exchange);
+
+        let mut recovered = Exchange::new(test_config(&dir)).unwrap();
+        recovered.recover().unwrap();
+        let engine = recovered.engine("BTC-USD").unwrap();
+        assert_eq!(engine.orderbook.best_bid().unwrap().price, dec!(99));
+    }
+
+    #[test]
+    fn pathological_orders_fail_typed_instead_of_panicking() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let err = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(0), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::InvalidPrice));
+        let err = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(-1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::InvalidQuantity));
+    }
+
+    #[test]
+    fn far_touch_orders_beyond_the_level_cap_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                max_price_levels: 2,
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+
+        // A third bid level is refused with a typed reason...
+        let err = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(97), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::LevelCap));
+        // ...while joining an existing level still works.
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        // The cap is per side: the ask side is unaffected.
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Sell, dec!(105), dec!(1)))
+            .unwrap();
+    }
+
+    #[test]
+    fn orders_past_the_per_level_count_cap_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                max_orders_per_level: 2,
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+
+        // A third order at the full level is refused with a typed reason...
+        let err = exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(99), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::LevelOrderCap));
+        // ...while a fresh level on the same side still works.
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+        // Crossing the full level is matching, not joining it.
+        let (order, trades) = exchange
+            .place_order(limit("BTC-USD", 4, Side::Sell, dec!(99), dec!(3)))
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+    }
+
+    #[test]
+    fn collar_rejections_land_in_the_deviation_histogram() {
+        struct Fixed(Decimal, Decimal);
+        impl crate::engine::ReferencePriceProvider for Fixed {
+            fn reference(&self, _market_id: &str) -> Option<(Decimal, Decimal)> {
+                Some((self.0, self.1))
+            }
+        }
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                collar_bps: dec!(100),
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+        // Before a reference feed exists the collar cannot be evaluated.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        exchange
+            .engine_mut("BTC-USD")
+            .unwrap()
+            .set_reference_provider(Box::new(Fixed(dec!(99.5), dec!(100.5))));
+
+        // 100 bps off a mid of 100 is exactly on the collar: allowed.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        // 200 bps and 5000 bps are over it, in different buckets.
+        let err = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(102), dec!(1)))
+            .unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::PriceCollar));
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(150), dec!(1)))
+            .unwrap_err();
+
+        let stats = exchange.collar_stats("BTC-USD");
+        assert_eq!(stats.rejections, 2);
+        // 200 bps falls in the (100, 250] bucket; 5000 bps in the unbounded
+        // tail.
+        assert_eq!(stats.deviation_buckets[2], 1);
+        assert_eq!(stats.deviation_buckets[COLLAR_BUCKET_BPS.len()], 1);
+        // Other markets are untouched.
+        assert_eq!(exchange.collar_stats("ETH-USD"), CollarStats::default());
+    }
+
+    #[test]
+    fn every_order_decision_reaches_the_audit_log() {
+        use std::sync::{Arc, Mutex};
+        #[derive(Clone)]
+        struct Capture(Arc<Mutex<Vec<AuditRecord>>>);
+        impl AuditSink for Capture {
+            fn record(&mut self, record: &AuditRecord) {
+                self.0.lock().unwrap().push(record.clone());
+            }
+        }
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange.set_audit_sink(Box::new(Capture(Arc::clone(&records))));
+
+        let (maker, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2)))
+            .unwrap();
+        let (_, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(0), dec!(1)))
+            .unwrap_err();
+        exchange.cancel_order("BTC-USD", maker.id, 1).unwrap();
+        // A cancel by someone else is a rejected decision too.
+        exchange
+            .place_order(limit("BTC-USD", 4, Side::Sell, dec!(105), dec!(1)))
+            .unwrap();
+        exchange.cancel_order("BTC-USD", 3, 9).unwrap_err();
+
+        let records = records.lock().unwrap();
+        let actions: Vec<(&u64, String)> = records
+            .iter()
+            .map(|r| {
+                let name = match &r.action {
+                    AuditAction::OrderAccepted { .. } => "accepted",
+                    AuditAction::OrderRejected { reason, .. } => reason.as_str(),
+                    AuditAction::OrderMatched { .. } => "matched",
+                    AuditAction::OrderCancelled { .. } => "cancelled",
+                    AuditAction::CancelRejected { reason, .. } => reason.as_str(),
+                };
+                (&r.actor, name.to_string())
+            })
+            .collect();
+        let expected: Vec<(&u64, String)> = [
+            (&1u64, "accepted"),
+            (&2, "accepted"),
+            (&2, "matched"),
+            (&3, "INVALID_PRICE"),
+            (&1, "cancelled"),
+            (&4, "accepted"),
+            (&9, "NOT_OWNER"),
+        ]
+        .iter()
+        .map(|(a, n)| (*a, n.to_string()))
+        .collect();
+        assert_eq!(actions, expected);
+        // The matched record identifies the trade it describes.
+        assert!(records.iter().any(|r| matches!(
+            &r.action,
+            AuditAction::OrderMatched { trade_id, .. } if *trade_id == trades[0].id
+        )));
+    }
+
+    #[test]
+    fn delayed_markets_journal_immediately_but_print_late() {
+        let second = 1_000_000_000i64;
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                trade_report_delay_ns: 10 * second,
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        let mut prints = exchange.engine("BTC-USD").unwrap().subscribe_trades();
+        let (_, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+
+        // Matching and journaling are unaffected by the delay...
+        assert!(exchange.wal_entries_from(1).unwrap().iter().any(
+            |e| matches!(&e.operation, WalOperation::TradeExecuted(t) if t.id == trades[0].id)
+        ));
+        // ...but the tape stays silent, live and backfill alike.
+        assert!(prints.try_recv().is_err());
+        assert!(exchange.engine("BTC-USD").unwrap().recent_trades.is_empty());
+        exchange.flush_trade_prints(now_ns());
+        assert!(prints.try_recv().is_err());
+
+        // Once the delay elapses the print is released with its fills.
+        exchange.flush_trade_prints(now_ns() + 11 * second);
+        let print = prints.try_recv().unwrap();
+        assert_eq!(print.trade.id, trades[0].id);
+        assert_eq!(
+            exchange.engine("BTC-USD").unwrap().recent_trades.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn aggregated_mode_journals_a_sweep_as_one_record() {
+        let dir = TempDir::new().unwrap();
+        let mut config = test_config(&dir);
+        config.wal_aggregate_trades = true;
+        let mut exchange = Exchange::new(config.clone()).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(101), dec!(2)))
+            .unwrap();
+        let (taker, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(101), dec!(3)))
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+
+        // The two-level sweep lands as one record whose constituent fills
+        // sum to the taker quantity; no per-trade records are written.
+        let entries = exchange.wal_entries_from(1).unwrap();
+        assert!(!entries
+            .iter()
+            .any(|e| matches!(e.operation, WalOperation::TradeExecuted(_))));
+        let matched: Vec<_> = entries
+            .iter()
+            .filter_map(|e| match &e.operation {
+                WalOperation::OrderMatched {
+                    taker_order_id,
+                    trades,
+                    ..
+                } => Some((*taker_order_id, trades)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].0, taker.id);
+        let filled: Decimal = matched[0].1.iter().map(|t| t.quantity).sum();
+        assert_eq!(filled, taker.quantity);
+        let digest = exchange.engine("BTC-USD").unwrap().orderbook.digest();
+        drop(exchange);
+
+        // Replay skips the aggregated record and regenerates the same book.
+        let mut recovered = Exchange::new(config).unwrap();
+        recovered.recover().unwrap();
+        assert_eq!(
+            recovered.engine("BTC-USD").unwrap().orderbook.digest(),
+            digest
+        );
+    }
+
+    #[test]
+    fn recovery_reconstructs_the_book_after_a_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let mut config = test_config(&dir);
+        // Tiny segments so the checkpoint actually has segments to drop.
+        config.wal_segment_max_bytes = 1;
+        let mut exchange = Exchange::new(config.clone()).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(2)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Sell, dec!(101), dec!(1)))
+            .unwrap();
+
+        let sequence = exchange.checkpoint().unwrap().unwrap();
+        assert_eq!(sequence, 2);
+        // Post-checkpoint traffic lands in the remaining WAL tail.
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+        let digest = exchange.engine("BTC-USD").unwrap().orderbook.digest();
+        drop(exchange);
+
+        let mut recovered = Exchange::new(config).unwrap();
+        recovered.recover().unwrap();
+        assert_eq!(
+            recovered.engine("BTC-USD").unwrap().orderbook.digest(),
+            digest
+        );
+    }
+
+    #[test]
+    fn recovery_over_budget_falls_back_to_the_latest_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let mut primary = Exchange::new(test_config(&dir)).unwrap();
+        primary
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        primary.checkpoint().unwrap().unwrap();
+        // Tail traffic after the checkpoint, slow to replay by construction.
+        primary
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+        primary
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(98), dec!(1)))
+            .unwrap();
+        drop(primary);
+
+        let mut config = test_config(&dir);
+        config.recovery_timeout_ms = 10;
+        let mut recovered = Exchange::new(config).unwrap();
+        recovered.recovery_replay_delay = Some(std::time::Duration::from_millis(25));
+        recovered.recover().unwrap();
+
+        // The first tail entry alone blew the budget, so the market is
+        // served from the snapshot: the checkpointed order is there, the
+        // tail orders are not.
+        let book = &recovered.engine("BTC-USD").unwrap().orderbook;
+        assert_eq!(book.orders.len(), 1);
+        assert!(book.get_order(1).is_some());
+
+        // Ids still resume above the skipped tail.
+        let (order, _) = recovered
+            .place_order(limit("BTC-USD", 4, Side::Buy, dec!(97), dec!(1)))
+            .unwrap();
+        assert_eq!(order.id, 4);
+    }
+
+    #[test]
+    fn byte_trigger_fires_once_the_write_volume_crosses_the_threshold() {
+        let dir = TempDir::new().unwrap();
+        let mut config = test_config(&dir);
+        config.checkpoint_wal_bytes = 64;
+        let mut exchange = Exchange::new(config).unwrap();
+        assert!(!exchange.checkpoint_due_by_bytes());
+
+        // One placement journals well past 64 bytes.
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(99), dec!(2)))
+            .unwrap();
+        assert!(exchange.checkpoint_due_by_bytes());
+
+        // Checkpointing produces a snapshot and re-arms the trigger.
+        exchange.checkpoint().unwrap().unwrap();
+        assert!(!exchange.checkpoint_due_by_bytes());
+        assert!(exchange
+            .snapshots
+            .load_latest_all()
+            .unwrap()
+            .iter()
+            .any(|s| s.market_id == "BTC-USD"));
+    }
+
+    #[test]
+    fn only_the_owner_may_cancel_an_order() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let (order, _) = exchange
+            .place_order(limit("BTC-USD", 7, Side::Buy, dec!(99), dec!(1)))
+            .unwrap();
+
+        let err = exchange.cancel_order("BTC-USD", order.id, 8).unwrap_err();
+        assert!(matches!(err, EngineError::PermissionDenied(_)));
+        // The failed attempt left the order resting; the owner's cancel works.
+        let cancelled = exchange
+            .cancel_order("BTC-USD", order.id, 7)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn fully_consumed_maker_is_journaled_filled_exactly_once() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let (maker, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(2)))
+            .unwrap();
+        // Two takers: the first leaves the maker partially filled (no fill
+        // record), the second consumes it.
+        exchange
+            .place_order(limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Buy, dec!(100), dec!(1)))
+            .unwrap();
+
+        let fills: Vec<_> = exchange
+            .wal_mut()
+            .read_from(1)
+            .unwrap()
+            .into_iter()
+            .filter_map(|e| match e.operation {
+                WalOperation::OrderFilled {
+                    order_id,
+                    filled_quantity,
+                    ..
+                } => Some((order_id, filled_quantity)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills, vec![(maker.id, dec!(2))]);
+    }
+
+    #[test]
+    fn hidden_ioc_trades_hit_the_wal_but_not_the_tape() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange
+            .place_order(limit("BTC-USD", 1, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        let tape = exchange.engine("BTC-USD").unwrap().subscribe_trades();
+
+        let mut hidden = limit("BTC-USD", 2, Side::Buy, dec!(100), dec!(1));
+        hidden.time_in_force = TimeInForce::Ioc;
+        hidden.public = false;
+        let (_, trades) = exchange.place_order(hidden).unwrap();
+        assert_eq!(trades.len(), 1);
+
+        // Journaled for audit and replay...
+        let journaled = exchange.wal_mut().read_from(1).unwrap();
+        assert!(journaled
+            .iter()
+            .any(|e| matches!(&e.operation, WalOperation::TradeExecuted(t) if t.id == trades[0].id)));
+        // ...but never broadcast or backfilled.
+        let mut tape = tape;
+        assert!(tape.try_recv().is_err());
+        assert!(exchange.engine("BTC-USD").unwrap().recent_trades.is_empty());
+    }
+
+    #[test]
+    fn a_new_quote_replaces_the_users_previous_bid_and_ask() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+
+        let quote = |bid: Decimal, ask: Decimal| NewQuote {
+            market_id: "BTC-USD".to_string(),
+            user_id: 1,
+            bid_price: bid,
+            bid_quantity: dec!(2),
+            ask_price: ask,
+            ask_quantity: dec!(2),
+        };
+        let ((bid1, _), (ask1, _)) = exchange.place_quote(quote(dec!(99), dec!(101))).unwrap();
+
+        // The replacement lands both sides and removes both old orders in
+        // the same call: exactly one bid and one ask rest afterwards, at
+        // the new levels.
+        let ((bid2, _), (ask2, _)) = exchange.place_quote(quote(dec!(98), dec!(102))).unwrap();
+        assert!(bid2.id > bid1.id && ask2.id > ask1.id);
+        let book = &exchange.engine("BTC-USD").unwrap().orderbook;
+        assert_eq!(book.best_bid().unwrap().price, dec!(98));
+        assert_eq!(book.best_ask().unwrap().price, dec!(102));
+        assert!(book.get_order(bid1.id).is_none());
+        assert!(book.get_order(ask1.id).is_none());
+        assert!(book.get_order(bid2.id).is_some());
+        assert!(book.get_order(ask2.id).is_some());
+
+        // A crossed quote is rejected whole; the previous quote's sides
+        // were already replaced above and stay untouched.
+        let err = exchange.place_quote(quote(dec!(103), dec!(102))).unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::InvalidPrice));
+        let book = &exchange.engine("BTC-USD").unwrap().orderbook;
+        assert!(book.get_order(bid2.id).is_some());
+        assert!(book.get_order(ask2.id).is_some());
+    }
+
+    #[test]
+    fn cancels_inside_the_minimum_resting_time_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        let mut markets = HashMap::new();
+        markets.insert(
+            "BTC-USD".to_string(),
+            MarketConfig {
+                min_resting_time_ns: 50_000_000,
+                ..MarketConfig::default()
+            },
+        );
+        exchange.set_market_configs(markets).unwrap();
+
+        let (order, _) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(100), dec!(2)))
+            .unwrap();
+        let err = exchange.cancel_order("BTC-USD", order.id, 1).unwrap_err();
+        assert_eq!(err.reject_reason(), Some(RejectReason::MinRestingTime));
+
+        // The order keeps resting and keeps filling inside the window:
+        // only the cancel is held back.
+        let (_, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        let cancelled = exchange.cancel_order("BTC-USD", order.id, 1).unwrap();
+        assert_eq!(cancelled.unwrap().id, order.id);
+    }
+
+    #[test]
+    fn pre_open_orders_accumulate_until_the_opening_uncross() {
+        let dir = TempDir::new().unwrap();
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange.set_phase("BTC-USD", MarketPhase::PreOpen).unwrap();
+        let mut phases = exchange.engine("BTC-USD").unwrap().subscribe_phases();
+
+        // Crossed flow accumulates without matching during the call.
+        let (bid, trades) = exchange
+            .place_order(limit("BTC-USD", 1, Side::Buy, dec!(101), dec!(1)))
+            .unwrap();
+        assert!(trades.is_empty());
+        let (ask, trades) = exchange
+            .place_order(limit("BTC-USD", 2, Side::Sell, dec!(100), dec!(1)))
+            .unwrap();
+        assert!(trades.is_empty());
+        assert_eq!(bid.status, OrderStatus::New);
+        assert_eq!(ask.status, OrderStatus::New);
+        assert!(exchange.engine("BTC-USD").unwrap().book_is_crossed());
+
+        // The opening uncross matches the accumulated cross at the earlier
+        // order's price, and the transition is broadcast.
+        let trades = exchange.set_phase("BTC-USD", MarketPhase::Open).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(101));
+        assert!(!exchange.engine("BTC-USD").unwrap().book_is_crossed());
+        assert_eq!(phases.try_recv().unwrap().phase, MarketPhase::Open);
+
+        // Matching is continuous from the open onwards.
+        exchange
+            .place_order(limit("BTC-USD", 3, Side::Sell, dec!(102), dec!(1)))
+            .unwrap();
+        let (taker, trades) = exchange
+            .place_order(limit("BTC-USD", 4, Side::Buy, dec!(102), dec!(1)))
+            .unwrap();
+        assert_eq!(taker.status, OrderStatus::Filled);
+        assert_eq!(trades.len(), 1);
+
+        // Recovery lands back in the journaled phase: a restart mid-call
+        // keeps accumulating instead of matching.
+        exchange.set_phase("BTC-USD", MarketPhase::Close).unwrap();
+        drop(exchange);
+        let mut exchange = Exchange::new(test_config(&dir)).unwrap();
+        exchange.recover().unwrap();
+        assert_eq!(
+            exchange.engine("BTC-USD").unwrap().phase(),
+            MarketPhase::Close
+        );
+    }
+}
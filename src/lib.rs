@@ -0,0 +1,23 @@
+//! xmarket order-matching engine.
+//!
+//! The engine keeps one [`engine::MatchingEngine`] per market, journals every
+//! accepted command to a write-ahead log ([`wal::WAL`]) and periodically
+//! persists per-market [`snapshot::Snapshot`]s so recovery only replays the
+//! WAL tail. A tonic gRPC front-end ([`service`]) exposes order entry and
+//! market data.
+
+pub mod audit;
+pub mod config;
+pub mod engine;
+pub mod error;
+pub mod exchange;
+pub mod mirror;
+pub mod orderbook;
+pub mod price_scale;
+pub mod pricing;
+pub mod proto;
+pub mod replication;
+pub mod service;
+pub mod snapshot;
+pub mod types;
+pub mod wal;
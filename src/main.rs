@@ -0,0 +1,199 @@
+//! Engine server binary: recovers state, then serves gRPC.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tonic::transport::Server;
+use tracing::{error, info};
+use xmarket_engine::config::EngineConfig;
+use xmarket_engine::exchange::Exchange;
+use xmarket_engine::proto::admin_server::AdminServer;
+use xmarket_engine::proto::market_data_server::MarketDataServer;
+use xmarket_engine::proto::order_entry_server::OrderEntryServer;
+use xmarket_engine::service::{
+    admin_auth_interceptor, AdminService, MarketDataService, OrderEntryService, PauseGate,
+    RequestLimiter, SharedExchange,
+};
+use xmarket_engine::types::now_ns;
+
+fn spawn_expiry_reaper(exchange: SharedExchange, interval_ms: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+        loop {
+            ticker.tick().await;
+            let (result, checkpoint_due) = {
+                let mut exchange = exchange.lock().unwrap_or_else(|p| p.into_inner());
+                // Fast-acked WAL writes ride this tick to durability.
+                if let Err(e) = exchange.flush_wal() {
+                    error!(error = %e, "WAL flush failed");
+                }
+                // Dark-pool-style markets release due trade prints here.
+                exchange.flush_trade_prints(now_ns());
+                (exchange.reap_expired(now_ns()), exchange.checkpoint_due_by_bytes())
+            };
+            match result {
+                Ok(expired) if !expired.is_empty() => {
+                    info!(count = expired.len(), "expired GTD orders");
+                }
+                Ok(_) => {}
+                Err(e) => error!(error = %e, "expiry reap failed"),
+            }
+            // Byte-based checkpoint trigger: fires when write volume since
+            // the last checkpoint crosses the configured threshold, however
+            // fast that happens.
+            if checkpoint_due {
+                run_checkpoint(&exchange);
+            }
+        }
+    });
+}
+
+fn spawn_checkpoint_timer(exchange: SharedExchange, interval_secs: u64) {
+    if interval_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        ticker.tick().await; // first tick fires immediately; skip it
+        loop {
+            ticker.tick().await;
+            run_checkpoint(&exchange);
+        }
+    });
+}
+
+fn spawn_dust_sweeper(exchange: SharedExchange, interval_secs: u64) {
+    if interval_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        ticker.tick().await; // first tick fires immediately; skip it
+        loop {
+            ticker.tick().await;
+            let result = {
+                let mut exchange = exchange.lock().unwrap_or_else(|p| p.into_inner());
+                exchange.sweep_dust()
+            };
+            match result {
+                Ok(swept) if !swept.is_empty() => {
+                    info!(count = swept.len(), "swept sub-lot dust orders");
+                }
+                Ok(_) => {}
+                Err(e) => error!(error = %e, "dust sweep failed"),
+            }
+        }
+    });
+}
+
+fn run_checkpoint(exchange: &SharedExchange) {
+    let result = {
+        let mut exchange = exchange.lock().unwrap_or_else(|p| p.into_inner());
+        exchange.checkpoint()
+    };
+    match result {
+        Ok(Some(sequence)) => info!(sequence, "checkpoint complete"),
+        Ok(None) => {}
+        Err(e) => error!(error = %e, "checkpoint failed; WAL left untruncated"),
+    }
+}
+
+/// Re-reads the markets file on SIGHUP and swaps it into the exchange.
+fn spawn_markets_reload(exchange: SharedExchange, markets_file: std::path::PathBuf) {
+    tokio::spawn(async move {
+        let Ok(mut hangups) =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        else {
+            error!("failed to install SIGHUP handler; markets hot-reload disabled");
+            return;
+        };
+        while hangups.recv().await.is_some() {
+            match xmarket_engine::config::load_market_configs(&markets_file) {
+                Ok(markets) => {
+                    let count = markets.len();
+                    let applied = exchange
+                        .lock()
+                        .unwrap_or_else(|p| p.into_inner())
+                        .set_market_configs(markets);
+                    match applied {
+                        Ok(()) => info!(count, "reloaded market configs"),
+                        Err(e) => error!(error = %e, "markets reload rejected; keeping previous configs"),
+                    }
+                }
+                Err(e) => error!(error = %e, "markets reload failed; keeping previous configs"),
+            }
+        }
+    });
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    let config = EngineConfig::from_env();
+    let addr = config.listen_addr.parse()?;
+
+    let mut exchange = Exchange::new(config.clone())?;
+    exchange.recover()?;
+    info!(markets = exchange.market_ids().len(), "recovery complete");
+    if config.prewarm_markets {
+        let warmed = exchange.prewarm();
+        info!(warmed, "prewarmed configured markets");
+    }
+
+    let exchange: SharedExchange = Arc::new(Mutex::new(exchange));
+    spawn_expiry_reaper(Arc::clone(&exchange), config.reap_interval_ms);
+    spawn_markets_reload(Arc::clone(&exchange), config.markets_file.clone());
+    spawn_checkpoint_timer(Arc::clone(&exchange), config.checkpoint_interval_secs);
+    spawn_dust_sweeper(Arc::clone(&exchange), config.dust_sweep_interval_secs);
+
+    let limiter = RequestLimiter::new(config.max_concurrent_requests);
+    let pause = PauseGate::default();
+
+    let mut builder = Server::builder()
+        .http2_keepalive_interval(
+            (config.http2_keepalive_interval_secs > 0)
+                .then(|| Duration::from_secs(config.http2_keepalive_interval_secs)),
+        )
+        .http2_keepalive_timeout(Some(Duration::from_secs(
+            config.http2_keepalive_timeout_secs,
+        )));
+    if config.max_concurrent_streams > 0 {
+        builder = builder.max_concurrent_streams(config.max_concurrent_streams);
+    }
+
+    info!(%addr, "engine listening");
+    builder
+        .add_service(OrderEntryServer::new(
+            OrderEntryService::with_limiter(Arc::clone(&exchange), limiter.clone())
+                .with_pause_gate(pause.clone()),
+        ))
+        .add_service(MarketDataServer::new(MarketDataService::with_limiter(
+            Arc::clone(&exchange),
+            limiter.clone(),
+        )))
+        .add_service(AdminServer::with_interceptor(
+            AdminService::with_limiter(Arc::clone(&exchange), limiter).with_pause_gate(pause),
+            admin_auth_interceptor(config.admin_token.clone()),
+        ))
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("shutting down");
+        })
+        .await?;
+    // A final checkpoint on graceful shutdown leaves the fastest possible
+    // recovery: snapshots at the head and a minimal WAL tail. Flush first so
+    // fast-acked appends are on disk before anything is truncated.
+    {
+        let mut exchange = exchange.lock().unwrap_or_else(|p| p.into_inner());
+        if let Err(e) = exchange.flush_wal() {
+            error!(error = %e, "final WAL flush failed");
+        }
+    }
+    run_checkpoint(&exchange);
+    Ok(())
+}
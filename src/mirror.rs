@@ -0,0 +1,139 @@
+//! Cold-start of an [`Orderbook`] from an external exchange feed.
+//!
+//! For mirroring another venue, a [`BookMirror`] ingests a full depth
+//! snapshot and subsequent level deltas (the shape most exchange feeds
+//! provide) and maintains an internal book of synthetic resting orders, one
+//! per price level. The result can be fed to local matching or analytics
+//! against live external liquidity. Synthetic orders all belong to
+//! [`SYNTHETIC_USER`] so they are distinguishable from real flow.
+
+use crate::orderbook::Orderbook;
+use crate::types::{now_ns, Order, OrderStatus, OrderType, Side, TimeInForce};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Owner of all mirrored liquidity.
+pub const SYNTHETIC_USER: u64 = 0;
+
+/// Full external book snapshot: `(price, quantity)` per level, in any order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalSnapshot {
+    pub market_id: String,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+/// A level update from the external feed: `quantity` replaces the level's
+/// total; zero deletes the level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalDelta {
+    pub side: Side,
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+/// Maintains a book of synthetic orders mirroring an external venue.
+pub struct BookMirror {
+    book: Orderbook,
+    next_synthetic_id: u64,
+}
+
+impl BookMirror {
+    /// Builds the mirror from a full snapshot, one synthetic order per level.
+    pub fn from_snapshot(snapshot: &ExternalSnapshot) -> Self {
+        let mut mirror = BookMirror {
+            book: Orderbook::new(snapshot.market_id.clone()),
+            next_synthetic_id: 1,
+        };
+        for &(price, quantity) in &snapshot.bids {
+            mirror.set_level(Side::Buy, price, quantity);
+        }
+        for &(price, quantity) in &snapshot.asks {
+            mirror.set_level(Side::Sell, price, quantity);
+        }
+        mirror
+    }
+
+    /// Applies one feed delta: replaces the level quantity, creating or
+    /// deleting the level as needed.
+    pub fn apply_delta(&mut self, delta: &ExternalDelta) {
+        self.set_level(delta.side, delta.price, delta.quantity);
+    }
+
+    pub fn book(&self) -> &Orderbook {
+        &self.book
+    }
+
+    fn set_level(&mut self, side: Side, price: Decimal, quantity: Decimal) {
+        // One synthetic order per level; replacing it wholesale keeps the
+        // mirror simple since external feeds carry no queue information.
+        if let Some(existing) = self.book.orders_at(side, price).first() {
+            self.book.remove_order(existing.id);
+        }
+        if quantity <= Decimal::ZERO {
+            return;
+        }
+        let id = self.next_synthetic_id;
+        self.next_synthetic_id += 1;
+        self.book.add_order(Order {
+            id,
+            user_id: SYNTHETIC_USER,
+            market_id: self.book.market_id.clone(),
+            side,
+            order_type: OrderType::Limit,
+            price,
+            quantity,
+            remaining_quantity: quantity,
+            status: OrderStatus::New,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: None,
+            session_id: None,
+            sequence: id,
+            timestamp: now_ns(),
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn snapshot_plus_deltas_reconstructs_the_external_book() {
+        let snapshot = ExternalSnapshot {
+            market_id: "BTC-USD".into(),
+            bids: vec![(dec!(100), dec!(2)), (dec!(99), dec!(1))],
+            asks: vec![(dec!(101), dec!(3)), (dec!(102), dec!(4))],
+        };
+        let mut mirror = BookMirror::from_snapshot(&snapshot);
+        assert_eq!(mirror.book().best_bid().unwrap().price, dec!(100));
+        assert_eq!(mirror.book().best_ask().unwrap().price, dec!(101));
+
+        // Quantity update on the best bid.
+        mirror.apply_delta(&ExternalDelta {
+            side: Side::Buy,
+            price: dec!(100),
+            quantity: dec!(5),
+        });
+        // Deletion of the best ask.
+        mirror.apply_delta(&ExternalDelta {
+            side: Side::Sell,
+            price: dec!(101),
+            quantity: dec!(0),
+        });
+
+        let bid = mirror.book().best_bid().unwrap();
+        assert_eq!((bid.price, bid.total_quantity()), (dec!(100), dec!(5)));
+        let ask = mirror.book().best_ask().unwrap();
+        assert_eq!((ask.price, ask.total_quantity()), (dec!(102), dec!(4)));
+    }
+}
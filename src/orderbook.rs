@@ -0,0 +1,922 @@
+//! Price-time priority orderbook.
+//!
+//! Resting orders live in two places: a per-price [`PriceLevel`] queue that
+//! preserves time priority, and a flat `orders` map for O(1) lookup by id.
+//! Mutations must keep the two in sync; [`Orderbook::update_order`] exists so
+//! partial fills can shrink an order without losing its queue position.
+
+use crate::types::{Order, OrderId, Side};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// How orders are sequenced within a price level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelOrdering {
+    /// Ascending `(timestamp, sequence)`: time priority holds even when
+    /// orders are inserted out of timestamp order (e.g. during replay).
+    #[default]
+    PriceTime,
+    /// Pure arrival order: whatever `add_order` sees first is first.
+    Fifo,
+    /// Size-time priority: descending remaining quantity, ties broken by
+    /// ascending `(timestamp, sequence)`. A resize moves the order's slot.
+    PriceTimeSize,
+}
+
+impl std::str::FromStr for LevelOrdering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "price_time" | "price-time" => Ok(LevelOrdering::PriceTime),
+            "fifo" => Ok(LevelOrdering::Fifo),
+            "price_time_size" | "price-time-size" => Ok(LevelOrdering::PriceTimeSize),
+            other => Err(format!("unknown level ordering: {other}")),
+        }
+    }
+}
+
+/// Queue of resting orders at a single price, best (oldest) first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    #[serde(with = "crate::types::stable_decimal")]
+    pub price: Decimal,
+    pub orders: VecDeque<Order>,
+}
+
+impl PriceLevel {
+    pub fn new(price: Decimal) -> Self {
+        PriceLevel {
+            price,
+            orders: VecDeque::new(),
+        }
+    }
+
+    pub fn add_order(&mut self, order: Order, ordering: LevelOrdering) {
+        match ordering {
+            LevelOrdering::Fifo => self.orders.push_back(order),
+            LevelOrdering::PriceTime => {
+                let key = (order.timestamp, order.sequence);
+                // Insert after every order with an equal-or-earlier key so
+                // equal keys keep arrival order (stable).
+                let idx = self
+                    .orders
+                    .iter()
+                    .position(|o| (o.timestamp, o.sequence) > key)
+                    .unwrap_or(self.orders.len());
+                self.orders.insert(idx, order);
+            }
+            LevelOrdering::PriceTimeSize => {
+                // Largest remaining first; within a size, time priority as
+                // above. Insertion keeps the level sorted so the matcher can
+                // always take the front without re-sorting.
+                let (qty, key) = (order.remaining_quantity, (order.timestamp, order.sequence));
+                let idx = self
+                    .orders
+                    .iter()
+                    .position(|o| {
+                        o.remaining_quantity < qty
+                            || (o.remaining_quantity == qty && (o.timestamp, o.sequence) > key)
+                    })
+                    .unwrap_or(self.orders.len());
+                self.orders.insert(idx, order);
+            }
+        }
+    }
+
+    pub fn remove_order(&mut self, order_id: OrderId) -> Option<Order> {
+        let idx = self.orders.iter().position(|o| o.id == order_id)?;
+        self.orders.remove(idx)
+    }
+
+    /// Replaces an order in place, preserving its queue position.
+    pub fn update_order(&mut self, order: &Order) -> bool {
+        if let Some(slot) = self.orders.iter_mut().find(|o| o.id == order.id) {
+            *slot = order.clone();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn total_quantity(&self) -> Decimal {
+        self.orders.iter().map(|o| o.remaining_quantity).sum()
+    }
+}
+
+/// One side of the depth returned by [`Orderbook::depth`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthLevel {
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub order_count: usize,
+}
+
+/// Incrementally maintained digest of the whole book: one SHA-256 per
+/// non-empty price level, XOR-combined into a root. Level mutations re-hash
+/// only that level, and comparing a primary against a replica replaying the
+/// same WAL is a 32-byte compare. Not serialized; rebuilt on snapshot load.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct BookDigest {
+    levels: HashMap<(Side, Decimal), [u8; 32]>,
+    root: [u8; 32],
+}
+
+impl BookDigest {
+    fn xor_into_root(&mut self, hash: &[u8; 32]) {
+        for (r, h) in self.root.iter_mut().zip(hash) {
+            *r ^= h;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Orderbook {
+    pub market_id: String,
+    /// Tie-break policy within each price level.
+    #[serde(default)]
+    pub level_ordering: LevelOrdering,
+    /// Bid levels keyed by price; the best bid is the last key.
+    pub bids: BTreeMap<Decimal, PriceLevel>,
+    /// Ask levels keyed by price; the best ask is the first key.
+    pub asks: BTreeMap<Decimal, PriceLevel>,
+    /// Flat index of every resting order by id.
+    pub orders: HashMap<OrderId, Order>,
+    /// Open resting notional (`price * remaining_quantity`) per user, kept
+    /// in lockstep with `orders` for pre-trade risk checks. Not serialized;
+    /// rebuilt on snapshot load like the digest.
+    #[serde(skip)]
+    open_notional: HashMap<u64, Decimal>,
+    /// Resting orders by `(user_id, client_order_id)`, for cancel-by-client-id.
+    /// Client ids are scoped per user, so two users may reuse the same id.
+    /// Not serialized; rebuilt on snapshot load like the digest.
+    #[serde(skip)]
+    client_index: HashMap<(u64, String), OrderId>,
+    #[serde(skip)]
+    digest: BookDigest,
+}
+
+impl Orderbook {
+    pub fn new(market_id: impl Into<String>) -> Self {
+        Self::with_ordering(market_id, LevelOrdering::default())
+    }
+
+    pub fn with_ordering(market_id: impl Into<String>, level_ordering: LevelOrdering) -> Self {
+        Orderbook {
+            market_id: market_id.into(),
+            level_ordering,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            open_notional: HashMap::new(),
+            client_index: HashMap::new(),
+            digest: BookDigest::default(),
+        }
+    }
+
+    fn side_levels_mut(&mut self, side: Side) -> &mut BTreeMap<Decimal, PriceLevel> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    pub fn add_order(&mut self, order: Order) {
+        let ordering = self.level_ordering;
+        let (side, price) = (order.side, order.price);
+        if let Some(client_id) = &order.client_order_id {
+            self.client_index
+                .insert((order.user_id, client_id.clone()), order.id);
+        }
+        *self.open_notional.entry(order.user_id).or_default() +=
+            order.price * order.remaining_quantity;
+        self.orders.insert(order.id, order.clone());
+        self.side_levels_mut(side)
+            .entry(price)
+            .or_insert_with(|| PriceLevel::new(price))
+            .add_order(order, ordering);
+        self.refresh_level_digest(side, price);
+    }
+
+    pub fn remove_order(&mut self, order_id: OrderId) -> Option<Order> {
+        let order = self.orders.remove(&order_id)?;
+        if let Some(client_id) = &order.client_order_id {
+            self.client_index.remove(&(order.user_id, client_id.clone()));
+        }
+        self.release_notional(order.user_id, order.price * order.remaining_quantity);
+        let levels = self.side_levels_mut(order.side);
+        if let Some(level) = levels.get_mut(&order.price) {
+            level.remove_order(order_id);
+            if level.is_empty() {
+                levels.remove(&order.price);
+            }
+        }
+        self.refresh_level_digest(order.side, order.price);
+        Some(order)
+    }
+
+    /// Updates a resting order in both the id index and its price level.
+    /// The price must be unchanged. Queue position is preserved, except
+    /// under [`LevelOrdering::PriceTimeSize`] where a size change re-slots
+    /// the order (removal plus sorted insert, no level re-sort). Returns
+    /// whether the order was found in its level; the id index is only
+    /// written when it was, so the two views cannot drift apart.
+    pub fn update_order(&mut self, order: &Order) -> bool {
+        let ordering = self.level_ordering;
+        let reslot = ordering == LevelOrdering::PriceTimeSize
+            && self
+                .orders
+                .get(&order.id)
+                .is_some_and(|old| old.remaining_quantity != order.remaining_quantity);
+        let in_level = self
+            .side_levels_mut(order.side)
+            .get_mut(&order.price)
+            .is_some_and(|level| {
+                if reslot {
+                    if level.remove_order(order.id).is_none() {
+                        return false;
+                    }
+                    level.add_order(order.clone(), ordering);
+                    true
+                } else {
+                    level.update_order(order)
+                }
+            });
+        if in_level {
+            if let Some(old) = self.orders.get(&order.id) {
+                self.release_notional(old.user_id, old.price * old.remaining_quantity);
+            }
+            *self.open_notional.entry(order.user_id).or_default() +=
+                order.price * order.remaining_quantity;
+            self.orders.insert(order.id, order.clone());
+            self.refresh_level_digest(order.side, order.price);
+        }
+        debug_assert!(in_level, "update_order for order {} not in its level", order.id);
+        in_level
+    }
+
+    /// The book's current digest root. Equal roots mean identical resting
+    /// state (levels, queue order, remaining quantities) with overwhelming
+    /// probability; compare between replicas to detect divergence.
+    pub fn digest(&self) -> [u8; 32] {
+        self.digest.root
+    }
+
+    /// One user's total open resting notional on this book.
+    pub fn user_open_notional(&self, user_id: u64) -> Decimal {
+        self.open_notional.get(&user_id).copied().unwrap_or_default()
+    }
+
+    fn release_notional(&mut self, user_id: u64, amount: Decimal) {
+        if let Some(open) = self.open_notional.get_mut(&user_id) {
+            *open -= amount;
+            // Drop exhausted entries so churned users do not accumulate.
+            if open.is_zero() {
+                self.open_notional.remove(&user_id);
+            }
+        }
+    }
+
+    /// The id of `user_id`'s resting order carrying this client id, if any.
+    pub fn find_client_order(&self, user_id: u64, client_order_id: &str) -> Option<OrderId> {
+        self.client_index
+            .get(&(user_id, client_order_id.to_string()))
+            .copied()
+    }
+
+    /// Recomputes the digest and the client-id index from scratch, e.g.
+    /// after deserializing a snapshot (neither is persisted).
+    pub fn rebuild_digest(&mut self) {
+        let mut digest = BookDigest::default();
+        for (side, levels) in [(Side::Buy, &self.bids), (Side::Sell, &self.asks)] {
+            for level in levels.values() {
+                let hash = Self::level_hash(side, level);
+                digest.xor_into_root(&hash);
+                digest.levels.insert((side, level.price), hash);
+            }
+        }
+        self.digest = digest;
+        self.client_index = self
+            .orders
+            .values()
+            .filter_map(|o| {
+                o.client_order_id
+                    .as_ref()
+                    .map(|c| ((o.user_id, c.clone()), o.id))
+            })
+            .collect();
+        self.open_notional.clear();
+        for order in self.orders.values() {
+            *self.open_notional.entry(order.user_id).or_default() +=
+                order.price * order.remaining_quantity;
+        }
+    }
+
+    /// Hash of one level: side, price and each queued order's identity and
+    /// remaining size, in queue order.
+    fn level_hash(side: Side, level: &PriceLevel) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update([side as u8]);
+        hasher.update(level.price.serialize());
+        for order in &level.orders {
+            hasher.update(order.id.to_le_bytes());
+            hasher.update(order.sequence.to_le_bytes());
+            hasher.update(order.remaining_quantity.serialize());
+        }
+        hasher.finalize().into()
+    }
+
+    /// Re-hashes one level after a mutation and folds the change into the
+    /// root (XOR out the old hash, XOR in the new).
+    fn refresh_level_digest(&mut self, side: Side, price: Decimal) {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let new = levels.get(&price).map(|level| Self::level_hash(side, level));
+        let old = match new {
+            Some(hash) => self.digest.levels.insert((side, price), hash),
+            None => self.digest.levels.remove(&(side, price)),
+        };
+        if let Some(old) = old {
+            self.digest.xor_into_root(&old);
+        }
+        if let Some(new) = new {
+            self.digest.xor_into_root(&new);
+        }
+    }
+
+    /// Cross-checks the book's redundant views against each other: every
+    /// indexed order rests in its price level with identical fields, levels
+    /// hold nothing the index does not, no level is empty, and the derived
+    /// notional, client-id and digest state match a recomputation. Used by
+    /// tests and, when configured, as a pre-snapshot guard; returns the
+    /// first discrepancy found.
+    pub fn verify_invariants(&self) -> Result<(), String> {
+        let mut resting = 0usize;
+        for (side, levels) in [(Side::Buy, &self.bids), (Side::Sell, &self.asks)] {
+            for (&price, level) in levels {
+                if level.is_empty() {
+                    return Err(format!("empty {side:?} level at {price}"));
+                }
+                if level.price != price {
+                    return Err(format!(
+                        "level keyed {price} carries price {}",
+                        level.price
+                    ));
+                }
+                for order in &level.orders {
+                    resting += 1;
+                    if order.side != side || order.price != price {
+                        return Err(format!("order {} rests in the wrong level", order.id));
+                    }
+                    match self.orders.get(&order.id) {
+                        Some(indexed) if indexed == order => {}
+                        Some(_) => {
+                            return Err(format!(
+                                "order {} differs between the index and its level",
+                                order.id
+                            ))
+                        }
+                        None => {
+                            return Err(format!(
+                                "order {} rests in a level but is not indexed",
+                                order.id
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+        if resting != self.orders.len() {
+            return Err(format!(
+                "{} orders indexed but {resting} resting in levels",
+                self.orders.len()
+            ));
+        }
+        let mut rebuilt = self.clone();
+        rebuilt.rebuild_digest();
+        if rebuilt.open_notional != self.open_notional {
+            return Err("open notional out of sync with resting orders".into());
+        }
+        if rebuilt.client_index != self.client_index {
+            return Err("client-order-id index out of sync".into());
+        }
+        if rebuilt.digest != self.digest {
+            return Err("book digest out of sync".into());
+        }
+        Ok(())
+    }
+
+    pub fn get_order(&self, order_id: OrderId) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    pub fn best_bid(&self) -> Option<&PriceLevel> {
+        self.bids.values().next_back()
+    }
+
+    pub fn best_ask(&self) -> Option<&PriceLevel> {
+        self.asks.values().next()
+    }
+
+    /// The resting orders at one price, front of queue first. Empty when no
+    /// level exists at that price.
+    pub fn orders_at(&self, side: Side, price: Decimal) -> Vec<Order> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels
+            .get(&price)
+            .map(|l| l.orders.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Where a resting order sits in the queue at its price level: its
+    /// index (0 = next to match at that price) and the total remaining
+    /// quantity queued ahead of it. `None` when the order is not resting.
+    pub fn queue_position(&self, order_id: OrderId) -> Option<(usize, Decimal)> {
+        let order = self.orders.get(&order_id)?;
+        let levels = match order.side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let level = levels.get(&order.price)?;
+        let mut ahead = Decimal::ZERO;
+        for (index, queued) in level.orders.iter().enumerate() {
+            if queued.id == order_id {
+                return Some((index, ahead));
+            }
+            ahead += queued.remaining_quantity;
+        }
+        None
+    }
+
+    /// All resting orders on one side in matching priority: best price
+    /// first, front of queue first within a level.
+    pub fn orders_in_priority(&self, side: Side) -> Box<dyn Iterator<Item = &Order> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bids.values().rev().flat_map(|l| l.orders.iter())),
+            Side::Sell => Box::new(self.asks.values().flat_map(|l| l.orders.iter())),
+        }
+    }
+
+    pub fn best_order(&self, side: Side) -> Option<&Order> {
+        let level = match side {
+            Side::Buy => self.best_bid()?,
+            Side::Sell => self.best_ask()?,
+        };
+        level.orders.front()
+    }
+
+    /// Aggregated depth, best-first, capped at `max_levels` per side.
+    pub fn depth(&self, max_levels: usize) -> (Vec<DepthLevel>, Vec<DepthLevel>) {
+        let to_level = |l: &PriceLevel| DepthLevel {
+            price: l.price,
+            quantity: l.total_quantity(),
+            order_count: l.order_count(),
+        };
+        let bids = self
+            .bids
+            .values()
+            .rev()
+            .take(max_levels)
+            .map(to_level)
+            .collect();
+        let asks = self.asks.values().take(max_levels).map(to_level).collect();
+        (bids, asks)
+    }
+
+    /// Volume-weighted average price to fill `target_quantity` by sweeping
+    /// `side`'s resting orders best-first, the institutional "price for
+    /// size" quote. `None` when the side cannot fill the full target (or
+    /// the target is non-positive): a partial price would understate the
+    /// cost of the sweep.
+    pub fn price_for_size(&self, side: Side, target_quantity: Decimal) -> Option<Decimal> {
+        if target_quantity <= Decimal::ZERO {
+            return None;
+        }
+        let mut remaining = target_quantity;
+        let mut notional = Decimal::ZERO;
+        for order in self.orders_in_priority(side) {
+            let take = remaining.min(order.remaining_quantity);
+            notional += take * order.price;
+            remaining -= take;
+            if remaining <= Decimal::ZERO {
+                return Some(notional / target_quantity);
+            }
+        }
+        None
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Occupied price levels across both sides.
+    pub fn level_count(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+
+    /// Reclaims excess capacity after a book has grown large and drained:
+    /// shrinks the flat order index, each level's queue and the digest
+    /// cache, and rebuilds the side maps so spare tree nodes are dropped.
+    /// Purely an allocation-level operation; resting orders, ordering and
+    /// the digest are unchanged. Intended for idle/maintenance paths, not
+    /// the hot path.
+    pub fn compact(&mut self) {
+        self.orders.shrink_to_fit();
+        self.open_notional.shrink_to_fit();
+        self.client_index.shrink_to_fit();
+        self.digest.levels.shrink_to_fit();
+        for level in self.bids.values_mut().chain(self.asks.values_mut()) {
+            level.orders.shrink_to_fit();
+        }
+        self.bids = std::mem::take(&mut self.bids).into_iter().collect();
+        self.asks = std::mem::take(&mut self.asks).into_iter().collect();
+    }
+
+    /// Like [`Orderbook::depth`], but everything beyond `max_levels` is
+    /// folded into one synthetic "rest of book" level per side carrying the
+    /// summed remaining quantity and order count, priced at the side's worst
+    /// price. Market-data only; matching always walks the full book.
+    pub fn depth_with_rest(&self, max_levels: usize) -> (Vec<DepthLevel>, Vec<DepthLevel>) {
+        fn take_side<'a>(
+            levels: impl Iterator<Item = &'a PriceLevel>,
+            max_levels: usize,
+        ) -> Vec<DepthLevel> {
+            let mut explicit = Vec::new();
+            let mut rest: Option<DepthLevel> = None;
+            for level in levels {
+                if explicit.len() < max_levels {
+                    explicit.push(DepthLevel {
+                        price: level.price,
+                        quantity: level.total_quantity(),
+                        order_count: level.order_count(),
+                    });
+                } else {
+                    let slot = rest.get_or_insert(DepthLevel {
+                        price: level.price,
+                        quantity: Decimal::ZERO,
+                        order_count: 0,
+                    });
+                    slot.price = level.price;
+                    slot.quantity += level.total_quantity();
+                    slot.order_count += level.order_count();
+                }
+            }
+            explicit.extend(rest);
+            explicit
+        }
+        (
+            take_side(self.bids.values().rev(), max_levels),
+            take_side(self.asks.values(), max_levels),
+        )
+    }
+
+    /// Serializes the book as a human-editable JSON fixture: the market id,
+    /// the level ordering and every resting order in priority sequence.
+    /// Unlike a recovery snapshot this is meant to be attached to a bug
+    /// report, trimmed by hand and loaded back into a unit test with
+    /// [`Orderbook::from_fixture`].
+    pub fn to_fixture(&self) -> String {
+        let fixture = Fixture {
+            market_id: self.market_id.clone(),
+            level_ordering: self.level_ordering,
+            orders: self
+                .orders_in_priority(Side::Buy)
+                .chain(self.orders_in_priority(Side::Sell))
+                .cloned()
+                .collect(),
+        };
+        serde_json::to_string_pretty(&fixture).expect("fixture serialization cannot fail")
+    }
+
+    /// Rebuilds a book from a [`Orderbook::to_fixture`] string. Orders are
+    /// re-inserted through [`Orderbook::add_order`] in their listed
+    /// sequence, so the derived indexes (notional, client ids, digest) are
+    /// always consistent even after the JSON was edited by hand.
+    pub fn from_fixture(fixture: &str) -> serde_json::Result<Orderbook> {
+        let fixture: Fixture = serde_json::from_str(fixture)?;
+        let mut book = Orderbook::with_ordering(fixture.market_id, fixture.level_ordering);
+        for order in fixture.orders {
+            book.add_order(order);
+        }
+        Ok(book)
+    }
+}
+
+/// Wire form of [`Orderbook::to_fixture`].
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    market_id: String,
+    #[serde(default)]
+    level_ordering: LevelOrdering,
+    orders: Vec<Order>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{OrderStatus, OrderType, TimeInForce};
+    use rust_decimal_macros::dec;
+
+    fn order(id: OrderId, side: Side, price: Decimal, qty: Decimal) -> Order {
+        Order {
+            id,
+            user_id: 1,
+            market_id: "BTC-USD".into(),
+            side,
+            order_type: OrderType::Limit,
+            price,
+            quantity: qty,
+            remaining_quantity: qty,
+            status: OrderStatus::New,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: None,
+            session_id: None,
+            sequence: id,
+            timestamp: id as i64,
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        }
+    }
+
+    #[test]
+    fn best_bid_and_ask_track_inserts_and_removes() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Buy, dec!(99), dec!(1)));
+        book.add_order(order(2, Side::Buy, dec!(100), dec!(2)));
+        book.add_order(order(3, Side::Sell, dec!(101), dec!(1)));
+
+        assert_eq!(book.best_bid().unwrap().price, dec!(100));
+        assert_eq!(book.best_ask().unwrap().price, dec!(101));
+
+        book.remove_order(2);
+        assert_eq!(book.best_bid().unwrap().price, dec!(99));
+        assert!(book.get_order(2).is_none());
+    }
+
+    #[test]
+    fn level_preserves_time_priority() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Sell, dec!(100), dec!(1)));
+        book.add_order(order(2, Side::Sell, dec!(100), dec!(1)));
+
+        assert_eq!(book.best_order(Side::Sell).unwrap().id, 1);
+        book.remove_order(1);
+        assert_eq!(book.best_order(Side::Sell).unwrap().id, 2);
+    }
+
+    #[test]
+    fn queue_position_reports_index_and_quantity_ahead() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Sell, dec!(100), dec!(2)));
+        book.add_order(order(2, Side::Sell, dec!(100), dec!(3)));
+        book.add_order(order(3, Side::Sell, dec!(100), dec!(1)));
+        // A different level does not count toward the queue.
+        book.add_order(order(4, Side::Sell, dec!(101), dec!(5)));
+
+        assert_eq!(book.queue_position(1), Some((0, Decimal::ZERO)));
+        assert_eq!(book.queue_position(2), Some((1, dec!(2))));
+        assert_eq!(book.queue_position(3), Some((2, dec!(5))));
+        assert_eq!(book.queue_position(99), None);
+
+        // A partial fill ahead shrinks the quantity ahead, not the index.
+        let mut front = book.get_order(1).unwrap().clone();
+        front.remaining_quantity = dec!(0.5);
+        book.update_order(&front);
+        assert_eq!(book.queue_position(2), Some((1, dec!(0.5))));
+
+        // The front order leaving promotes everyone behind it.
+        book.remove_order(1);
+        assert_eq!(book.queue_position(2), Some((0, Decimal::ZERO)));
+    }
+
+    #[test]
+    fn price_time_ordering_sorts_out_of_order_timestamps() {
+        let mut book = Orderbook::new("BTC-USD");
+        // Arrival order differs from timestamp order, as during replay.
+        let mut late = order(10, Side::Sell, dec!(100), dec!(1));
+        late.timestamp = 300;
+        let mut early = order(11, Side::Sell, dec!(100), dec!(1));
+        early.timestamp = 100;
+        let mut middle = order(12, Side::Sell, dec!(100), dec!(1));
+        middle.timestamp = 200;
+        book.add_order(late);
+        book.add_order(early);
+        book.add_order(middle);
+
+        let ids: Vec<u64> = book.asks[&dec!(100)].orders.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![11, 12, 10]);
+        assert_eq!(book.best_order(Side::Sell).unwrap().id, 11);
+    }
+
+    #[test]
+    fn fifo_ordering_keeps_arrival_order() {
+        let mut book = Orderbook::with_ordering("BTC-USD", LevelOrdering::Fifo);
+        let mut late = order(10, Side::Sell, dec!(100), dec!(1));
+        late.timestamp = 300;
+        let mut early = order(11, Side::Sell, dec!(100), dec!(1));
+        early.timestamp = 100;
+        book.add_order(late);
+        book.add_order(early);
+
+        let ids: Vec<u64> = book.asks[&dec!(100)].orders.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![10, 11]);
+    }
+
+    #[test]
+    fn size_time_priority_puts_a_larger_later_order_first() {
+        let mut book = Orderbook::with_ordering("BTC-USD", LevelOrdering::PriceTimeSize);
+        book.add_order(order(1, Side::Sell, dec!(100), dec!(1)));
+        let mut big = order(2, Side::Sell, dec!(100), dec!(5));
+        big.timestamp = 500;
+        book.add_order(big);
+        book.add_order(order(3, Side::Sell, dec!(100), dec!(1)));
+
+        let ids: Vec<u64> = book.asks[&dec!(100)].orders.iter().map(|o| o.id).collect();
+        // Size first, then time among the equal-size orders.
+        assert_eq!(ids, vec![2, 1, 3]);
+
+        // Shrinking the big order re-slots it behind the untouched ones.
+        let mut shrunk = book.get_order(2).unwrap().clone();
+        shrunk.remaining_quantity = dec!(1);
+        assert!(book.update_order(&shrunk));
+        let ids: Vec<u64> = book.asks[&dec!(100)].orders.iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn update_order_keeps_index_and_level_in_sync() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Sell, dec!(100), dec!(5)));
+
+        let mut partial = book.get_order(1).unwrap().clone();
+        partial.remaining_quantity = dec!(2);
+        assert!(book.update_order(&partial));
+
+        assert_eq!(book.get_order(1).unwrap().remaining_quantity, dec!(2));
+        let level = book.asks.get(&dec!(100)).unwrap();
+        assert_eq!(level.orders.front().unwrap().remaining_quantity, dec!(2));
+    }
+
+    #[test]
+    fn depth_with_rest_folds_tail_into_one_level() {
+        let mut book = Orderbook::new("BTC-USD");
+        for i in 0..5u64 {
+            book.add_order(order(
+                i + 1,
+                Side::Buy,
+                dec!(100) - Decimal::from(i),
+                Decimal::from(i + 1),
+            ));
+        }
+
+        let (bids, asks) = book.depth_with_rest(2);
+        assert!(asks.is_empty());
+        // Two explicit levels plus one aggregate for the remaining three.
+        assert_eq!(bids.len(), 3);
+        assert_eq!(bids[0].price, dec!(100));
+        assert_eq!(bids[1].price, dec!(99));
+        let rest = &bids[2];
+        // 3 + 4 + 5 from the 98/97/96 tail, priced at the worst level.
+        assert_eq!(rest.quantity, dec!(12));
+        assert_eq!(rest.order_count, 3);
+        assert_eq!(rest.price, dec!(96));
+
+        // With a cap wider than the book there is no aggregate level.
+        let (bids, _) = book.depth_with_rest(10);
+        assert_eq!(bids.len(), 5);
+    }
+
+    #[test]
+    fn depth_aggregates_per_level() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Buy, dec!(100), dec!(1)));
+        book.add_order(order(2, Side::Buy, dec!(100), dec!(2)));
+        book.add_order(order(3, Side::Buy, dec!(99), dec!(5)));
+
+        let (bids, asks) = book.depth(10);
+        assert!(asks.is_empty());
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].price, dec!(100));
+        assert_eq!(bids[0].quantity, dec!(3));
+        assert_eq!(bids[0].order_count, 2);
+    }
+
+    #[test]
+    fn orders_at_returns_level_queue_in_priority_order() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Buy, dec!(100), dec!(1)));
+        book.add_order(order(2, Side::Buy, dec!(100), dec!(2)));
+        book.add_order(order(3, Side::Buy, dec!(99), dec!(3)));
+
+        let level = book.orders_at(Side::Buy, dec!(100));
+        assert_eq!(level.iter().map(|o| o.id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(level[1].quantity, dec!(2));
+        assert!(book.orders_at(Side::Sell, dec!(100)).is_empty());
+        assert!(book.orders_at(Side::Buy, dec!(101)).is_empty());
+    }
+
+    #[test]
+    fn compact_reclaims_capacity_and_preserves_the_book() {
+        let mut book = Orderbook::new("BTC-USD");
+        for id in 1..=4096 {
+            book.add_order(order(id, Side::Buy, dec!(100), dec!(1)));
+        }
+        for id in 1..=4094 {
+            book.remove_order(id);
+        }
+        let before = book.orders.capacity();
+        let digest = book.digest();
+
+        book.compact();
+
+        assert!(book.orders.capacity() < before);
+        assert_eq!(book.order_count(), 2);
+        // Queue order and digest survive compaction.
+        let remaining: Vec<u64> = book
+            .orders_at(Side::Buy, dec!(100))
+            .iter()
+            .map(|o| o.id)
+            .collect();
+        assert_eq!(remaining, vec![4095, 4096]);
+        assert_eq!(book.digest(), digest);
+    }
+
+    #[test]
+    fn digests_match_for_identical_event_streams_and_diverge_after_one_more() {
+        let mut a = Orderbook::new("BTC-USD");
+        let mut b = Orderbook::new("BTC-USD");
+        for book in [&mut a, &mut b] {
+            book.add_order(order(1, Side::Buy, dec!(100), dec!(1)));
+            book.add_order(order(2, Side::Buy, dec!(100), dec!(2)));
+            book.add_order(order(3, Side::Sell, dec!(101), dec!(1)));
+            book.remove_order(2);
+        }
+        assert_eq!(a.digest(), b.digest());
+        // The incremental root agrees with a from-scratch rebuild.
+        let incremental = a.digest();
+        a.rebuild_digest();
+        assert_eq!(a.digest(), incremental);
+
+        b.add_order(order(4, Side::Sell, dec!(102), dec!(1)));
+        assert_ne!(a.digest(), b.digest());
+    }
+
+    #[test]
+    fn price_for_size_is_the_vwap_across_the_levels_it_spans() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Sell, dec!(100), dec!(2)));
+        book.add_order(order(2, Side::Sell, dec!(102), dec!(4)));
+        book.add_order(order(3, Side::Buy, dec!(99), dec!(1)));
+
+        // Inside the best level the quote is just its price.
+        assert_eq!(book.price_for_size(Side::Sell, dec!(2)), Some(dec!(100)));
+        // Spanning both: (2*100 + 2*102) / 4 = 101.
+        assert_eq!(book.price_for_size(Side::Sell, dec!(4)), Some(dec!(101)));
+        // More than the side holds: no full fill, no price.
+        assert_eq!(book.price_for_size(Side::Sell, dec!(7)), None);
+        assert_eq!(book.price_for_size(Side::Buy, dec!(1)), Some(dec!(99)));
+        assert_eq!(book.price_for_size(Side::Buy, Decimal::ZERO), None);
+    }
+
+    #[test]
+    fn fixture_round_trip_preserves_orders_and_priority() {
+        let mut book = Orderbook::new("BTC-USD");
+        book.add_order(order(1, Side::Buy, dec!(100), dec!(2)));
+        book.add_order(order(2, Side::Buy, dec!(100), dec!(1)));
+        book.add_order(order(3, Side::Buy, dec!(99), dec!(1)));
+        book.add_order(order(4, Side::Sell, dec!(101), dec!(3)));
+
+        let restored = Orderbook::from_fixture(&book.to_fixture()).unwrap();
+        assert_eq!(restored, book);
+        // Queue priority within the shared level survives the trip.
+        let ids: Vec<OrderId> = restored
+            .orders_in_priority(Side::Buy)
+            .map(|o| o.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        // The rebuilt derived state matches too, digest included.
+        assert_eq!(restored.digest(), book.digest());
+    }
+}
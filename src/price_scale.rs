@@ -0,0 +1,81 @@
+//! Precision-safe conversion between `Decimal` prices and scaled integers.
+//!
+//! Book keys are plain [`Decimal`]s, so the engine itself never needs a
+//! fixed-point representation, but anything exchanging prices as scaled
+//! integers (feeds, export formats, foreign keys) must go through these
+//! helpers rather than an ad-hoc `* 1e8`: overflow and precision loss
+//! surface as errors instead of being silently truncated to zero.
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleError {
+    /// The scaled value does not fit in an `i64`, or the scale factor itself
+    /// is out of `Decimal`'s supported range.
+    Overflow,
+    /// The price carries more fractional digits than the scale preserves;
+    /// scaling it would drop precision.
+    PrecisionLoss,
+}
+
+impl fmt::Display for ScaleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleError::Overflow => write!(f, "scaled price overflows i64"),
+            ScaleError::PrecisionLoss => {
+                write!(f, "price has more precision than the scale preserves")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScaleError {}
+
+/// Converts `price` to an integer carrying `decimals` fractional digits
+/// (e.g. `decimals = 8` turns `1.5` into `150_000_000`).
+pub fn scale(price: Decimal, decimals: u32) -> Result<i64, ScaleError> {
+    let factor = 10u64.checked_pow(decimals).ok_or(ScaleError::Overflow)?;
+    let scaled = price
+        .checked_mul(Decimal::from(factor))
+        .ok_or(ScaleError::Overflow)?;
+    if !scaled.fract().is_zero() {
+        return Err(ScaleError::PrecisionLoss);
+    }
+    scaled.to_i64().ok_or(ScaleError::Overflow)
+}
+
+/// Inverse of [`scale`]: reinterprets `value` as a decimal with `decimals`
+/// fractional digits.
+pub fn unscale(value: i64, decimals: u32) -> Result<Decimal, ScaleError> {
+    Decimal::try_from_i128_with_scale(i128::from(value), decimals)
+        .map_err(|_| ScaleError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn representative_prices_round_trip() {
+        for price in [dec!(0), dec!(0.00000001), dec!(1.5), dec!(64231.25), dec!(-3.125)] {
+            let scaled = scale(price, 8).unwrap();
+            assert_eq!(unscale(scaled, 8).unwrap(), price);
+        }
+        assert_eq!(scale(dec!(1.5), 8).unwrap(), 150_000_000);
+    }
+
+    #[test]
+    fn excess_precision_is_rejected_not_truncated() {
+        assert_eq!(scale(dec!(0.123456789), 8), Err(ScaleError::PrecisionLoss));
+        assert_eq!(scale(dec!(1.001), 2), Err(ScaleError::PrecisionLoss));
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(scale(dec!(99999999999999999999), 8), Err(ScaleError::Overflow));
+        assert_eq!(scale(dec!(1), 20), Err(ScaleError::Overflow));
+    }
+}
@@ -0,0 +1,124 @@
+//! Precision policy for derived quantities.
+//!
+//! Fees, notionals, average prices and VWAPs multiply and divide
+//! [`Decimal`]s whose exact result can exceed `rust_decimal`'s 28-digit
+//! capacity, at which point the library quietly drops the excess fractional
+//! digits. Rather than letting each call site decide (or not notice), every
+//! derived quantity goes through a [`PricingPolicy`]: results are rounded to
+//! one configured scale with one configured strategy, and an operation whose
+//! *integral* part cannot be represented at all surfaces as an error instead
+//! of a wrong number.
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PricingError {
+    /// The exact result's integral part does not fit in a `Decimal`; no
+    /// rounding policy can salvage it.
+    Overflow,
+    /// Division by zero.
+    DivisionByZero,
+}
+
+impl fmt::Display for PricingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::Overflow => write!(f, "derived quantity overflows Decimal"),
+            PricingError::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl std::error::Error for PricingError {}
+
+/// Scale and rounding applied to every derived quantity. The default keeps
+/// 12 fractional digits and rounds midpoints to even, matching the banker's
+/// rounding the wire canonicalization already uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PricingPolicy {
+    /// Fractional digits retained on derived quantities.
+    pub scale: u32,
+    pub strategy: RoundingStrategy,
+}
+
+impl Default for PricingPolicy {
+    fn default() -> Self {
+        PricingPolicy {
+            scale: 12,
+            strategy: RoundingStrategy::MidpointNearestEven,
+        }
+    }
+}
+
+impl PricingPolicy {
+    /// A policy with the given scale and the default strategy.
+    pub fn with_scale(scale: u32) -> Self {
+        PricingPolicy {
+            scale,
+            ..Self::default()
+        }
+    }
+
+    /// Rounds an already computed value to the policy's scale.
+    pub fn round(&self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(self.scale, self.strategy)
+    }
+
+    /// `a * b`, rounded to the policy's scale. Excess fractional digits in
+    /// the exact product round per the strategy (never silently truncate);
+    /// an unrepresentable integral part is an error.
+    pub fn mul(&self, a: Decimal, b: Decimal) -> Result<Decimal, PricingError> {
+        a.checked_mul(b)
+            .map(|v| self.round(v))
+            .ok_or(PricingError::Overflow)
+    }
+
+    /// `a / b`, rounded to the policy's scale.
+    pub fn div(&self, a: Decimal, b: Decimal) -> Result<Decimal, PricingError> {
+        if b.is_zero() {
+            return Err(PricingError::DivisionByZero);
+        }
+        a.checked_div(b)
+            .map(|v| self.round(v))
+            .ok_or(PricingError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+
+    #[test]
+    fn high_precision_product_rounds_per_policy_not_silently() {
+        // The exact product has 22 fractional digits; at scale 6 the policy
+        // rounds it deterministically instead of letting the library keep
+        // whatever fits in 28 significant digits.
+        let policy = PricingPolicy::with_scale(6);
+        let a = Decimal::from_str("1234567.89012345678901").unwrap();
+        let b = Decimal::from_str("9.87654321").unwrap();
+        let product = policy.mul(a, b).unwrap();
+        assert_eq!(product, dec!(12193263.112483));
+        assert!(product.scale() <= 6);
+    }
+
+    #[test]
+    fn midpoints_round_to_even() {
+        let policy = PricingPolicy::with_scale(2);
+        assert_eq!(policy.round(dec!(0.125)), dec!(0.12));
+        assert_eq!(policy.round(dec!(0.135)), dec!(0.14));
+    }
+
+    #[test]
+    fn integral_overflow_is_an_error_not_a_wrong_number() {
+        let policy = PricingPolicy::default();
+        let err = policy.mul(Decimal::MAX, dec!(2)).unwrap_err();
+        assert_eq!(err, PricingError::Overflow);
+        assert_eq!(
+            policy.div(dec!(1), Decimal::ZERO),
+            Err(PricingError::DivisionByZero)
+        );
+    }
+}
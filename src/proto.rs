@@ -0,0 +1,5 @@
+//! Generated protobuf/gRPC types for `xmarket.engine.v1`.
+
+#![allow(clippy::all)]
+
+include!(concat!(env!("OUT_DIR"), "/xmarket.engine.v1.rs"));
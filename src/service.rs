@@ -0,0 +1,1716 @@
+//! tonic gRPC front-end for the exchange.
+
+use crate::config::MarketConfig;
+use crate::engine::MarketPhase;
+use crate::error::{EngineError, RejectReason};
+use crate::exchange::{AckMode, Exchange, NewOrder, NewQuote};
+use crate::proto as pb;
+use crate::proto::admin_server::Admin;
+use crate::proto::market_data_server::MarketData;
+use crate::proto::order_entry_server::OrderEntry;
+use crate::types::{now_ns, OrderType, PegReference, Side, TimeInForce, Trade};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub type SharedExchange = Arc<Mutex<Exchange>>;
+
+/// Global in-flight request cap shared across services. Requests beyond the
+/// limit are rejected immediately with `resource_exhausted` rather than
+/// queued, so saturation is visible to clients.
+#[derive(Clone, Default)]
+pub struct RequestLimiter {
+    semaphore: Option<Arc<tokio::sync::Semaphore>>,
+}
+
+impl RequestLimiter {
+    /// `max_concurrent == 0` means unlimited.
+    pub fn new(max_concurrent: usize) -> Self {
+        RequestLimiter {
+            semaphore: (max_concurrent > 0)
+                .then(|| Arc::new(tokio::sync::Semaphore::new(max_concurrent))),
+        }
+    }
+
+    fn acquire(&self) -> Result<Option<tokio::sync::OwnedSemaphorePermit>, Status> {
+        match &self.semaphore {
+            None => Ok(None),
+            Some(semaphore) => Arc::clone(semaphore)
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| {
+                    let mut status =
+                        Status::resource_exhausted("engine at max concurrent requests");
+                    status.metadata_mut().insert(
+                        "x-reject-reason",
+                        RejectReason::RateLimited.as_str().parse().unwrap(),
+                    );
+                    status
+                }),
+        }
+    }
+}
+
+/// Service-wide operational pause, shared between order entry and the Admin
+/// service that toggles it. While paused, mutating order-entry requests are
+/// rejected with `unavailable`; [`PauseGate::pause`] returns only once
+/// requests admitted earlier have drained, so an operator can take a
+/// consistent snapshot or perform maintenance with nothing in flight.
+/// Long-lived streams are not gated — they hold no engine state between
+/// events.
+#[derive(Clone, Default)]
+pub struct PauseGate {
+    inner: Arc<PauseState>,
+}
+
+#[derive(Default)]
+struct PauseState {
+    paused: std::sync::atomic::AtomicBool,
+    in_flight: std::sync::atomic::AtomicUsize,
+}
+
+impl PauseGate {
+    /// Admits one request, or rejects it while paused. The returned guard
+    /// counts the request as in-flight until it drops.
+    fn enter(&self) -> Result<InFlight, Status> {
+        use std::sync::atomic::Ordering;
+        self.inner.in_flight.fetch_add(1, Ordering::AcqRel);
+        // Check after registering, so a pause landing concurrently either
+        // sees this request in the drain count or we see its flag.
+        if self.inner.paused.load(Ordering::Acquire) {
+            self.inner.in_flight.fetch_sub(1, Ordering::AcqRel);
+            return Err(Status::unavailable("engine paused for maintenance"));
+        }
+        Ok(InFlight(Arc::clone(&self.inner)))
+    }
+
+    /// Stops admitting requests, then waits for admitted ones to drain.
+    pub async fn pause(&self) {
+        use std::sync::atomic::Ordering;
+        self.inner.paused.store(true, Ordering::Release);
+        while self.inner.in_flight.load(Ordering::Acquire) > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+    }
+
+    pub fn resume(&self) {
+        self.inner
+            .paused
+            .store(false, std::sync::atomic::Ordering::Release);
+    }
+}
+
+struct InFlight(Arc<PauseState>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0
+            .in_flight
+            .fetch_sub(1, std::sync::atomic::Ordering::AcqRel);
+    }
+}
+
+fn lock_exchange(exchange: &SharedExchange) -> std::sync::MutexGuard<'_, Exchange> {
+    exchange.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn parse_decimal(field: &str, value: &str) -> Result<Decimal, Status> {
+    Decimal::from_str(value)
+        .map_err(|_| Status::invalid_argument(format!("invalid decimal for {field}: {value:?}")))
+}
+
+fn parse_side(value: i32) -> Result<Side, Status> {
+    match pb::Side::try_from(value) {
+        Ok(pb::Side::Buy) => Ok(Side::Buy),
+        Ok(pb::Side::Sell) => Ok(Side::Sell),
+        _ => Err(Status::invalid_argument("side must be BUY or SELL")),
+    }
+}
+
+fn parse_order_type(value: i32) -> Result<OrderType, Status> {
+    match pb::OrderType::try_from(value) {
+        Ok(pb::OrderType::Limit) => Ok(OrderType::Limit),
+        Ok(pb::OrderType::Market) => Ok(OrderType::Market),
+        _ => Err(Status::invalid_argument("order_type must be LIMIT or MARKET")),
+    }
+}
+
+fn parse_time_in_force(value: i32) -> Result<TimeInForce, Status> {
+    match pb::TimeInForce::try_from(value) {
+        Ok(pb::TimeInForce::Gtc) | Ok(pb::TimeInForce::Unspecified) => Ok(TimeInForce::Gtc),
+        Ok(pb::TimeInForce::Ioc) => Ok(TimeInForce::Ioc),
+        Ok(pb::TimeInForce::Gtd) => Ok(TimeInForce::Gtd),
+        Err(_) => Err(Status::invalid_argument("unknown time_in_force")),
+    }
+}
+
+fn trade_to_proto(trade: &Trade, config: &MarketConfig) -> pb::Trade {
+    pb::Trade {
+        trade_id: trade.id,
+        market_id: trade.market_id.clone(),
+        price: config.format_price(trade.price),
+        quantity: config.format_quantity(trade.quantity),
+        maker_order_id: trade.maker_order_id,
+        taker_order_id: trade.taker_order_id,
+        timestamp_ns: trade.timestamp,
+        aggressor: match trade.aggressor {
+            Some(Side::Buy) => pb::Side::Buy as i32,
+            Some(Side::Sell) => pb::Side::Sell as i32,
+            None => pb::Side::Unspecified as i32,
+        },
+        taker_is_buyer: trade.taker_is_buyer().unwrap_or(false),
+    }
+}
+
+fn bbo_to_proto(
+    market_id: &str,
+    update: &crate::engine::BboUpdate,
+    config: &MarketConfig,
+) -> pb::BboUpdate {
+    let (bid_price, bid_quantity) = match update.bbo.bid {
+        Some((p, q)) => (config.format_price(p), config.format_quantity(q)),
+        None => (String::new(), String::new()),
+    };
+    let (ask_price, ask_quantity) = match update.bbo.ask {
+        Some((p, q)) => (config.format_price(p), config.format_quantity(q)),
+        None => (String::new(), String::new()),
+    };
+    pb::BboUpdate {
+        market_id: market_id.to_string(),
+        bid_price,
+        bid_quantity,
+        ask_price,
+        ask_quantity,
+        timestamp_ns: update.timestamp,
+    }
+}
+
+fn depth_snapshot(exchange: &mut Exchange, market_id: &str, depth: usize) -> pb::DepthSnapshot {
+    let aggregate_tail = exchange.config.depth_aggregate_tail;
+    let market_config = exchange.market_config(market_id);
+    let (bids, asks) = exchange
+        .engine(market_id)
+        .map(|e| {
+            if aggregate_tail {
+                e.orderbook.depth_with_rest(depth)
+            } else {
+                e.orderbook.depth(depth)
+            }
+        })
+        .unwrap_or_default();
+    let to_proto = |levels: Vec<crate::orderbook::DepthLevel>| {
+        levels
+            .into_iter()
+            .map(|l| pb::DepthLevel {
+                price: market_config.format_price(l.price),
+                quantity: market_config.format_quantity(l.quantity),
+                order_count: l.order_count as u32,
+            })
+            .collect()
+    };
+    let stream_sequence = exchange
+        .engine(market_id)
+        .map(|e| e.book_stream_sequence())
+        .unwrap_or(0);
+    pb::DepthSnapshot {
+        market_id: market_id.to_string(),
+        bids: to_proto(bids),
+        asks: to_proto(asks),
+        timestamp_ns: now_ns(),
+        stream_sequence,
+    }
+}
+
+/// Interceptor for the Admin service: requires `authorization: Bearer <token>`
+/// when a token is configured; an empty token disables the check.
+pub fn admin_auth_interceptor(
+    token: String,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    move |request: Request<()>| {
+        if token.is_empty() {
+            return Ok(request);
+        }
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value == format!("Bearer {token}"));
+        if authorized {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("admin token required"))
+        }
+    }
+}
+
+pub struct AdminService {
+    exchange: SharedExchange,
+    limiter: RequestLimiter,
+    pause: PauseGate,
+    started: std::time::Instant,
+}
+
+impl AdminService {
+    pub fn new(exchange: SharedExchange) -> Self {
+        Self::with_limiter(exchange, RequestLimiter::default())
+    }
+
+    pub fn with_limiter(exchange: SharedExchange, limiter: RequestLimiter) -> Self {
+        AdminService {
+            exchange,
+            limiter,
+            pause: PauseGate::default(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// Shares the pause gate with the order-entry service it controls.
+    pub fn with_pause_gate(mut self, pause: PauseGate) -> Self {
+        self.pause = pause;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn force_snapshot(
+        &self,
+        request: Request<pb::ForceSnapshotRequest>,
+    ) -> Result<Response<pb::ForceSnapshotResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let mut exchange = lock_exchange(&self.exchange);
+        let snapshots = if req.market_id.is_empty() {
+            // All markets at one consistent WAL boundary.
+            exchange
+                .snapshot_all()
+                .map_err(|e| Status::from(EngineError::from(e)))?
+                .into_iter()
+                .map(|(market_id, path, sequence)| pb::SnapshotInfo {
+                    market_id,
+                    path: path.display().to_string(),
+                    sequence,
+                })
+                .collect()
+        } else {
+            let market_id = req.market_id;
+            let saved = exchange
+                .snapshot_market(&market_id)
+                .map_err(|e| Status::from(EngineError::from(e)))?;
+            match saved {
+                Some((path, sequence)) => vec![pb::SnapshotInfo {
+                    market_id,
+                    path: path.display().to_string(),
+                    sequence,
+                }],
+                None => return Err(Status::not_found(format!("unknown market {market_id}"))),
+            }
+        };
+        Ok(Response::new(pb::ForceSnapshotResponse { snapshots }))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<pb::StatsRequest>,
+    ) -> Result<Response<pb::StatsResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let io_err = |e| Status::from(EngineError::from(e));
+        let mut exchange = lock_exchange(&self.exchange);
+        let (wal_segments, wal_bytes) = exchange.wal_stats().map_err(io_err)?;
+        let now = now_ns();
+        let mut markets = Vec::new();
+        for market_id in exchange.market_ids() {
+            let last_snapshot_sequence = exchange
+                .latest_snapshot_sequence(&market_id)
+                .map_err(io_err)?
+                .unwrap_or(0);
+            let Some(engine) = exchange.engine_mut(&market_id) else {
+                continue;
+            };
+            let oldest_resting_age_ns = engine.oldest_resting_age_ns(now).unwrap_or(0);
+            let quality = engine.match_quality();
+            markets.push(pb::MarketStats {
+                resting_orders: engine.orderbook.order_count() as u64,
+                price_levels: engine.orderbook.level_count() as u64,
+                last_snapshot_sequence,
+                oldest_resting_age_ns,
+                fill_ratio: quality
+                    .fill_ratio()
+                    .map(|r| r.to_string())
+                    .unwrap_or_default(),
+                avg_time_to_first_fill_ns: quality.avg_time_to_first_fill_ns().unwrap_or(0),
+                avg_time_to_full_fill_ns: quality.avg_time_to_full_fill_ns().unwrap_or(0),
+                market_id,
+            });
+        }
+        Ok(Response::new(pb::StatsResponse {
+            markets,
+            next_sequence: exchange.next_sequence(),
+            wal_segments,
+            wal_bytes,
+            uptime_secs: self.started.elapsed().as_secs() as i64,
+        }))
+    }
+
+    type StreamWalStream = ReceiverStream<Result<pb::WalRecord, Status>>;
+
+    /// Warm-standby replication: ships every framed WAL record from the
+    /// requested sequence, then tails the log for new appends. Polling the
+    /// log keeps the hot path free of replication hooks; a standby applies
+    /// the records via [`crate::replication::Standby`].
+    async fn stream_wal(
+        &self,
+        request: Request<pb::StreamWalRequest>,
+    ) -> Result<Response<Self::StreamWalStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let mut cursor = req.from_sequence.max(1);
+
+        let (tx, rx) = mpsc::channel(256);
+        let exchange = Arc::clone(&self.exchange);
+        tokio::spawn(async move {
+            loop {
+                let entries = {
+                    let exchange = lock_exchange(&exchange);
+                    exchange.wal_entries_from(cursor)
+                };
+                let entries = match entries {
+                    Ok(entries) => entries,
+                    Err(e) => {
+                        let _ = tx.send(Err(Status::from(EngineError::from(e)))).await;
+                        return;
+                    }
+                };
+                for entry in entries {
+                    let record = match crate::wal::encode_record(&entry) {
+                        Ok(record) => record,
+                        Err(e) => {
+                            let _ = tx.send(Err(Status::from(EngineError::from(e)))).await;
+                            return;
+                        }
+                    };
+                    cursor = entry.sequence + 1;
+                    let update = pb::WalRecord {
+                        sequence: entry.sequence,
+                        record,
+                    };
+                    if tx.send(Ok(update)).await.is_err() {
+                        return;
+                    }
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(WAL_TAIL_POLL_MS)).await;
+                if tx.is_closed() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type DumpBookStream = ReceiverStream<Result<pb::BookOrder, Status>>;
+
+    /// Streams every resting order of a market with full, unredacted
+    /// detail, bids then asks in queue-priority order. Streaming keeps the
+    /// response bounded for huge books; the book is copied under the lock
+    /// so the dump is a consistent point-in-time view.
+    async fn dump_book(
+        &self,
+        request: Request<pb::DumpBookRequest>,
+    ) -> Result<Response<Self::DumpBookStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let orders: Vec<pb::BookOrder> = {
+            let exchange = lock_exchange(&self.exchange);
+            let Some(engine) = exchange.engine(&req.market_id) else {
+                return Err(Status::not_found(format!("unknown market {}", req.market_id)));
+            };
+            [Side::Buy, Side::Sell]
+                .into_iter()
+                .flat_map(|side| engine.orderbook.orders_in_priority(side))
+                .map(|o| pb::BookOrder {
+                    side: match o.side {
+                        Side::Buy => pb::Side::Buy as i32,
+                        Side::Sell => pb::Side::Sell as i32,
+                    },
+                    price: o.price.to_string(),
+                    order_id: o.id,
+                    user_id: o.user_id,
+                    quantity: o.quantity.to_string(),
+                    remaining_quantity: o.remaining_quantity.to_string(),
+                    timestamp_ns: o.timestamp,
+                    sequence: o.sequence,
+                    client_order_id: o.client_order_id.clone().unwrap_or_default(),
+                    all_or_none: o.all_or_none,
+                    hidden: !o.public,
+                    last_look: o.last_look,
+                })
+                .collect()
+        };
+
+        let (tx, rx) = mpsc::channel(256);
+        tokio::spawn(async move {
+            for order in orders {
+                if tx.send(Ok(order)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn pause(
+        &self,
+        _request: Request<pb::PauseRequest>,
+    ) -> Result<Response<pb::PauseResponse>, Status> {
+        // Deliberately not limiter-gated: pausing must work on a saturated
+        // service.
+        self.pause.pause().await;
+        Ok(Response::new(pb::PauseResponse {}))
+    }
+
+    async fn resume(
+        &self,
+        _request: Request<pb::ResumeRequest>,
+    ) -> Result<Response<pb::ResumeResponse>, Status> {
+        self.pause.resume();
+        Ok(Response::new(pb::ResumeResponse {}))
+    }
+
+    async fn set_phase(
+        &self,
+        request: Request<pb::SetPhaseRequest>,
+    ) -> Result<Response<pb::SetPhaseResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+        let phase: MarketPhase = req.phase.parse().map_err(Status::invalid_argument)?;
+        let mut exchange = lock_exchange(&self.exchange);
+        let trades = exchange
+            .set_phase(&req.market_id, phase)
+            .map_err(Status::from)?;
+        let market_config = exchange.market_config(&req.market_id);
+        drop(exchange);
+        Ok(Response::new(pb::SetPhaseResponse {
+            phase: phase.as_str().to_string(),
+            trades: trades
+                .iter()
+                .map(|t| trade_to_proto(t, &market_config))
+                .collect(),
+        }))
+    }
+}
+
+/// How often a StreamWal tail re-polls the log for new appends.
+const WAL_TAIL_POLL_MS: u64 = 50;
+
+pub struct OrderEntryService {
+    exchange: SharedExchange,
+    limiter: RequestLimiter,
+    pause: PauseGate,
+    /// Per-client acknowledgement counters for gap detection, keyed by the
+    /// authenticated user id. Service-local by design: they number this
+    /// server's responses, not book events, so they restart at 1 with the
+    /// process.
+    ack_sequences: Mutex<HashMap<u64, u64>>,
+}
+
+impl OrderEntryService {
+    pub fn new(exchange: SharedExchange) -> Self {
+        Self::with_limiter(exchange, RequestLimiter::default())
+    }
+
+    pub fn with_limiter(exchange: SharedExchange, limiter: RequestLimiter) -> Self {
+        OrderEntryService {
+            exchange,
+            limiter,
+            pause: PauseGate::default(),
+            ack_sequences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_pause_gate(mut self, pause: PauseGate) -> Self {
+        self.pause = pause;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl OrderEntry for OrderEntryService {
+    async fn place_order(
+        &self,
+        request: Request<pb::PlaceOrderRequest>,
+    ) -> Result<Response<pb::PlaceOrderResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let _in_flight = self.pause.enter()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+        let order_type = parse_order_type(req.order_type)?;
+        let price = match order_type {
+            OrderType::Limit => parse_decimal("price", &req.price)?,
+            // A market order carrying a price is rejected rather than
+            // silently ignored: the caller either meant a limit order or is
+            // expecting slippage protection this engine does not apply here.
+            OrderType::Market if !req.price.is_empty() => {
+                return Err(Status::invalid_argument(
+                    "market orders must not carry a price; use a limit order for a price cap",
+                ));
+            }
+            OrderType::Market => Decimal::ZERO,
+        };
+        let quantity = parse_decimal("quantity", &req.quantity)?;
+        if quantity <= Decimal::ZERO {
+            return Err(Status::invalid_argument("quantity must be positive"));
+        }
+        let time_in_force = parse_time_in_force(req.time_in_force)?;
+        let new_order = NewOrder {
+            market_id: req.market_id,
+            user_id: req.user_id,
+            side: parse_side(req.side)?,
+            order_type,
+            price,
+            quantity,
+            time_in_force,
+            all_or_none: req.all_or_none,
+            account_group: (!req.account_group.is_empty()).then_some(req.account_group),
+            public: !req.hidden,
+            quantity_in_quote: req.quantity_in_quote,
+            last_look: req.last_look,
+            ack_mode: match pb::AckMode::try_from(req.ack_mode) {
+                Ok(pb::AckMode::Fast) => AckMode::Fast,
+                _ => AckMode::Durable,
+            },
+            expires_at: (req.expires_at_ns > 0).then_some(req.expires_at_ns),
+            client_order_id: (!req.client_order_id.is_empty()).then_some(req.client_order_id),
+            session_id: (!req.session_id.is_empty()).then_some(req.session_id),
+            expected_sequence: (req.expected_sequence > 0).then_some(req.expected_sequence),
+            peg: match pb::PegReference::try_from(req.peg) {
+                Ok(pb::PegReference::Bid) => Some(PegReference::Bid),
+                Ok(pb::PegReference::Ask) => Some(PegReference::Ask),
+                Ok(pb::PegReference::Mid) => Some(PegReference::Mid),
+                _ => None,
+            },
+            peg_offset_bps: if req.peg_offset_bps.is_empty() {
+                Decimal::ZERO
+            } else {
+                parse_decimal("peg_offset_bps", &req.peg_offset_bps)?
+            },
+        };
+
+        let mut exchange = lock_exchange(&self.exchange);
+        let (order, trades) = exchange.place_order(new_order).map_err(Status::from)?;
+        let market_config = exchange.market_config(&order.market_id);
+        drop(exchange);
+
+        // Only accepted placements consume an ack number, so the sequence
+        // stays contiguous across the responses the client actually gets.
+        let client_ack_sequence = {
+            let mut acks = self.ack_sequences.lock().unwrap_or_else(|p| p.into_inner());
+            let slot = acks.entry(order.user_id).or_insert(0);
+            *slot += 1;
+            *slot
+        };
+
+        Ok(Response::new(pb::PlaceOrderResponse {
+            order_id: order.id,
+            status: order.status.as_str().to_string(),
+            remaining_quantity: market_config.format_quantity(order.remaining_quantity),
+            trades: trades
+                .iter()
+                .map(|t| trade_to_proto(t, &market_config))
+                .collect(),
+            client_ack_sequence,
+        }))
+    }
+
+    async fn place_quote(
+        &self,
+        request: Request<pb::PlaceQuoteRequest>,
+    ) -> Result<Response<pb::PlaceQuoteResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let _in_flight = self.pause.enter()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+        let bid_price = parse_decimal("bid_price", &req.bid_price)?;
+        let bid_quantity = parse_decimal("bid_quantity", &req.bid_quantity)?;
+        let ask_price = parse_decimal("ask_price", &req.ask_price)?;
+        let ask_quantity = parse_decimal("ask_quantity", &req.ask_quantity)?;
+        if bid_quantity <= Decimal::ZERO || ask_quantity <= Decimal::ZERO {
+            return Err(Status::invalid_argument("quantities must be positive"));
+        }
+
+        let mut exchange = lock_exchange(&self.exchange);
+        let ((bid, bid_trades), (ask, ask_trades)) = exchange
+            .place_quote(NewQuote {
+                market_id: req.market_id,
+                user_id: req.user_id,
+                bid_price,
+                bid_quantity,
+                ask_price,
+                ask_quantity,
+            })
+            .map_err(Status::from)?;
+        let market_config = exchange.market_config(&bid.market_id);
+        drop(exchange);
+
+        Ok(Response::new(pb::PlaceQuoteResponse {
+            bid_order_id: bid.id,
+            ask_order_id: ask.id,
+            bid_remaining_quantity: market_config.format_quantity(bid.remaining_quantity),
+            ask_remaining_quantity: market_config.format_quantity(ask.remaining_quantity),
+            trades: bid_trades
+                .iter()
+                .chain(ask_trades.iter())
+                .map(|t| trade_to_proto(t, &market_config))
+                .collect(),
+        }))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<pb::CancelOrderRequest>,
+    ) -> Result<Response<pb::CancelOrderResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let _in_flight = self.pause.enter()?;
+        let req = request.into_inner();
+        let mut exchange = lock_exchange(&self.exchange);
+        let cancelled = if req.order_id != 0 {
+            exchange.cancel_order(&req.market_id, req.order_id, req.user_id)
+        } else if !req.client_order_id.is_empty() {
+            exchange.cancel_order_by_client_id(&req.market_id, &req.client_order_id, req.user_id)
+        } else {
+            return Err(Status::invalid_argument(
+                "order_id or client_order_id is required",
+            ));
+        }
+        .map_err(Status::from)?;
+        match cancelled {
+            Some(order) => Ok(Response::new(pb::CancelOrderResponse {
+                order_id: order.id,
+                status: order.status.as_str().to_string(),
+            })),
+            None => Err(Status::not_found(format!(
+                "order {} not found in {}",
+                req.order_id, req.market_id
+            ))),
+        }
+    }
+
+    async fn amend_order(
+        &self,
+        request: Request<pb::AmendOrderRequest>,
+    ) -> Result<Response<pb::AmendOrderResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let _in_flight = self.pause.enter()?;
+        let req = request.into_inner();
+        let new_price = parse_decimal("new_price", &req.new_price)?;
+        let new_quantity = parse_decimal("new_quantity", &req.new_quantity)?;
+        if new_quantity <= Decimal::ZERO {
+            return Err(Status::invalid_argument("new_quantity must be positive"));
+        }
+        let amended = lock_exchange(&self.exchange)
+            .amend_order(
+                &req.market_id,
+                req.order_id,
+                req.user_id,
+                new_price,
+                new_quantity,
+            )
+            .map_err(Status::from)?;
+        match amended {
+            Some((order, _trades)) => Ok(Response::new(pb::AmendOrderResponse {
+                order_id: order.id,
+                status: order.status.as_str().to_string(),
+            })),
+            None => Err(Status::not_found(format!(
+                "order {} not found in {}",
+                req.order_id, req.market_id
+            ))),
+        }
+    }
+
+    type SubscribeOrdersStream = ReceiverStream<Result<pb::OrderUpdate, Status>>;
+
+    async fn subscribe_orders(
+        &self,
+        request: Request<pb::SubscribeOrdersRequest>,
+    ) -> Result<Response<Self::SubscribeOrdersStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+
+        let (mut order_rx, market_config) = {
+            let mut exchange = lock_exchange(&self.exchange);
+            let market_config = exchange.market_config(&req.market_id);
+            let engine = exchange.get_or_create_engine(&req.market_id);
+            (engine.subscribe_orders(), market_config)
+        };
+
+        let (tx, rx) = mpsc::channel(256);
+        tokio::spawn(async move {
+            while let Ok(update) = order_rx.recv().await {
+                if update.order.user_id != req.user_id {
+                    continue;
+                }
+                // Terminal-only subscribers reconcile at the end; partial
+                // fills are suppressed for them.
+                if req.terminal_only && !update.order.status.is_terminal() {
+                    continue;
+                }
+                let message = pb::OrderUpdate {
+                    order_id: update.order.id,
+                    market_id: update.market_id,
+                    status: update.order.status.as_str().to_string(),
+                    remaining_quantity: market_config
+                        .format_quantity(update.order.remaining_quantity),
+                    timestamp_ns: update.timestamp,
+                };
+                if tx.send(Ok(message)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type SessionStream = ReceiverStream<Result<pb::SessionEvent, Status>>;
+
+    async fn reduce_order(
+        &self,
+        request: Request<pb::ReduceOrderRequest>,
+    ) -> Result<Response<pb::ReduceOrderResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let _in_flight = self.pause.enter()?;
+        let req = request.into_inner();
+        let reduce_by = parse_decimal("reduce_by", &req.reduce_by)?;
+        let mut exchange = lock_exchange(&self.exchange);
+        let reduced = exchange
+            .reduce_order(&req.market_id, req.order_id, req.user_id, reduce_by)
+            .map_err(Status::from)?;
+        let market_config = exchange.market_config(&req.market_id);
+        drop(exchange);
+        match reduced {
+            Some(order) => Ok(Response::new(pb::ReduceOrderResponse {
+                order_id: order.id,
+                status: order.status.as_str().to_string(),
+                remaining_quantity: market_config.format_quantity(order.remaining_quantity),
+            })),
+            None => Err(Status::not_found(format!(
+                "order {} not found in {}",
+                req.order_id, req.market_id
+            ))),
+        }
+    }
+
+    async fn session(
+        &self,
+        request: Request<pb::SessionRequest>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.session_id.is_empty() {
+            return Err(Status::invalid_argument("session_id is required"));
+        }
+
+        let (tx, rx) = mpsc::channel(4);
+        let exchange = Arc::clone(&self.exchange);
+        let session_id = req.session_id;
+        tokio::spawn(async move {
+            let ack = pb::SessionEvent {
+                session_id: session_id.clone(),
+                event: "ACTIVE".to_string(),
+            };
+            if tx.send(Ok(ack)).await.is_err() {
+                return;
+            }
+            // The channel closes when the client drops the stream; that is
+            // the disconnect signal for cancel-on-disconnect.
+            tx.closed().await;
+            let result = lock_exchange(&exchange).cancel_session(&session_id);
+            match result {
+                Ok(cancelled) => {
+                    tracing::info!(
+                        session_id,
+                        count = cancelled.len(),
+                        "cancelled session orders on disconnect"
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(session_id, error = %e, "cancel-on-disconnect failed");
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+}
+
+pub struct MarketDataService {
+    exchange: SharedExchange,
+    limiter: RequestLimiter,
+}
+
+impl MarketDataService {
+    pub fn new(exchange: SharedExchange) -> Self {
+        Self::with_limiter(exchange, RequestLimiter::default())
+    }
+
+    pub fn with_limiter(exchange: SharedExchange, limiter: RequestLimiter) -> Self {
+        MarketDataService { exchange, limiter }
+    }
+}
+
+#[tonic::async_trait]
+impl MarketData for MarketDataService {
+    async fn get_depth(
+        &self,
+        request: Request<pb::GetDepthRequest>,
+    ) -> Result<Response<pb::DepthSnapshot>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let mut exchange = lock_exchange(&self.exchange);
+        let depth = if req.depth > 0 {
+            req.depth as usize
+        } else {
+            exchange.config.depth_levels
+        };
+        Ok(Response::new(depth_snapshot(&mut exchange, &req.market_id, depth)))
+    }
+
+    type StreamTradesStream = ReceiverStream<Result<pb::TradeUpdate, Status>>;
+
+    async fn stream_trades(
+        &self,
+        request: Request<pb::StreamTradesRequest>,
+    ) -> Result<Response<Self::StreamTradesStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+
+        let (backfill, mut trade_rx, market_config) = {
+            let mut exchange = lock_exchange(&self.exchange);
+            let market_config = exchange.market_config(&req.market_id);
+            let engine = exchange.get_or_create_engine(&req.market_id);
+            let backfill: Vec<Trade> = engine.recent_trades.iter().cloned().collect();
+            (backfill, engine.subscribe_trades(), market_config)
+        };
+
+        let (tx, rx) = mpsc::channel(256);
+        tokio::spawn(async move {
+            for trade in &backfill {
+                let update = pb::TradeUpdate {
+                    trade: Some(trade_to_proto(trade, &market_config)),
+                    aggressor: pb::Side::Unspecified as i32,
+                    backfill: true,
+                    maker_fee: String::new(),
+                    taker_fee: String::new(),
+                    stream_sequence: 0,
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                match trade_rx.recv().await {
+                    Ok(print) => {
+                        let update = pb::TradeUpdate {
+                            trade: Some(trade_to_proto(&print.trade, &market_config)),
+                            aggressor: match print.aggressor {
+                                Side::Buy => pb::Side::Buy as i32,
+                                Side::Sell => pb::Side::Sell as i32,
+                            },
+                            backfill: false,
+                            maker_fee: print.maker_fee.to_string(),
+                            taker_fee: print.taker_fee.to_string(),
+                            stream_sequence: print.stream_sequence,
+                        };
+                        if tx.send(Ok(update)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        // A trade feed must not silently drop prints; tell
+                        // the client to resubscribe and backfill.
+                        let _ = tx
+                            .send(Err(Status::data_loss(format!(
+                                "trade stream lagged, {missed} prints dropped; resubscribe"
+                            ))))
+                            .await;
+                        break;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_vwap(
+        &self,
+        request: Request<pb::VwapRequest>,
+    ) -> Result<Response<pb::VwapResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.window_ns <= 0 {
+            return Err(Status::invalid_argument("window_ns must be positive"));
+        }
+        let mut exchange = lock_exchange(&self.exchange);
+        let result = exchange
+            .engine_mut(&req.market_id)
+            .and_then(|e| e.vwap(req.window_ns, now_ns()));
+        let (vwap, volume, notional) = match result {
+            Some((vwap, volume, notional)) => {
+                (vwap.to_string(), volume.to_string(), notional.to_string())
+            }
+            None => (String::new(), "0".to_string(), "0".to_string()),
+        };
+        Ok(Response::new(pb::VwapResponse {
+            market_id: req.market_id,
+            vwap,
+            volume,
+            notional,
+            window_ns: req.window_ns,
+        }))
+    }
+
+    async fn get_quote_tiers(
+        &self,
+        request: Request<pb::QuoteTiersRequest>,
+    ) -> Result<Response<pb::QuoteTiersResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let exchange = lock_exchange(&self.exchange);
+        let market_config = exchange.market_config(&req.market_id);
+        let tiers = match exchange.engine(&req.market_id) {
+            Some(engine) => market_config
+                .quote_size_tiers
+                .iter()
+                .map(|&size| {
+                    let quote = |side| {
+                        engine
+                            .orderbook
+                            .price_for_size(side, size)
+                            .map(|p| p.to_string())
+                            .unwrap_or_default()
+                    };
+                    pb::TierQuote {
+                        size: market_config.format_quantity(size),
+                        bid_price: quote(Side::Buy),
+                        ask_price: quote(Side::Sell),
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Response::new(pb::QuoteTiersResponse {
+            market_id: req.market_id,
+            tiers,
+        }))
+    }
+
+    async fn get_level_detail(
+        &self,
+        request: Request<pb::LevelDetailRequest>,
+    ) -> Result<Response<pb::LevelDetailResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let side = parse_side(req.side)?;
+        let price = parse_decimal("price", &req.price)?;
+
+        let exchange = lock_exchange(&self.exchange);
+        let orders = exchange
+            .engine(&req.market_id)
+            .map(|e| e.orderbook.orders_at(side, price))
+            .unwrap_or_default();
+        Ok(Response::new(pb::LevelDetailResponse {
+            market_id: req.market_id,
+            price: price.to_string(),
+            orders: orders
+                .iter()
+                .map(|o| pb::RestingOrder {
+                    order_id: o.id,
+                    // Only the owner sees whose order it is.
+                    user_id: if o.user_id == req.user_id { o.user_id } else { 0 },
+                    quantity: o.quantity.to_string(),
+                    remaining_quantity: o.remaining_quantity.to_string(),
+                    timestamp_ns: o.timestamp,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_queue_position(
+        &self,
+        request: Request<pb::QueuePositionRequest>,
+    ) -> Result<Response<pb::QueuePositionResponse>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+
+        let exchange = lock_exchange(&self.exchange);
+        let engine = exchange
+            .engine(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("unknown market: {}", req.market_id)))?;
+        // Non-owners get the same answer as for a nonexistent order, so the
+        // endpoint cannot be used to probe other participants' queue spots.
+        let owned = engine
+            .orderbook
+            .get_order(req.order_id)
+            .is_some_and(|o| o.user_id == req.user_id);
+        let (position, ahead) = owned
+            .then(|| engine.orderbook.queue_position(req.order_id))
+            .flatten()
+            .ok_or_else(|| Status::not_found(format!("order {} not found", req.order_id)))?;
+        Ok(Response::new(pb::QueuePositionResponse {
+            market_id: req.market_id,
+            order_id: req.order_id,
+            position: position as u32,
+            quantity_ahead: ahead.to_string(),
+        }))
+    }
+
+    type SubscribeDepthStream = ReceiverStream<Result<pb::DepthSnapshot, Status>>;
+
+    async fn subscribe_depth(
+        &self,
+        request: Request<pb::SubscribeDepthRequest>,
+    ) -> Result<Response<Self::SubscribeDepthStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        let (mut book_rx, initial, depth) = {
+            let mut exchange = lock_exchange(&self.exchange);
+            let depth = if req.depth > 0 {
+                req.depth as usize
+            } else {
+                exchange.config.depth_levels
+            };
+            let rx = exchange.get_or_create_engine(&req.market_id).subscribe_book();
+            let initial = depth_snapshot(&mut exchange, &req.market_id, depth);
+            (rx, initial, depth)
+        };
+
+        let (tx, rx) = mpsc::channel(64);
+        let exchange = Arc::clone(&self.exchange);
+        let market_id = req.market_id;
+        tokio::spawn(async move {
+            if tx.send(Ok(initial)).await.is_err() {
+                return;
+            }
+            loop {
+                match book_rx.recv().await {
+                    Ok(update) => {
+                        let mut snapshot = {
+                            let mut exchange = lock_exchange(&exchange);
+                            depth_snapshot(&mut exchange, &market_id, depth)
+                        };
+                        // Stamp the triggering update's position, not the
+                        // current counter: coalescing here would otherwise
+                        // look like a gap to the client.
+                        snapshot.stream_sequence = update.stream_sequence;
+                        if tx.send(Ok(snapshot)).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Skipped updates are fine: the next snapshot is complete.
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Gap recovery for depth subscribers: returns the complete current
+    /// book stamped with the current stream sequence, so the client resumes
+    /// from the next update with no missed state.
+    async fn resync(
+        &self,
+        request: Request<pb::ResyncRequest>,
+    ) -> Result<Response<pb::DepthSnapshot>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+        let mut exchange = lock_exchange(&self.exchange);
+        let depth = if req.depth > 0 {
+            req.depth as usize
+        } else {
+            exchange.config.depth_levels
+        };
+        Ok(Response::new(depth_snapshot(&mut exchange, &req.market_id, depth)))
+    }
+
+    type SubscribeBboStream = ReceiverStream<Result<pb::BboUpdate, Status>>;
+
+    async fn subscribe_bbo(
+        &self,
+        request: Request<pb::SubscribeBboRequest>,
+    ) -> Result<Response<Self::SubscribeBboStream>, Status> {
+        let _permit = self.limiter.acquire()?;
+        let req = request.into_inner();
+        if req.market_id.is_empty() {
+            return Err(Status::invalid_argument("market_id is required"));
+        }
+        let (mut bbo_rx, initial, market_config) = {
+            let mut exchange = lock_exchange(&self.exchange);
+            let market_config = exchange.market_config(&req.market_id);
+            let engine = exchange.get_or_create_engine(&req.market_id);
+            let initial = crate::engine::BboUpdate {
+                market_id: req.market_id.clone(),
+                bbo: engine.current_bbo(),
+                timestamp: now_ns(),
+            };
+            (engine.subscribe_bbo(), initial, market_config)
+        };
+
+        let (tx, rx) = mpsc::channel(64);
+        let market_id = req.market_id;
+        tokio::spawn(async move {
+            if tx.send(Ok(bbo_to_proto(&market_id, &initial, &market_config))).await.is_err() {
+                return;
+            }
+            loop {
+                match bbo_rx.recv().await {
+                    Ok(update) => {
+                        if tx.send(Ok(bbo_to_proto(&market_id, &update, &market_config))).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Missed intermediate BBOs are fine; the next change
+                    // carries the full current touch.
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use rust_decimal_macros::dec;
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    fn new_limit(user_id: u64, side: Side, price: &str, qty: &str) -> NewOrder {
+        NewOrder {
+            market_id: "BTC-USD".into(),
+            user_id,
+            side,
+            order_type: OrderType::Limit,
+            price: price.parse().unwrap(),
+            quantity: qty.parse().unwrap(),
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: None,
+            session_id: None,
+            ack_mode: AckMode::Durable,
+            expected_sequence: None,
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn trade_stream_backfills_then_delivers_live_prints() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        // One trade before the subscriber connects: it arrives as backfill.
+        {
+            let mut ex = lock_exchange(&exchange);
+            ex.place_order(new_limit(1, Side::Sell, "100", "1")).unwrap();
+            ex.place_order(new_limit(2, Side::Buy, "100", "1")).unwrap();
+        }
+
+        let service = MarketDataService::new(Arc::clone(&exchange));
+        let mut stream = service
+            .stream_trades(Request::new(pb::StreamTradesRequest {
+                market_id: "BTC-USD".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let first = stream.next().await.unwrap().unwrap();
+        assert!(first.backfill);
+        assert_eq!(first.trade.as_ref().unwrap().price, "100");
+        assert_eq!(first.aggressor, pb::Side::Unspecified as i32);
+
+        // A trade after connecting arrives live with its aggressor side.
+        {
+            let mut ex = lock_exchange(&exchange);
+            ex.place_order(new_limit(3, Side::Sell, "101", "2")).unwrap();
+            ex.place_order(new_limit(4, Side::Buy, "101", "2")).unwrap();
+        }
+        let live = stream.next().await.unwrap().unwrap();
+        assert!(!live.backfill);
+        assert_eq!(live.trade.as_ref().unwrap().price, "101");
+        assert_eq!(live.aggressor, pb::Side::Buy as i32);
+    }
+
+    #[tokio::test]
+    async fn resync_reanchors_a_depth_stream_after_a_dropped_update() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let service = MarketDataService::new(Arc::clone(&exchange));
+        let mut stream = service
+            .subscribe_depth(Request::new(pb::SubscribeDepthRequest {
+                market_id: "BTC-USD".into(),
+                depth: 10,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let initial = stream.next().await.unwrap().unwrap();
+
+        lock_exchange(&exchange)
+            .place_order(new_limit(1, Side::Buy, "98", "1"))
+            .unwrap();
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(update.stream_sequence, initial.stream_sequence + 1);
+
+        // Simulate a dropped message: the next update is read and discarded,
+        // as if it never reached the client.
+        lock_exchange(&exchange)
+            .place_order(new_limit(2, Side::Buy, "97", "2"))
+            .unwrap();
+        let dropped = stream.next().await.unwrap().unwrap();
+
+        // The client saw the gap and resyncs: the snapshot carries the full
+        // book (including the missed level) and the current position.
+        let resync = service
+            .resync(Request::new(pb::ResyncRequest {
+                market_id: "BTC-USD".into(),
+                depth: 10,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resync.stream_sequence, dropped.stream_sequence);
+        assert_eq!(resync.bids.len(), 2);
+        assert_eq!(resync.bids[1].price, "97");
+
+        // Subsequent updates continue from the resync position.
+        lock_exchange(&exchange)
+            .place_order(new_limit(3, Side::Buy, "96", "1"))
+            .unwrap();
+        let next = stream.next().await.unwrap().unwrap();
+        assert_eq!(next.stream_sequence, resync.stream_sequence + 1);
+    }
+
+    #[tokio::test]
+    async fn force_snapshot_writes_file_at_current_sequence() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        lock_exchange(&exchange)
+            .place_order(new_limit(1, Side::Buy, "99", "1"))
+            .unwrap();
+
+        let service = AdminService::new(Arc::clone(&exchange));
+        let response = service
+            .force_snapshot(Request::new(pb::ForceSnapshotRequest {
+                market_id: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.snapshots.len(), 1);
+        let info = &response.snapshots[0];
+        assert_eq!(info.market_id, "BTC-USD");
+        // One journaled place: the snapshot covers WAL sequence 1.
+        assert_eq!(info.sequence, 1);
+        assert!(std::path::Path::new(&info.path).exists());
+    }
+
+    #[tokio::test]
+    async fn stats_reflect_resting_orders_and_wal_growth() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        {
+            let mut ex = lock_exchange(&exchange);
+            // Two resting bids at distinct levels, one of them cancelled, and
+            // a crossed pair that fully fills: one order left resting.
+            ex.place_order(new_limit(1, Side::Buy, "98", "1")).unwrap();
+            let (cancelled, _) = ex.place_order(new_limit(2, Side::Buy, "97", "1")).unwrap();
+            ex.cancel_order("BTC-USD", cancelled.id, 0).unwrap();
+            ex.place_order(new_limit(3, Side::Sell, "100", "1")).unwrap();
+            ex.place_order(new_limit(4, Side::Buy, "100", "1")).unwrap();
+        }
+
+        let service = AdminService::new(Arc::clone(&exchange));
+        let stats = service
+            .get_stats(Request::new(pb::StatsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(stats.markets.len(), 1);
+        let market = &stats.markets[0];
+        assert_eq!(market.market_id, "BTC-USD");
+        assert_eq!(market.resting_orders, 1);
+        assert_eq!(market.price_levels, 1);
+        assert_eq!(market.last_snapshot_sequence, 0);
+        // The one resting order has a measurable age.
+        assert!(market.oldest_resting_age_ns > 0);
+        // Four places, one cancel, one journaled trade and one fill record.
+        assert_eq!(stats.next_sequence, 8);
+        assert!(stats.wal_segments >= 1);
+        assert!(stats.wal_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn book_dump_streams_exactly_the_resting_orders() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        {
+            let mut ex = lock_exchange(&exchange);
+            ex.place_order(new_limit(1, Side::Buy, "99", "2")).unwrap();
+            ex.place_order(new_limit(2, Side::Sell, "101", "3")).unwrap();
+            // Partially fill the ask so the dump shows live remainders.
+            ex.place_order(new_limit(3, Side::Buy, "101", "1")).unwrap();
+        }
+
+        let service = AdminService::new(Arc::clone(&exchange));
+        let mut stream = service
+            .dump_book(Request::new(pb::DumpBookRequest {
+                market_id: "BTC-USD".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut dumped = Vec::new();
+        while let Some(order) = stream.next().await {
+            dumped.push(order.unwrap());
+        }
+
+        let summary: Vec<(u64, i32, &str)> = dumped
+            .iter()
+            .map(|o| (o.order_id, o.side, o.remaining_quantity.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, pb::Side::Buy as i32, "2"),
+                (2, pb::Side::Sell as i32, "2"),
+            ]
+        );
+
+        let missing = service
+            .dump_book(Request::new(pb::DumpBookRequest {
+                market_id: "NOPE".into(),
+            }))
+            .await;
+        assert_eq!(missing.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn pause_drains_in_flight_requests_and_rejects_until_resume() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let pause = PauseGate::default();
+        let orders = OrderEntryService::new(Arc::clone(&exchange)).with_pause_gate(pause.clone());
+        let admin = AdminService::new(Arc::clone(&exchange)).with_pause_gate(pause.clone());
+
+        // Hold a request in flight: Pause must not return until it drains.
+        let in_flight = pause.enter().unwrap();
+        let draining = admin.pause(Request::new(pb::PauseRequest {}));
+        tokio::pin!(draining);
+        assert!(tokio::time::timeout(
+            std::time::Duration::from_millis(20),
+            draining.as_mut()
+        )
+        .await
+        .is_err());
+
+        // New requests are turned away while draining and while paused.
+        let status = orders
+            .place_order(Request::new(pb::PlaceOrderRequest {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                side: pb::Side::Buy as i32,
+                order_type: pb::OrderType::Limit as i32,
+                price: "100".into(),
+                quantity: "1".into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        // The in-flight request completing lets Pause return.
+        drop(in_flight);
+        draining.await.unwrap();
+
+        admin.resume(Request::new(pb::ResumeRequest {})).await.unwrap();
+        let response = orders
+            .place_order(Request::new(pb::PlaceOrderRequest {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                side: pb::Side::Buy as i32,
+                order_type: pb::OrderType::Limit as i32,
+                price: "100".into(),
+                quantity: "1".into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status, "NEW");
+    }
+
+    #[tokio::test]
+    async fn terminal_only_order_stream_skips_partial_fills() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let service = OrderEntryService::new(Arc::clone(&exchange));
+        let mut stream = service
+            .subscribe_orders(Request::new(pb::SubscribeOrdersRequest {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                terminal_only: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // A partial fill of the resting maker produces no message; the
+        // final fill arrives as the terminal state.
+        {
+            let mut ex = lock_exchange(&exchange);
+            ex.place_order(new_limit(1, Side::Sell, "100", "2")).unwrap();
+            ex.place_order(new_limit(2, Side::Buy, "100", "1")).unwrap();
+            ex.place_order(new_limit(3, Side::Buy, "100", "1")).unwrap();
+        }
+        let update = stream.next().await.unwrap().unwrap();
+        assert_eq!(update.order_id, 1);
+        assert_eq!(update.status, "FILLED");
+        assert_eq!(update.remaining_quantity, "0");
+
+        // Without the preference, the partial fill is delivered too.
+        let mut full = service
+            .subscribe_orders(Request::new(pb::SubscribeOrdersRequest {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                terminal_only: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        {
+            let mut ex = lock_exchange(&exchange);
+            ex.place_order(new_limit(1, Side::Sell, "101", "2")).unwrap();
+            ex.place_order(new_limit(2, Side::Buy, "101", "1")).unwrap();
+        }
+        let update = full.next().await.unwrap().unwrap();
+        assert_eq!(update.status, "PARTIALLY_FILLED");
+        assert_eq!(update.remaining_quantity, "1");
+    }
+
+    #[tokio::test]
+    async fn ack_sequences_are_contiguous_per_client() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let service = OrderEntryService::new(exchange);
+        let place = |user_id: u64, price: &str| {
+            service.place_order(Request::new(pb::PlaceOrderRequest {
+                market_id: "BTC-USD".into(),
+                user_id,
+                side: pb::Side::Buy as i32,
+                order_type: pb::OrderType::Limit as i32,
+                price: price.into(),
+                quantity: "1".into(),
+                ..Default::default()
+            }))
+        };
+
+        for expected in 1..=3u64 {
+            let response = place(1, "99").await.unwrap().into_inner();
+            assert_eq!(response.client_ack_sequence, expected);
+        }
+        // A second client numbers independently, and its placements do not
+        // disturb the first client's run.
+        assert_eq!(
+            place(2, "98").await.unwrap().into_inner().client_ack_sequence,
+            1
+        );
+        assert_eq!(
+            place(1, "99").await.unwrap().into_inner().client_ack_sequence,
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn market_order_with_a_price_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let service = OrderEntryService::new(exchange);
+        let status = service
+            .place_order(Request::new(pb::PlaceOrderRequest {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                side: pb::Side::Buy as i32,
+                order_type: pb::OrderType::Market as i32,
+                price: "100".into(),
+                quantity: "1".into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn saturated_limiter_rejects_with_resource_exhausted() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        let limiter = RequestLimiter::new(1);
+        let service = MarketDataService::with_limiter(Arc::clone(&exchange), limiter.clone());
+
+        // Hold the only permit, as an in-flight request would.
+        let _held = limiter.acquire().unwrap();
+        let err = service
+            .get_depth(Request::new(pb::GetDepthRequest {
+                market_id: "BTC-USD".into(),
+                depth: 0,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+        // Once the permit is released the request goes through.
+        drop(_held);
+        assert!(service
+            .get_depth(Request::new(pb::GetDepthRequest {
+                market_id: "BTC-USD".into(),
+                depth: 0,
+            }))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn dropping_session_stream_cancels_session_orders() {
+        let dir = TempDir::new().unwrap();
+        let config = EngineConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..EngineConfig::default()
+        };
+        let exchange: SharedExchange = Arc::new(Mutex::new(Exchange::new(config).unwrap()));
+        lock_exchange(&exchange)
+            .place_order(NewOrder {
+                market_id: "BTC-USD".into(),
+                user_id: 1,
+                side: Side::Buy,
+                order_type: OrderType::Limit,
+                price: dec!(99),
+                quantity: dec!(1),
+                time_in_force: TimeInForce::Gtc,
+                all_or_none: false,
+                account_group: None,
+                public: true,
+                quantity_in_quote: false,
+                last_look: false,
+                expires_at: None,
+                client_order_id: None,
+                session_id: Some("mm-1".into()),
+                ack_mode: AckMode::Durable,
+                expected_sequence: None,
+                peg: None,
+                peg_offset_bps: Decimal::ZERO,
+            })
+            .unwrap();
+
+        let service = OrderEntryService::new(Arc::clone(&exchange));
+        let mut stream = service
+            .session(Request::new(pb::SessionRequest {
+                session_id: "mm-1".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let ack = stream.next().await.unwrap().unwrap();
+        assert_eq!(ack.event, "ACTIVE");
+
+        // Dropping the stream simulates the client disconnecting.
+        drop(stream);
+        for _ in 0..100 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            if lock_exchange(&exchange)
+                .engine("BTC-USD")
+                .unwrap()
+                .orderbook
+                .order_count()
+                == 0
+            {
+                return;
+            }
+        }
+        panic!("session orders were not cancelled after disconnect");
+    }
+}
@@ -0,0 +1,708 @@
+//! Per-market engine snapshots.
+//!
+//! A [`Snapshot`] captures one market's book and counters together with the
+//! WAL sequence it is consistent with; recovery loads the latest snapshot and
+//! replays the WAL tail from there. Snapshots are written atomically
+//! (tmp file + rename) by the [`SnapshotManager`].
+//!
+//! Three on-disk formats are supported, selected by [`SnapshotFormat`]:
+//! compact bincode (`.snap`, the default), pretty-printed JSON (`.json`)
+//! with `Decimal` fields as strings for human inspection and tooling, and a
+//! memory-mapped variant of the bincode layout (`.msnap`) that decodes
+//! straight out of the page cache for faster recovery of huge books.
+//! [`SnapshotManager::load`] detects the format from the file extension, so a
+//! manager configured for one format can still read the others.
+
+use crate::engine::MarketPhase;
+use crate::orderbook::{LevelOrdering, Orderbook};
+use crate::wal::{OrderV4, OrderV5};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// On-disk snapshot format version: the leading byte of every bincode
+/// snapshot file. Bump when [`Snapshot`] (or anything it embeds) changes
+/// incompatibly and keep a decode arm for old versions in
+/// [`SnapshotManager::load`]. JSON snapshots are self-describing and tolerate
+/// added fields, so they carry no version byte.
+pub const SNAPSHOT_FORMAT_VERSION: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub market_id: String,
+    /// Last WAL sequence whose effects are included in this snapshot.
+    pub sequence: i64,
+    pub timestamp: i64,
+    pub orderbook: Orderbook,
+    pub next_trade_id: u64,
+    /// Matching RNG state at the snapshot point, so a restore resumes the
+    /// deterministic draw sequence exactly (version 2).
+    #[serde(default)]
+    pub rng_state: u64,
+    /// Session phase at the snapshot point, so an auction market restored
+    /// mid-call keeps accumulating instead of matching (version 4).
+    #[serde(default)]
+    pub phase: MarketPhase,
+    /// Cumulative traded base quantity per user, so volume-tiered fee
+    /// discounts resume after recovery (version 6).
+    #[serde(default)]
+    pub user_volumes: HashMap<u64, Decimal>,
+}
+
+/// Version-5 layout, before per-user traded volume was persisted. Restored
+/// volumes start at zero, dropping users to the base tier.
+#[derive(Deserialize)]
+struct SnapshotV5 {
+    market_id: String,
+    sequence: i64,
+    timestamp: i64,
+    orderbook: Orderbook,
+    next_trade_id: u64,
+    rng_state: u64,
+    phase: MarketPhase,
+}
+
+impl From<SnapshotV5> for Snapshot {
+    fn from(v5: SnapshotV5) -> Snapshot {
+        Snapshot {
+            market_id: v5.market_id,
+            sequence: v5.sequence,
+            timestamp: v5.timestamp,
+            orderbook: v5.orderbook,
+            next_trade_id: v5.next_trade_id,
+            rng_state: v5.rng_state,
+            phase: v5.phase,
+            user_volumes: HashMap::new(),
+        }
+    }
+}
+
+/// Version-4 layout, before decimal fields moved to the pinned
+/// [`crate::types::stable_decimal`] encoding.
+#[derive(Deserialize)]
+struct SnapshotV4 {
+    market_id: String,
+    sequence: i64,
+    timestamp: i64,
+    orderbook: OrderbookV4,
+    next_trade_id: u64,
+    rng_state: u64,
+    phase: MarketPhase,
+}
+
+impl From<SnapshotV4> for Snapshot {
+    fn from(v4: SnapshotV4) -> Snapshot {
+        Snapshot {
+            market_id: v4.market_id,
+            sequence: v4.sequence,
+            timestamp: v4.timestamp,
+            orderbook: v4.orderbook.into(),
+            next_trade_id: v4.next_trade_id,
+            rng_state: v4.rng_state,
+            phase: v4.phase,
+            user_volumes: HashMap::new(),
+        }
+    }
+}
+
+/// Version-3 layout, before the session phase was persisted. The missing
+/// phase restores as continuous.
+#[derive(Deserialize)]
+struct SnapshotV3 {
+    market_id: String,
+    sequence: i64,
+    timestamp: i64,
+    orderbook: OrderbookV4,
+    next_trade_id: u64,
+    rng_state: u64,
+}
+
+impl From<SnapshotV3> for Snapshot {
+    fn from(v3: SnapshotV3) -> Snapshot {
+        Snapshot {
+            market_id: v3.market_id,
+            sequence: v3.sequence,
+            timestamp: v3.timestamp,
+            orderbook: v3.orderbook.into(),
+            next_trade_id: v3.next_trade_id,
+            rng_state: v3.rng_state,
+            phase: MarketPhase::default(),
+            user_volumes: HashMap::new(),
+        }
+    }
+}
+
+/// One price level as serialized by snapshot formats 3 and 4: the current
+/// order shape, but decimals in the dependency's default encoding.
+#[derive(Deserialize)]
+struct PriceLevelV4 {
+    #[allow(dead_code)]
+    price: Decimal,
+    orders: VecDeque<OrderV5>,
+}
+
+/// [`Orderbook`] as serialized by snapshot formats 3 and 4. Converted the
+/// same way as [`OrderbookV2`]: re-insert every resting order.
+#[derive(Deserialize)]
+struct OrderbookV4 {
+    market_id: String,
+    #[serde(default)]
+    level_ordering: LevelOrdering,
+    bids: BTreeMap<Decimal, PriceLevelV4>,
+    asks: BTreeMap<Decimal, PriceLevelV4>,
+    #[allow(dead_code)]
+    orders: HashMap<u64, OrderV5>,
+}
+
+impl From<OrderbookV4> for Orderbook {
+    fn from(v4: OrderbookV4) -> Orderbook {
+        let mut book = Orderbook::with_ordering(v4.market_id, v4.level_ordering);
+        for level in v4.bids.into_values().chain(v4.asks.into_values()) {
+            for order in level.orders {
+                book.add_order(order.into());
+            }
+        }
+        book
+    }
+}
+
+/// One price level as serialized by snapshot formats 1 and 2: orders in the
+/// pre-peg [`OrderV4`] shape.
+#[derive(Deserialize)]
+struct PriceLevelV2 {
+    #[allow(dead_code)]
+    price: Decimal,
+    orders: VecDeque<OrderV4>,
+}
+
+/// [`Orderbook`] as serialized by snapshot formats 1 and 2. Converted by
+/// re-inserting every resting order through [`Orderbook::add_order`], which
+/// rebuilds the flat index (the serialized one is redundant) and preserves
+/// queue order under every level-ordering policy.
+#[derive(Deserialize)]
+struct OrderbookV2 {
+    market_id: String,
+    #[serde(default)]
+    level_ordering: LevelOrdering,
+    bids: BTreeMap<Decimal, PriceLevelV2>,
+    asks: BTreeMap<Decimal, PriceLevelV2>,
+    #[allow(dead_code)]
+    orders: HashMap<u64, OrderV4>,
+}
+
+impl From<OrderbookV2> for Orderbook {
+    fn from(v2: OrderbookV2) -> Orderbook {
+        let mut book = Orderbook::with_ordering(v2.market_id, v2.level_ordering);
+        for level in v2.bids.into_values().chain(v2.asks.into_values()) {
+            for order in level.orders {
+                book.add_order(order.into());
+            }
+        }
+        book
+    }
+}
+
+/// Version-2 layout, before peg parameters existed on orders.
+#[derive(Deserialize)]
+struct SnapshotV2 {
+    market_id: String,
+    sequence: i64,
+    timestamp: i64,
+    orderbook: OrderbookV2,
+    next_trade_id: u64,
+    rng_state: u64,
+}
+
+impl From<SnapshotV2> for Snapshot {
+    fn from(v2: SnapshotV2) -> Snapshot {
+        Snapshot {
+            market_id: v2.market_id,
+            sequence: v2.sequence,
+            timestamp: v2.timestamp,
+            orderbook: v2.orderbook.into(),
+            next_trade_id: v2.next_trade_id,
+            rng_state: v2.rng_state,
+            phase: MarketPhase::default(),
+            user_volumes: HashMap::new(),
+        }
+    }
+}
+
+/// Version-1 layout, before the matching RNG state was persisted. Kept so
+/// old snapshot files stay loadable; the missing state restores as 0.
+#[derive(Deserialize)]
+struct SnapshotV1 {
+    market_id: String,
+    sequence: i64,
+    timestamp: i64,
+    orderbook: OrderbookV2,
+    next_trade_id: u64,
+}
+
+impl From<SnapshotV1> for Snapshot {
+    fn from(v1: SnapshotV1) -> Snapshot {
+        Snapshot {
+            market_id: v1.market_id,
+            sequence: v1.sequence,
+            timestamp: v1.timestamp,
+            orderbook: v1.orderbook.into(),
+            next_trade_id: v1.next_trade_id,
+            rng_state: 0,
+            phase: MarketPhase::default(),
+            user_volumes: HashMap::new(),
+        }
+    }
+}
+
+/// On-disk serialization format for snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    /// Compact binary (`.snap`).
+    #[default]
+    Bincode,
+    /// Human-readable JSON with decimals as strings (`.json`).
+    Json,
+    /// Same bytes as [`SnapshotFormat::Bincode`] (`.msnap`), but loaded
+    /// through a memory map: the deserializer reads the page cache
+    /// directly instead of an intermediate heap buffer, skipping the big
+    /// read-and-copy that dominates recovery of huge books.
+    Mmap,
+}
+
+impl SnapshotFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            SnapshotFormat::Bincode => "snap",
+            SnapshotFormat::Json => "json",
+            SnapshotFormat::Mmap => "msnap",
+        }
+    }
+
+    /// Detects the format from a file extension, if recognized.
+    pub fn from_path(path: &Path) -> Option<SnapshotFormat> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("snap") => Some(SnapshotFormat::Bincode),
+            Some("json") => Some(SnapshotFormat::Json),
+            Some("msnap") => Some(SnapshotFormat::Mmap),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bincode" | "snap" => Ok(SnapshotFormat::Bincode),
+            "json" => Ok(SnapshotFormat::Json),
+            "mmap" | "msnap" => Ok(SnapshotFormat::Mmap),
+            other => Err(format!("unknown snapshot format: {other}")),
+        }
+    }
+}
+
+pub struct SnapshotManager {
+    dir: PathBuf,
+    format: SnapshotFormat,
+    /// Additional attempts after a failed [`SnapshotManager::save`], with
+    /// exponential backoff in between. Covers transient directory
+    /// unavailability (e.g. a remounting volume); 0 fails fast.
+    save_retries: u32,
+}
+
+/// First backoff after a failed save attempt; doubles per retry.
+const SAVE_RETRY_BACKOFF_MS: u64 = 50;
+
+impl SnapshotManager {
+    /// Creates a manager writing the default (bincode) format.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_format(dir, SnapshotFormat::default())
+    }
+
+    pub fn with_format(dir: impl Into<PathBuf>, format: SnapshotFormat) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(SnapshotManager {
+            dir,
+            format,
+            save_retries: 2,
+        })
+    }
+
+    /// Overrides the number of save retries.
+    pub fn with_save_retries(mut self, save_retries: u32) -> Self {
+        self.save_retries = save_retries;
+        self
+    }
+
+    pub fn format(&self) -> SnapshotFormat {
+        self.format
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn snapshot_path(&self, market_id: &str, sequence: i64) -> PathBuf {
+        self.dir.join(format!(
+            "{market_id}-{sequence:020}.{}",
+            self.format.extension()
+        ))
+    }
+
+    /// Writes a snapshot atomically, returning its path. A failed write is
+    /// retried up to `save_retries` times with exponential backoff,
+    /// recreating the directory first in case it went away; persistent
+    /// failure is returned (and logged) rather than panicking, so a flaky
+    /// mount degrades checkpointing instead of killing the service.
+    pub fn save(&self, snapshot: &Snapshot) -> io::Result<PathBuf> {
+        let mut backoff = std::time::Duration::from_millis(SAVE_RETRY_BACKOFF_MS);
+        let mut attempt = 0;
+        loop {
+            match self.save_once(snapshot) {
+                Ok(path) => return Ok(path),
+                Err(e) if attempt < self.save_retries => {
+                    tracing::warn!(
+                        error = %e,
+                        attempt,
+                        "snapshot save failed; retrying after backoff"
+                    );
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn save_once(&self, snapshot: &Snapshot) -> io::Result<PathBuf> {
+        // The directory may have vanished since construction (unmounted,
+        // cleaned up); recreate rather than fail the checkpoint.
+        std::fs::create_dir_all(&self.dir)?;
+        let encoded = match self.format {
+            SnapshotFormat::Bincode | SnapshotFormat::Mmap => {
+                let mut buf = vec![SNAPSHOT_FORMAT_VERSION];
+                buf.extend(
+                    bincode::serialize(snapshot)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                );
+                buf
+            }
+            SnapshotFormat::Json => serde_json::to_vec_pretty(snapshot)?,
+        };
+
+        let path = self.snapshot_path(&snapshot.market_id, snapshot.sequence);
+        let tmp = path.with_extension("tmp");
+        {
+            let mut file = std::fs::File::create(&tmp)?;
+            file.write_all(&encoded)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    /// Decodes the versioned bincode layout shared by the `snap` and
+    /// `msnap` formats.
+    fn decode_bincode(data: &[u8]) -> io::Result<Snapshot> {
+        match data.split_first() {
+            Some((&SNAPSHOT_FORMAT_VERSION, payload)) => bincode::deserialize(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&1, payload)) => bincode::deserialize::<SnapshotV1>(payload)
+                .map(Snapshot::from)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&2, payload)) => bincode::deserialize::<SnapshotV2>(payload)
+                .map(Snapshot::from)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&3, payload)) => bincode::deserialize::<SnapshotV3>(payload)
+                .map(Snapshot::from)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&4, payload)) => bincode::deserialize::<SnapshotV4>(payload)
+                .map(Snapshot::from)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&5, payload)) => bincode::deserialize::<SnapshotV5>(payload)
+                .map(Snapshot::from)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some((&version, _)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported snapshot version {version}"),
+            )),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty snapshot file",
+            )),
+        }
+    }
+
+    /// Loads a snapshot, detecting the format from the file extension and
+    /// falling back to bincode for unrecognized extensions.
+    pub fn load(&self, path: &Path) -> io::Result<Snapshot> {
+        let format = SnapshotFormat::from_path(path).unwrap_or(SnapshotFormat::Bincode);
+        let result: io::Result<Snapshot> = match format {
+            SnapshotFormat::Bincode => Self::decode_bincode(&std::fs::read(path)?),
+            SnapshotFormat::Mmap => {
+                let file = std::fs::File::open(path)?;
+                // Safety: snapshots are written atomically (tmp + rename)
+                // and never modified in place afterwards, so the mapping
+                // cannot observe concurrent writes.
+                let mapped = unsafe { memmap2::Mmap::map(&file)? };
+                Self::decode_bincode(&mapped)
+            }
+            SnapshotFormat::Json => serde_json::from_slice(&std::fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        // The book digest is not persisted; restore it so loaded books are
+        // immediately comparable to live ones.
+        let mut snapshot = result?;
+        snapshot.orderbook.rebuild_digest();
+        Ok(snapshot)
+    }
+
+    /// All snapshot files as `(market_id, sequence, path)`, sorted by market
+    /// then sequence.
+    fn list(&self) -> io::Result<Vec<(String, i64, PathBuf)>> {
+        let mut found = Vec::new();
+        // A missing directory means no snapshots, not a failed recovery:
+        // the manager may point at a volume that has nothing to offer yet.
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(found),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if SnapshotFormat::from_path(&path).is_none() {
+                continue;
+            }
+            let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            // `{market_id}-{sequence:020}`; market ids may themselves
+            // contain '-', so split at the last one.
+            let Some((market, seq)) = stem.rsplit_once('-') else {
+                continue;
+            };
+            if let Ok(sequence) = seq.parse::<i64>() {
+                found.push((market.to_string(), sequence, path));
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// Sequence of the newest snapshot for a market, without loading it.
+    pub fn latest_sequence(&self, market_id: &str) -> io::Result<Option<i64>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|(m, _, _)| m == market_id)
+            .map(|(_, seq, _)| seq)
+            .max())
+    }
+
+    /// Latest snapshot for a market, if one exists.
+    pub fn load_latest(&self, market_id: &str) -> io::Result<Option<Snapshot>> {
+        let best = self
+            .list()?
+            .into_iter()
+            .filter(|(m, _, _)| m == market_id)
+            .max_by_key(|(_, seq, _)| *seq);
+        match best {
+            Some((_, _, path)) => Ok(Some(self.load(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Latest snapshot for every market present in the directory.
+    pub fn load_latest_all(&self) -> io::Result<Vec<Snapshot>> {
+        let mut latest: Vec<(String, i64, PathBuf)> = Vec::new();
+        for (market, seq, path) in self.list()? {
+            match latest.iter_mut().find(|(m, _, _)| *m == market) {
+                Some(slot) if slot.1 < seq => *slot = (market, seq, path),
+                Some(_) => {}
+                None => latest.push((market, seq, path)),
+            }
+        }
+        latest
+            .into_iter()
+            .map(|(_, _, path)| self.load(&path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{now_ns, Order, OrderStatus, OrderType, Side, TimeInForce};
+    use rust_decimal_macros::dec;
+    use tempfile::TempDir;
+
+    fn sample_snapshot() -> Snapshot {
+        let mut orderbook = Orderbook::new("BTC-USD");
+        orderbook.add_order(Order {
+            id: 1,
+            user_id: 7,
+            market_id: "BTC-USD".into(),
+            side: Side::Buy,
+            order_type: OrderType::Limit,
+            price: dec!(99.5),
+            quantity: dec!(2.25),
+            remaining_quantity: dec!(1.75),
+            status: OrderStatus::PartiallyFilled,
+            time_in_force: TimeInForce::Gtc,
+            all_or_none: false,
+            account_group: None,
+            public: true,
+            quantity_in_quote: false,
+            last_look: false,
+            expires_at: None,
+            client_order_id: Some("c-1".into()),
+            session_id: None,
+            sequence: 1,
+            timestamp: now_ns(),
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        });
+        Snapshot {
+            market_id: "BTC-USD".into(),
+            sequence: 42,
+            timestamp: now_ns(),
+            orderbook,
+            next_trade_id: 10,
+            rng_state: 99,
+            phase: MarketPhase::default(),
+            user_volumes: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn bincode_snapshot_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let manager = SnapshotManager::new(dir.path()).unwrap();
+        let snapshot = sample_snapshot();
+
+        let path = manager.save(&snapshot).unwrap();
+        assert_eq!(path.extension().unwrap(), "snap");
+        assert_eq!(manager.load(&path).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn bincode_snapshot_preserves_decimal_scale_exactly() {
+        let dir = TempDir::new().unwrap();
+        let manager = SnapshotManager::new(dir.path()).unwrap();
+        let mut snapshot = sample_snapshot();
+        let mut order = snapshot.orderbook.get_order(1).unwrap().clone();
+        // Trailing zeros survive only under the pinned decimal encoding.
+        order.remaining_quantity = dec!(1.750);
+        snapshot.orderbook.update_order(&order);
+
+        let path = manager.save(&snapshot).unwrap();
+        let loaded = manager.load(&path).unwrap();
+        let restored = loaded.orderbook.get_order(1).unwrap();
+        assert_eq!(restored.remaining_quantity.scale(), 3);
+        assert_eq!(
+            restored.remaining_quantity.serialize(),
+            order.remaining_quantity.serialize()
+        );
+    }
+
+    #[test]
+    fn json_snapshot_round_trips_and_is_readable() {
+        let dir = TempDir::new().unwrap();
+        let manager = SnapshotManager::with_format(dir.path(), SnapshotFormat::Json).unwrap();
+        let snapshot = sample_snapshot();
+
+        let path = manager.save(&snapshot).unwrap();
+        assert_eq!(path.extension().unwrap(), "json");
+
+        // Decimals are serialized as strings for human inspection.
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("\"99.5\""));
+
+        assert_eq!(manager.load(&path).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn mapped_snapshot_loads_the_same_book_as_bincode() {
+        let dir = TempDir::new().unwrap();
+        let snapshot = sample_snapshot();
+        let bin_path = SnapshotManager::new(dir.path())
+            .unwrap()
+            .save(&snapshot)
+            .unwrap();
+        let mapped_manager =
+            SnapshotManager::with_format(dir.path(), SnapshotFormat::Mmap).unwrap();
+        let mapped_path = mapped_manager.save(&snapshot).unwrap();
+        assert_eq!(mapped_path.extension().unwrap(), "msnap");
+
+        // Same bytes, different load path: the mapped file decodes to a
+        // book identical to the bincode one.
+        let bincode_loaded = mapped_manager.load(&bin_path).unwrap();
+        let mapped_loaded = mapped_manager.load(&mapped_path).unwrap();
+        assert_eq!(mapped_loaded, bincode_loaded);
+        assert_eq!(
+            mapped_loaded.orderbook.digest(),
+            bincode_loaded.orderbook.digest()
+        );
+    }
+
+    #[test]
+    fn load_detects_format_from_extension_regardless_of_config() {
+        let dir = TempDir::new().unwrap();
+        let json_manager = SnapshotManager::with_format(dir.path(), SnapshotFormat::Json).unwrap();
+        let snapshot = sample_snapshot();
+        let path = json_manager.save(&snapshot).unwrap();
+
+        // A bincode-configured manager can still read the JSON file.
+        let bin_manager = SnapshotManager::new(dir.path()).unwrap();
+        assert_eq!(bin_manager.load(&path).unwrap(), snapshot);
+        assert_eq!(
+            bin_manager.load_latest("BTC-USD").unwrap().unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn missing_directory_reads_as_no_snapshots() {
+        let dir = TempDir::new().unwrap();
+        let nested = dir.path().join("not-mounted");
+        let manager = SnapshotManager::new(&nested).unwrap();
+        std::fs::remove_dir_all(&nested).unwrap();
+
+        // A vanished directory is "nothing snapshotted", not a recovery
+        // failure.
+        assert_eq!(manager.latest_sequence("BTC-USD").unwrap(), None);
+        assert!(manager.load_latest("BTC-USD").unwrap().is_none());
+        assert!(manager.load_latest_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn save_recreates_a_vanished_directory() {
+        let dir = TempDir::new().unwrap();
+        let nested = dir.path().join("snapshots");
+        let manager = SnapshotManager::new(&nested).unwrap();
+        std::fs::remove_dir_all(&nested).unwrap();
+
+        let path = manager.save(&sample_snapshot()).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn load_latest_picks_highest_sequence() {
+        let dir = TempDir::new().unwrap();
+        let manager = SnapshotManager::new(dir.path()).unwrap();
+        let mut snapshot = sample_snapshot();
+        manager.save(&snapshot).unwrap();
+        snapshot.sequence = 100;
+        manager.save(&snapshot).unwrap();
+
+        let latest = manager.load_latest("BTC-USD").unwrap().unwrap();
+        assert_eq!(latest.sequence, 100);
+        assert!(manager.load_latest("ETH-USD").unwrap().is_none());
+    }
+}
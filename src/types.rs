@@ -0,0 +1,324 @@
+//! Core domain types shared by the orderbook, WAL and snapshots.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type OrderId = u64;
+pub type UserId = u64;
+
+/// Current wall-clock time in nanoseconds since the Unix epoch.
+pub fn now_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before Unix epoch")
+        .as_nanos() as i64
+}
+
+/// Wall-clock source whose readings never go backward: if the system clock
+/// steps back (e.g. an NTP adjustment), the next reading clamps to the last
+/// issued timestamp + 1. Use for priority timestamps, where a regression
+/// would let a later-received order sort ahead of an earlier one; reporting
+/// timestamps should keep using the raw [`now_ns`].
+#[derive(Debug, Default, Clone)]
+pub struct MonotonicClock {
+    last_ns: i64,
+}
+
+impl MonotonicClock {
+    pub fn now_ns(&mut self) -> i64 {
+        self.tick(now_ns())
+    }
+
+    /// Clamps `wall_ns` against the last issued reading; separate from
+    /// [`MonotonicClock::now_ns`] so tests can simulate clock steps.
+    pub fn tick(&mut self, wall_ns: i64) -> i64 {
+        self.last_ns = if wall_ns > self.last_ns {
+            wall_ns
+        } else {
+            self.last_ns + 1
+        };
+        self.last_ns
+    }
+}
+
+/// Stable serde encoding for the `Decimal` fields the WAL and snapshots
+/// persist, pinned independently of `rust_decimal`'s default representation
+/// (which is not guaranteed across dependency upgrades). Human-readable
+/// formats keep the canonical string, so JSON snapshots and the audit log
+/// are unchanged; binary formats use the crate's documented 16-byte
+/// mantissa-plus-scale layout, which round-trips bit-exactly. Any change
+/// here is an on-disk format change and must bump both the WAL and
+/// snapshot versions.
+pub mod stable_decimal {
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            value.to_string().serialize(serializer)
+        } else {
+            serializer.serialize_bytes(&value.serialize())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = String::deserialize(deserializer)?;
+            text.parse().map_err(serde::de::Error::custom)
+        } else {
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
+    struct BytesVisitor;
+
+    impl serde::de::Visitor<'_> for BytesVisitor {
+        type Value = Decimal;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("16 bytes of packed decimal")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Decimal, E> {
+            let bytes: [u8; 16] = v
+                .try_into()
+                .map_err(|_| E::invalid_length(v.len(), &self))?;
+            Ok(Decimal::deserialize(bytes))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderType {
+    Limit,
+    Market,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Good till cancelled (default).
+    #[default]
+    Gtc,
+    /// Immediate or cancel: any unfilled remainder is cancelled.
+    Ioc,
+    /// Good till date: rests until `expires_at` passes.
+    Gtd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Filled | OrderStatus::Cancelled | OrderStatus::Expired | OrderStatus::Rejected
+        )
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::New => "NEW",
+            OrderStatus::PartiallyFilled => "PARTIALLY_FILLED",
+            OrderStatus::Filled => "FILLED",
+            OrderStatus::Cancelled => "CANCELLED",
+            OrderStatus::Expired => "EXPIRED",
+            OrderStatus::Rejected => "REJECTED",
+        }
+    }
+}
+
+fn default_public() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Order {
+    pub id: OrderId,
+    pub user_id: UserId,
+    pub market_id: String,
+    pub side: Side,
+    pub order_type: OrderType,
+    /// Limit price; zero for market orders.
+    #[serde(with = "stable_decimal")]
+    pub price: Decimal,
+    #[serde(with = "stable_decimal")]
+    pub quantity: Decimal,
+    #[serde(with = "stable_decimal")]
+    pub remaining_quantity: Decimal,
+    pub status: OrderStatus,
+    pub time_in_force: TimeInForce,
+    /// All-or-none: while resting, only matches takers that can consume the
+    /// entire remaining quantity in one fill.
+    #[serde(default)]
+    pub all_or_none: bool,
+    /// Self-trade prevention scope: orders sharing a group never match each
+    /// other, regardless of user id. `None` falls back to exact user match.
+    #[serde(default)]
+    pub account_group: Option<String>,
+    /// When false, trades this order takes are kept off the public trade
+    /// tape (they are still journaled and counted in VWAP). Used for hidden
+    /// IOC liquidity probes.
+    #[serde(default = "default_public")]
+    pub public: bool,
+    /// Market orders only: `quantity` is quote notional to spend rather
+    /// than base quantity; the engine sweeps until the notional is
+    /// exhausted, partially filling the last level if needed.
+    #[serde(default)]
+    pub quantity_in_quote: bool,
+    /// RFQ-style last-look protection: while this resting quote is younger
+    /// than the market's last-look window, an incoming match cancels it
+    /// instead of trading, and the taker moves on to the next maker.
+    #[serde(default)]
+    pub last_look: bool,
+    /// Nanosecond expiry for GTD orders.
+    pub expires_at: Option<i64>,
+    pub client_order_id: Option<String>,
+    /// Cancel-on-disconnect session this order belongs to, if any.
+    pub session_id: Option<String>,
+    /// Engine-assigned monotonic sequence, used for deterministic tie-breaks.
+    pub sequence: u64,
+    /// Nanosecond acceptance timestamp.
+    pub timestamp: i64,
+    /// Pegged limit: the reference price this order tracks. While resting
+    /// the engine re-prices the order whenever the reference moves; `None`
+    /// is an ordinary limit (WAL format 5).
+    #[serde(default)]
+    pub peg: Option<PegReference>,
+    /// Peg offset in basis points of the reference price, signed: a buy
+    /// pegged to mid at -50 bids half a percent below mid. Ignored when
+    /// `peg` is `None`.
+    #[serde(default, with = "stable_decimal")]
+    pub peg_offset_bps: Decimal,
+}
+
+impl Order {
+    pub fn filled_quantity(&self) -> Decimal {
+        self.quantity - self.remaining_quantity
+    }
+}
+
+/// Reference price a pegged limit tracks; see [`Order::peg`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PegReference {
+    /// The best resting bid.
+    Bid,
+    /// The best resting ask.
+    Ask,
+    /// The midpoint of the best bid and ask; undefined (no repricing) while
+    /// either side is empty.
+    Mid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    /// Per-market monotonic trade id.
+    pub id: u64,
+    pub market_id: String,
+    #[serde(with = "stable_decimal")]
+    pub price: Decimal,
+    #[serde(with = "stable_decimal")]
+    pub quantity: Decimal,
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub maker_user_id: UserId,
+    pub taker_user_id: UserId,
+    pub timestamp: i64,
+    /// Side of the aggressing (taker) order. `None` only on trades
+    /// journaled before the aggressor was recorded (WAL format < 4).
+    pub aggressor: Option<Side>,
+}
+
+/// Which side of a trade a leg sat on, for fee reporting: the maker leg
+/// provided the liquidity the taker leg removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Trade {
+    /// Whether the taker bought; `None` on pre-aggressor journaled trades.
+    pub fn taker_is_buyer(&self) -> Option<bool> {
+        self.aggressor.map(|side| side == Side::Buy)
+    }
+
+    /// The liquidity flag of one leg of this trade, or `None` when the
+    /// order was not part of it.
+    pub fn liquidity_of(&self, order_id: OrderId) -> Option<Liquidity> {
+        if order_id == self.maker_order_id {
+            Some(Liquidity::Maker)
+        } else if order_id == self.taker_order_id {
+            Some(Liquidity::Taker)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wrapped {
+        #[serde(with = "stable_decimal")]
+        value: Decimal,
+    }
+
+    #[test]
+    fn stable_decimal_round_trips_bit_exactly_through_bincode() {
+        // Trailing-zero scale distinguishes representations that compare
+        // equal: 100.500 must come back as 100.500, not 100.5.
+        let original = Wrapped { value: dec!(100.500) };
+        let bytes = bincode::serialize(&original).unwrap();
+        let restored: Wrapped = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(restored, original);
+        assert_eq!(restored.value.scale(), 3);
+        assert_eq!(restored.value.serialize(), original.value.serialize());
+    }
+
+    #[test]
+    fn stable_decimal_keeps_the_string_form_in_json() {
+        let wrapped = Wrapped { value: dec!(99.50) };
+        let json = serde_json::to_string(&wrapped).unwrap();
+        assert_eq!(json, r#"{"value":"99.50"}"#);
+        assert_eq!(serde_json::from_str::<Wrapped>(&json).unwrap(), wrapped);
+    }
+
+    #[test]
+    fn monotonic_clock_absorbs_backward_clock_steps() {
+        let mut clock = MonotonicClock::default();
+        assert_eq!(clock.tick(100), 100);
+        assert_eq!(clock.tick(200), 200);
+        // The wall clock steps back; priority timestamps keep advancing.
+        assert_eq!(clock.tick(50), 201);
+        assert_eq!(clock.tick(50), 202);
+        // Once wall time passes the clamp, readings track it again.
+        assert_eq!(clock.tick(300), 300);
+    }
+}
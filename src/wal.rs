@@ -0,0 +1,1652 @@
+//! Write-ahead log.
+//!
+//! Every accepted command is appended as a bincode-encoded, length-prefixed
+//! [`WalEntry`] carrying two sequence numbers: a globally monotonic WAL
+//! offset and a per-market event sequence. The log is split into
+//! segments keyed by their first sequence; a segment is closed and a new one
+//! started once it exceeds the configured size. Recovery replays entries
+//! from a snapshot's sequence via [`WAL::read_from`].
+//!
+//! Segment storage sits behind [`WalBackend`]: production uses
+//! [`FileWalBackend`] (`wal-{first_sequence}.log` files); tests can use
+//! [`MemoryWalBackend`] to exercise WAL behavior without touching disk.
+
+use crate::engine::MarketPhase;
+use crate::types::{now_ns, Order, Trade};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_PREFIX: &str = "wal-";
+const SEGMENT_SUFFIX: &str = ".log";
+
+/// On-disk record format version: the byte between each record's length
+/// prefix and its bincode payload. Bump this when [`WalEntry`] or
+/// [`WalOperation`] change incompatibly and keep a decode arm for every
+/// historical version in [`WAL::decode_entry`], so old segments stay
+/// readable across schema evolution.
+pub const WAL_FORMAT_VERSION: u8 = 6;
+
+/// How many idempotency keys the recently-seen set retains (oldest evicted
+/// first). A retry window, not a permanent registry: retries arriving after
+/// this many keyed appends are no longer deduplicated.
+pub const IDEMPOTENCY_WINDOW: usize = 4096;
+
+// New operation kinds may be introduced by later format versions, so
+// downstream matches (e.g. the replay tool) must carry a wildcard arm.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WalOperation {
+    PlaceOrder(Order),
+    CancelOrder {
+        market_id: String,
+        order_id: u64,
+    },
+    AmendOrder {
+        market_id: String,
+        order_id: u64,
+        #[serde(with = "crate::types::stable_decimal")]
+        new_price: Decimal,
+        #[serde(with = "crate::types::stable_decimal")]
+        new_quantity: Decimal,
+        sequence: u64,
+    },
+    /// In-place size reduction that keeps queue priority.
+    ReduceOrder {
+        market_id: String,
+        order_id: u64,
+        #[serde(with = "crate::types::stable_decimal")]
+        reduce_by: Decimal,
+    },
+    /// Audit record; replay regenerates trades from commands and skips these.
+    TradeExecuted(Trade),
+    /// Audit record marking a resting maker's transition to fully filled,
+    /// emitted exactly once per order; skipped in replay like
+    /// [`WalOperation::TradeExecuted`].
+    OrderFilled {
+        market_id: String,
+        order_id: u64,
+        #[serde(with = "crate::types::stable_decimal")]
+        filled_quantity: Decimal,
+    },
+    /// Audit record for a pegged order moved to a new level. Repricing is
+    /// deterministic from the commands, so replay regenerates it and skips
+    /// these like [`WalOperation::TradeExecuted`].
+    OrderRepriced {
+        market_id: String,
+        order_id: u64,
+        #[serde(with = "crate::types::stable_decimal")]
+        new_price: Decimal,
+    },
+    /// Aggregated audit record: every fill one taker produced in a single
+    /// matching pass, in fill order. Written instead of per-trade
+    /// [`WalOperation::TradeExecuted`] entries when `wal_aggregate_trades`
+    /// is set, so a deep sweep costs one append; skipped in replay the same
+    /// way.
+    OrderMatched {
+        market_id: String,
+        taker_order_id: u64,
+        trades: Vec<Trade>,
+    },
+    /// Session-phase transition (auction schedule). A command, not an audit
+    /// record: replay must apply it, or orders that accumulated through an
+    /// auction call would match continuously instead.
+    SetPhase {
+        market_id: String,
+        phase: MarketPhase,
+    },
+}
+
+impl WalOperation {
+    /// The market this operation belongs to. Every operation carries one;
+    /// the WAL uses it to assign per-market sequences.
+    pub fn market_id(&self) -> &str {
+        match self {
+            WalOperation::PlaceOrder(order) => &order.market_id,
+            WalOperation::CancelOrder { market_id, .. }
+            | WalOperation::AmendOrder { market_id, .. }
+            | WalOperation::ReduceOrder { market_id, .. }
+            | WalOperation::OrderFilled { market_id, .. }
+            | WalOperation::OrderRepriced { market_id, .. }
+            | WalOperation::OrderMatched { market_id, .. }
+            | WalOperation::SetPhase { market_id, .. } => market_id,
+            WalOperation::TradeExecuted(trade) => &trade.market_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// Global WAL offset: this entry's position in the log across all
+    /// markets. Used for truncation and snapshot boundaries.
+    pub sequence: i64,
+    /// Per-market event sequence, monotonic within one market's stream and
+    /// independent of other markets. Recovery orders each market's replay by
+    /// this. 0 on records written before format version 2; consumers fall
+    /// back to the global offset for those.
+    pub market_sequence: i64,
+    pub timestamp: i64,
+    /// Client-supplied retry deduplication key, if the append was keyed.
+    /// Persisted so the recently-seen set survives a restart.
+    pub idempotency_key: Option<String>,
+    pub operation: WalOperation,
+}
+
+/// [`Order`] as serialized by WAL formats 1 through 4 (and snapshot formats
+/// 1 and 2), before peg parameters existed.
+#[derive(Deserialize)]
+pub(crate) struct OrderV4 {
+    id: u64,
+    user_id: u64,
+    market_id: String,
+    side: crate::types::Side,
+    order_type: crate::types::OrderType,
+    price: Decimal,
+    quantity: Decimal,
+    remaining_quantity: Decimal,
+    status: crate::types::OrderStatus,
+    time_in_force: crate::types::TimeInForce,
+    all_or_none: bool,
+    account_group: Option<String>,
+    public: bool,
+    quantity_in_quote: bool,
+    last_look: bool,
+    expires_at: Option<i64>,
+    client_order_id: Option<String>,
+    session_id: Option<String>,
+    sequence: u64,
+    timestamp: i64,
+}
+
+impl From<OrderV4> for Order {
+    fn from(v4: OrderV4) -> Order {
+        Order {
+            id: v4.id,
+            user_id: v4.user_id,
+            market_id: v4.market_id,
+            side: v4.side,
+            order_type: v4.order_type,
+            price: v4.price,
+            quantity: v4.quantity,
+            remaining_quantity: v4.remaining_quantity,
+            status: v4.status,
+            time_in_force: v4.time_in_force,
+            all_or_none: v4.all_or_none,
+            account_group: v4.account_group,
+            public: v4.public,
+            quantity_in_quote: v4.quantity_in_quote,
+            last_look: v4.last_look,
+            expires_at: v4.expires_at,
+            client_order_id: v4.client_order_id,
+            session_id: v4.session_id,
+            sequence: v4.sequence,
+            timestamp: v4.timestamp,
+            // Pegs did not exist before format 5.
+            peg: None,
+            peg_offset_bps: Decimal::ZERO,
+        }
+    }
+}
+
+/// [`Trade`] as serialized by WAL formats 1 through 3, before the aggressor
+/// side was recorded.
+#[derive(Deserialize)]
+struct TradeV3 {
+    id: u64,
+    market_id: String,
+    price: Decimal,
+    quantity: Decimal,
+    maker_order_id: u64,
+    taker_order_id: u64,
+    maker_user_id: u64,
+    taker_user_id: u64,
+    timestamp: i64,
+}
+
+impl From<TradeV3> for Trade {
+    fn from(v3: TradeV3) -> Trade {
+        Trade {
+            id: v3.id,
+            market_id: v3.market_id,
+            price: v3.price,
+            quantity: v3.quantity,
+            maker_order_id: v3.maker_order_id,
+            taker_order_id: v3.taker_order_id,
+            maker_user_id: v3.maker_user_id,
+            taker_user_id: v3.taker_user_id,
+            timestamp: v3.timestamp,
+            // Not recorded before format 4.
+            aggressor: None,
+        }
+    }
+}
+
+/// [`WalOperation`] as serialized by formats 1 through 3: identical variant
+/// layout, but orders in the old [`OrderV4`] shape and audit trades in the
+/// old [`TradeV3`] shape.
+#[derive(Deserialize)]
+enum WalOperationV3 {
+    PlaceOrder(OrderV4),
+    CancelOrder {
+        market_id: String,
+        order_id: u64,
+    },
+    AmendOrder {
+        market_id: String,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+        sequence: u64,
+    },
+    ReduceOrder {
+        market_id: String,
+        order_id: u64,
+        reduce_by: Decimal,
+    },
+    TradeExecuted(TradeV3),
+    OrderFilled {
+        market_id: String,
+        order_id: u64,
+        filled_quantity: Decimal,
+    },
+}
+
+impl From<WalOperationV3> for WalOperation {
+    fn from(v3: WalOperationV3) -> WalOperation {
+        match v3 {
+            WalOperationV3::PlaceOrder(order) => WalOperation::PlaceOrder(order.into()),
+            WalOperationV3::CancelOrder {
+                market_id,
+                order_id,
+            } => WalOperation::CancelOrder {
+                market_id,
+                order_id,
+            },
+            WalOperationV3::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            } => WalOperation::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            },
+            WalOperationV3::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            } => WalOperation::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            },
+            WalOperationV3::TradeExecuted(trade) => WalOperation::TradeExecuted(trade.into()),
+            WalOperationV3::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            } => WalOperation::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            },
+        }
+    }
+}
+
+/// Format-3 entry layout; only the operation's trade shape differs from the
+/// current entry.
+#[derive(Deserialize)]
+struct WalEntryV3 {
+    sequence: i64,
+    market_sequence: i64,
+    timestamp: i64,
+    idempotency_key: Option<String>,
+    operation: WalOperationV3,
+}
+
+/// [`WalOperation`] as serialized by format 4: trades in the [`TradeV5`]
+/// shape, orders still in the old [`OrderV4`] shape and no repricing
+/// records.
+#[derive(Deserialize)]
+enum WalOperationV4 {
+    PlaceOrder(OrderV4),
+    CancelOrder {
+        market_id: String,
+        order_id: u64,
+    },
+    AmendOrder {
+        market_id: String,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+        sequence: u64,
+    },
+    ReduceOrder {
+        market_id: String,
+        order_id: u64,
+        reduce_by: Decimal,
+    },
+    TradeExecuted(TradeV5),
+    OrderFilled {
+        market_id: String,
+        order_id: u64,
+        filled_quantity: Decimal,
+    },
+}
+
+impl From<WalOperationV4> for WalOperation {
+    fn from(v4: WalOperationV4) -> WalOperation {
+        match v4 {
+            WalOperationV4::PlaceOrder(order) => WalOperation::PlaceOrder(order.into()),
+            WalOperationV4::CancelOrder {
+                market_id,
+                order_id,
+            } => WalOperation::CancelOrder {
+                market_id,
+                order_id,
+            },
+            WalOperationV4::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            } => WalOperation::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            },
+            WalOperationV4::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            } => WalOperation::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            },
+            WalOperationV4::TradeExecuted(trade) => WalOperation::TradeExecuted(trade.into()),
+            WalOperationV4::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            } => WalOperation::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            },
+        }
+    }
+}
+
+/// Format-4 entry layout; only the operation's order shape differs from the
+/// current entry.
+#[derive(Deserialize)]
+struct WalEntryV4 {
+    sequence: i64,
+    market_sequence: i64,
+    timestamp: i64,
+    idempotency_key: Option<String>,
+    operation: WalOperationV4,
+}
+
+/// [`Order`] as serialized by WAL format 5 (and snapshot formats 3 and 4):
+/// the current field set, but every `Decimal` in the dependency's default
+/// encoding rather than the pinned [`crate::types::stable_decimal`] one.
+#[derive(Deserialize)]
+pub(crate) struct OrderV5 {
+    id: u64,
+    user_id: u64,
+    market_id: String,
+    side: crate::types::Side,
+    order_type: crate::types::OrderType,
+    price: Decimal,
+    quantity: Decimal,
+    remaining_quantity: Decimal,
+    status: crate::types::OrderStatus,
+    time_in_force: crate::types::TimeInForce,
+    all_or_none: bool,
+    account_group: Option<String>,
+    public: bool,
+    quantity_in_quote: bool,
+    last_look: bool,
+    expires_at: Option<i64>,
+    client_order_id: Option<String>,
+    session_id: Option<String>,
+    sequence: u64,
+    timestamp: i64,
+    peg: Option<crate::types::PegReference>,
+    peg_offset_bps: Decimal,
+}
+
+impl From<OrderV5> for Order {
+    fn from(v5: OrderV5) -> Order {
+        Order {
+            id: v5.id,
+            user_id: v5.user_id,
+            market_id: v5.market_id,
+            side: v5.side,
+            order_type: v5.order_type,
+            price: v5.price,
+            quantity: v5.quantity,
+            remaining_quantity: v5.remaining_quantity,
+            status: v5.status,
+            time_in_force: v5.time_in_force,
+            all_or_none: v5.all_or_none,
+            account_group: v5.account_group,
+            public: v5.public,
+            quantity_in_quote: v5.quantity_in_quote,
+            last_look: v5.last_look,
+            expires_at: v5.expires_at,
+            client_order_id: v5.client_order_id,
+            session_id: v5.session_id,
+            sequence: v5.sequence,
+            timestamp: v5.timestamp,
+            peg: v5.peg,
+            peg_offset_bps: v5.peg_offset_bps,
+        }
+    }
+}
+
+/// [`Trade`] as serialized by WAL formats 4 and 5: the current field set
+/// with default-encoded decimals, like [`OrderV5`].
+#[derive(Deserialize)]
+pub(crate) struct TradeV5 {
+    id: u64,
+    market_id: String,
+    price: Decimal,
+    quantity: Decimal,
+    maker_order_id: u64,
+    taker_order_id: u64,
+    maker_user_id: u64,
+    taker_user_id: u64,
+    timestamp: i64,
+    aggressor: Option<crate::types::Side>,
+}
+
+impl From<TradeV5> for Trade {
+    fn from(v5: TradeV5) -> Trade {
+        Trade {
+            id: v5.id,
+            market_id: v5.market_id,
+            price: v5.price,
+            quantity: v5.quantity,
+            maker_order_id: v5.maker_order_id,
+            taker_order_id: v5.taker_order_id,
+            maker_user_id: v5.maker_user_id,
+            taker_user_id: v5.taker_user_id,
+            timestamp: v5.timestamp,
+            aggressor: v5.aggressor,
+        }
+    }
+}
+
+/// [`WalOperation`] as serialized by format 5: the current variant set with
+/// orders, trades and amounts in their default decimal encoding.
+#[derive(Deserialize)]
+enum WalOperationV5 {
+    PlaceOrder(OrderV5),
+    CancelOrder {
+        market_id: String,
+        order_id: u64,
+    },
+    AmendOrder {
+        market_id: String,
+        order_id: u64,
+        new_price: Decimal,
+        new_quantity: Decimal,
+        sequence: u64,
+    },
+    ReduceOrder {
+        market_id: String,
+        order_id: u64,
+        reduce_by: Decimal,
+    },
+    TradeExecuted(TradeV5),
+    OrderFilled {
+        market_id: String,
+        order_id: u64,
+        filled_quantity: Decimal,
+    },
+    OrderRepriced {
+        market_id: String,
+        order_id: u64,
+        new_price: Decimal,
+    },
+    OrderMatched {
+        market_id: String,
+        taker_order_id: u64,
+        trades: Vec<TradeV5>,
+    },
+    SetPhase {
+        market_id: String,
+        phase: MarketPhase,
+    },
+}
+
+impl From<WalOperationV5> for WalOperation {
+    fn from(v5: WalOperationV5) -> WalOperation {
+        match v5 {
+            WalOperationV5::PlaceOrder(order) => WalOperation::PlaceOrder(order.into()),
+            WalOperationV5::CancelOrder {
+                market_id,
+                order_id,
+            } => WalOperation::CancelOrder {
+                market_id,
+                order_id,
+            },
+            WalOperationV5::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            } => WalOperation::AmendOrder {
+                market_id,
+                order_id,
+                new_price,
+                new_quantity,
+                sequence,
+            },
+            WalOperationV5::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            } => WalOperation::ReduceOrder {
+                market_id,
+                order_id,
+                reduce_by,
+            },
+            WalOperationV5::TradeExecuted(trade) => WalOperation::TradeExecuted(trade.into()),
+            WalOperationV5::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            } => WalOperation::OrderFilled {
+                market_id,
+                order_id,
+                filled_quantity,
+            },
+            WalOperationV5::OrderRepriced {
+                market_id,
+                order_id,
+                new_price,
+            } => WalOperation::OrderRepriced {
+                market_id,
+                order_id,
+                new_price,
+            },
+            WalOperationV5::OrderMatched {
+                market_id,
+                taker_order_id,
+                trades,
+            } => WalOperation::OrderMatched {
+                market_id,
+                taker_order_id,
+                trades: trades.into_iter().map(Trade::from).collect(),
+            },
+            WalOperationV5::SetPhase { market_id, phase } => {
+                WalOperation::SetPhase { market_id, phase }
+            }
+        }
+    }
+}
+
+/// Format-5 entry layout; only the decimal encoding differs from the
+/// current entry.
+#[derive(Deserialize)]
+struct WalEntryV5 {
+    sequence: i64,
+    market_sequence: i64,
+    timestamp: i64,
+    idempotency_key: Option<String>,
+    operation: WalOperationV5,
+}
+
+/// Version-2 on-disk layout, before `idempotency_key` existed.
+#[derive(Deserialize)]
+struct WalEntryV2 {
+    sequence: i64,
+    market_sequence: i64,
+    timestamp: i64,
+    operation: WalOperationV3,
+}
+
+/// Version-1 on-disk layout, before `market_sequence` existed.
+#[derive(Deserialize)]
+struct WalEntryV1 {
+    sequence: i64,
+    timestamp: i64,
+    operation: WalOperationV3,
+}
+
+/// Storage behind a [`WAL`]: an ordered set of append-only segments keyed by
+/// their first sequence. Framing and decoding stay in [`WAL`]; a backend only
+/// moves bytes.
+pub trait WalBackend: Send {
+    /// First sequences of existing segments, ascending.
+    fn segments(&self) -> io::Result<Vec<i64>>;
+    /// Full raw contents of the segment starting at `first_sequence`.
+    fn read_segment(&self, first_sequence: i64) -> io::Result<Vec<u8>>;
+    /// Makes the segment starting at `first_sequence` the write target,
+    /// creating it if absent, and returns its current length in bytes.
+    fn open_segment(&mut self, first_sequence: i64) -> io::Result<u64>;
+    /// Buffers one framed record into the open segment; durability is
+    /// deferred until [`WalBackend::sync`].
+    fn write(&mut self, record: &[u8]) -> io::Result<()>;
+    /// Makes all previously written records durable. Called once per append
+    /// (or once per batch under group commit).
+    fn sync(&mut self) -> io::Result<()>;
+    /// Deletes the segment starting at `first_sequence`.
+    fn remove_segment(&mut self, first_sequence: i64) -> io::Result<()>;
+    /// Length in bytes of the segment starting at `first_sequence`. The
+    /// default reads the whole segment; backends with cheaper metadata
+    /// should override.
+    fn segment_len(&self, first_sequence: i64) -> io::Result<u64> {
+        Ok(self.read_segment(first_sequence)?.len() as u64)
+    }
+}
+
+/// Production backend: one `wal-{first_sequence}.log` file per segment,
+/// appends synced to disk.
+pub struct FileWalBackend {
+    dir: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl FileWalBackend {
+    /// Creates `dir` if needed; no segment is opened until
+    /// [`WalBackend::open_segment`].
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileWalBackend { dir, writer: None })
+    }
+
+    fn segment_path(&self, first_sequence: i64) -> PathBuf {
+        WAL::segment_path(&self.dir, first_sequence)
+    }
+}
+
+impl WalBackend for FileWalBackend {
+    fn segments(&self) -> io::Result<Vec<i64>> {
+        Ok(WAL::list_segments(&self.dir)?
+            .into_iter()
+            .map(|(first, _)| first)
+            .collect())
+    }
+
+    fn read_segment(&self, first_sequence: i64) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        File::open(self.segment_path(first_sequence))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn open_segment(&mut self, first_sequence: i64) -> io::Result<u64> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        let path = self.segment_path(first_sequence);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        self.writer = Some(BufWriter::new(file));
+        Ok(len)
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no open WAL segment"))?
+            .write_all(record)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("no open WAL segment"))?;
+        writer.flush()?;
+        writer.get_ref().sync_data()
+    }
+
+    fn remove_segment(&mut self, first_sequence: i64) -> io::Result<()> {
+        std::fs::remove_file(self.segment_path(first_sequence))
+    }
+
+    fn segment_len(&self, first_sequence: i64) -> io::Result<u64> {
+        Ok(std::fs::metadata(self.segment_path(first_sequence))?.len())
+    }
+}
+
+/// Test backend: segments held in memory, so WAL behavior can be exercised
+/// quickly and in isolation from the filesystem.
+#[derive(Default)]
+pub struct MemoryWalBackend {
+    segments: BTreeMap<i64, Vec<u8>>,
+    open: Option<i64>,
+}
+
+impl WalBackend for MemoryWalBackend {
+    fn segments(&self) -> io::Result<Vec<i64>> {
+        Ok(self.segments.keys().copied().collect())
+    }
+
+    fn read_segment(&self, first_sequence: i64) -> io::Result<Vec<u8>> {
+        self.segments
+            .get(&first_sequence)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such WAL segment"))
+    }
+
+    fn open_segment(&mut self, first_sequence: i64) -> io::Result<u64> {
+        let len = self.segments.entry(first_sequence).or_default().len();
+        self.open = Some(first_sequence);
+        Ok(len as u64)
+    }
+
+    fn write(&mut self, record: &[u8]) -> io::Result<()> {
+        let open = self
+            .open
+            .ok_or_else(|| io::Error::other("no open WAL segment"))?;
+        self.segments
+            .get_mut(&open)
+            .expect("open segment exists")
+            .extend_from_slice(record);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn remove_segment(&mut self, first_sequence: i64) -> io::Result<()> {
+        self.segments.remove(&first_sequence);
+        Ok(())
+    }
+}
+
+/// Encodes one entry with the standard record framing — length prefix,
+/// version byte, bincode payload — exactly as it lands in a segment. Used
+/// both for appends and for shipping entries over a replication stream;
+/// peers decode with [`decode_records`].
+pub fn encode_record(entry: &WalEntry) -> io::Result<Vec<u8>> {
+    let encoded =
+        bincode::serialize(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut record = Vec::with_capacity(5 + encoded.len());
+    record.extend_from_slice(&(1 + encoded.len() as u32).to_le_bytes());
+    record.push(WAL_FORMAT_VERSION);
+    record.extend_from_slice(&encoded);
+    Ok(record)
+}
+
+/// Decodes a buffer of framed records; the inverse of [`encode_record`] and
+/// the same decoding segments go through, including old-version records.
+pub fn decode_records(data: &[u8]) -> io::Result<Vec<WalEntry>> {
+    WAL::decode_segment(data)
+}
+
+pub struct WAL {
+    backend: Box<dyn WalBackend>,
+    segment_max_bytes: u64,
+    current_segment_bytes: u64,
+    next_sequence: i64,
+    /// Set when async appends have been written but not yet synced; cleared
+    /// by the next sync (explicit flush or a durable batch).
+    pending_sync: bool,
+    /// Last assigned per-market sequence for each market, seeded from the
+    /// log at open.
+    market_sequences: HashMap<String, i64>,
+    /// Recently-seen idempotency keys mapped to their original sequence,
+    /// bounded by [`IDEMPOTENCY_WINDOW`]; `recent_key_order` tracks eviction
+    /// order. Seeded from the log tail at open so retries straddling a
+    /// restart still deduplicate.
+    recent_keys: HashMap<String, i64>,
+    recent_key_order: VecDeque<String>,
+    /// Minimum age entries must reach before [`WAL::truncate_before`] may
+    /// delete their segment; 0 disables the window. See
+    /// [`WAL::set_retention_ns`].
+    retention_ns: i64,
+    /// Record bytes appended since this process opened the log; never
+    /// decremented, so callers can meter write volume by differencing.
+    bytes_appended: u64,
+    /// Test-only fault injection: when set, the next appends fail without
+    /// writing or consuming a sequence.
+    #[cfg(test)]
+    pub fail_appends: bool,
+    /// Test-only fault injection: the next N append calls succeed and
+    /// every later one fails, for exercising a failure landing between the
+    /// batches of a single operation.
+    #[cfg(test)]
+    pub fail_appends_after: Option<u32>,
+}
+
+impl WAL {
+    /// Opens (or creates) a file-backed log in `dir`, resuming after the
+    /// last entry.
+    pub fn open(dir: impl Into<PathBuf>, segment_max_bytes: u64) -> io::Result<Self> {
+        Self::with_backend(Box::new(FileWalBackend::new(dir)?), segment_max_bytes)
+    }
+
+    /// An empty log on [`MemoryWalBackend`], for tests.
+    pub fn in_memory(segment_max_bytes: u64) -> Self {
+        Self::with_backend(Box::new(MemoryWalBackend::default()), segment_max_bytes)
+            .expect("memory backend cannot fail to open")
+    }
+
+    /// Opens a log over an arbitrary backend, resuming after the last entry.
+    pub fn with_backend(
+        mut backend: Box<dyn WalBackend>,
+        segment_max_bytes: u64,
+    ) -> io::Result<Self> {
+        let segments = backend.segments()?;
+        let next_sequence = match segments.last() {
+            Some(&first) => {
+                let entries = Self::decode_segment(&backend.read_segment(first)?)?;
+                entries.last().map(|e| e.sequence + 1).unwrap_or(1)
+            }
+            None => 1,
+        };
+        // Per-market counters resume from the highest seen anywhere in the
+        // log; pre-v2 entries carry 0 and never advance them.
+        let mut market_sequences: HashMap<String, i64> = HashMap::new();
+        let mut seen_keys: Vec<(String, i64)> = Vec::new();
+        for first in &segments {
+            for entry in Self::decode_segment(&backend.read_segment(*first)?)? {
+                let counter = market_sequences
+                    .entry(entry.operation.market_id().to_string())
+                    .or_default();
+                *counter = (*counter).max(entry.market_sequence);
+                if let Some(key) = entry.idempotency_key {
+                    seen_keys.push((key, entry.sequence));
+                }
+            }
+        }
+        // Rebuild the recently-seen set from the newest keys in the log.
+        let mut recent_keys = HashMap::new();
+        let mut recent_key_order = VecDeque::new();
+        let skip = seen_keys.len().saturating_sub(IDEMPOTENCY_WINDOW);
+        for (key, sequence) in seen_keys.into_iter().skip(skip) {
+            if recent_keys.insert(key.clone(), sequence).is_none() {
+                recent_key_order.push_back(key);
+            }
+        }
+        let open_first = segments.last().copied().unwrap_or(next_sequence);
+        let current_segment_bytes = backend.open_segment(open_first)?;
+
+        Ok(WAL {
+            backend,
+            segment_max_bytes,
+            current_segment_bytes,
+            next_sequence,
+            market_sequences,
+            recent_keys,
+            recent_key_order,
+            retention_ns: 0,
+            bytes_appended: 0,
+            pending_sync: false,
+            #[cfg(test)]
+            fail_appends: false,
+            #[cfg(test)]
+            fail_appends_after: None,
+        })
+    }
+
+    fn segment_path(dir: &Path, first_sequence: i64) -> PathBuf {
+        dir.join(format!("{SEGMENT_PREFIX}{first_sequence:020}{SEGMENT_SUFFIX}"))
+    }
+
+    /// All segment files in `dir` sorted by first sequence.
+    pub fn list_segments(dir: &Path) -> io::Result<Vec<(i64, PathBuf)>> {
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if let Some(stem) = name
+                .strip_prefix(SEGMENT_PREFIX)
+                .and_then(|s| s.strip_suffix(SEGMENT_SUFFIX))
+            {
+                if let Ok(first) = stem.parse::<i64>() {
+                    segments.push((first, path));
+                }
+            }
+        }
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Sets the retention window: [`WAL::truncate_before`] keeps any segment
+    /// whose newest entry is younger than this, even when a snapshot already
+    /// covers it. Compliance regimes that mandate keeping the raw log for N
+    /// days set this to N days; point-in-time recovery then works anywhere
+    /// inside the window. 0 (the default) lets checkpoints reclaim freely.
+    pub fn set_retention_ns(&mut self, retention_ns: i64) {
+        self.retention_ns = retention_ns;
+    }
+
+    pub fn next_sequence(&self) -> i64 {
+        self.next_sequence
+    }
+
+    /// Last per-market sequence assigned for a market; 0 before its first
+    /// entry.
+    pub fn market_sequence(&self, market_id: &str) -> i64 {
+        self.market_sequences.get(market_id).copied().unwrap_or(0)
+    }
+
+    /// Number of segments and total bytes currently in the log.
+    pub fn segment_stats(&self) -> io::Result<(u64, u64)> {
+        let segments = self.backend.segments()?;
+        let mut bytes = 0u64;
+        for first in &segments {
+            bytes += self.backend.segment_len(*first)?;
+        }
+        Ok((segments.len() as u64, bytes))
+    }
+
+    /// Appends an operation, returning its assigned sequence. The entry is
+    /// durable (per the backend's guarantee) before this returns.
+    pub fn append(&mut self, operation: WalOperation) -> io::Result<i64> {
+        Ok(self.append_batch(vec![operation])?[0])
+    }
+
+    /// Like [`WAL::append`], but deduplicated by `key`: if the key is in the
+    /// recently-seen set the operation is not re-appended and the original
+    /// sequence is returned, making a keyed append safe to retry after a
+    /// lost ack. The set is bounded ([`IDEMPOTENCY_WINDOW`]) and survives a
+    /// restart because keys are persisted in their entries.
+    pub fn append_keyed(&mut self, operation: WalOperation, key: &str) -> io::Result<i64> {
+        if let Some(&sequence) = self.recent_keys.get(key) {
+            return Ok(sequence);
+        }
+        let sequence =
+            self.append_batch_inner(vec![(operation, Some(key.to_string()))], true)?[0];
+        self.remember_key(key.to_string(), sequence);
+        Ok(sequence)
+    }
+
+    fn remember_key(&mut self, key: String, sequence: i64) {
+        if self.recent_keys.insert(key.clone(), sequence).is_none() {
+            self.recent_key_order.push_back(key);
+        }
+        while self.recent_key_order.len() > IDEMPOTENCY_WINDOW {
+            if let Some(evicted) = self.recent_key_order.pop_front() {
+                self.recent_keys.remove(&evicted);
+            }
+        }
+    }
+
+    /// Group commit: appends every operation and issues a single sync for
+    /// the whole batch, so callers journaling several records at once (e.g.
+    /// the trades and fill markers of one matching pass) pay one fsync
+    /// instead of one per record. Durability is all-or-nothing at the sync;
+    /// a torn tail is dropped on replay. Returns the assigned sequences, in
+    /// order.
+    pub fn append_batch(&mut self, operations: Vec<WalOperation>) -> io::Result<Vec<i64>> {
+        let operations = operations.into_iter().map(|op| (op, None)).collect();
+        self.append_batch_inner(operations, true)
+    }
+
+    /// Fast-ack variant: the batch is written (in order) but not synced, so
+    /// this returns as soon as the records are buffered. Durability arrives
+    /// at the next sync — an explicit [`WAL::flush`] or any durable batch,
+    /// whose sync covers everything buffered before it. A crash in between
+    /// loses the tail; callers opt in per request.
+    pub fn append_batch_async(&mut self, operations: Vec<WalOperation>) -> io::Result<Vec<i64>> {
+        let operations = operations.into_iter().map(|op| (op, None)).collect();
+        self.append_batch_inner(operations, false)
+    }
+
+    /// Syncs any async appends still pending. A no-op when everything is
+    /// already durable.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.pending_sync {
+            self.backend.sync()?;
+            self.pending_sync = false;
+        }
+        Ok(())
+    }
+
+    fn append_batch_inner(
+        &mut self,
+        operations: Vec<(WalOperation, Option<String>)>,
+        durable: bool,
+    ) -> io::Result<Vec<i64>> {
+        #[cfg(test)]
+        if self.fail_appends {
+            return Err(io::Error::other("injected WAL append failure"));
+        }
+        #[cfg(test)]
+        if let Some(calls) = &mut self.fail_appends_after {
+            if *calls == 0 {
+                return Err(io::Error::other("injected WAL append failure"));
+            }
+            *calls -= 1;
+        }
+        if operations.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut sequences = Vec::with_capacity(operations.len());
+        let mut next_sequence = self.next_sequence;
+        let mut segment_bytes = self.current_segment_bytes;
+        let mut appended_bytes = 0u64;
+        let mut market_sequences = self.market_sequences.clone();
+        for (operation, idempotency_key) in operations {
+            let sequence = next_sequence;
+            let market_sequence = {
+                let counter = market_sequences
+                    .entry(operation.market_id().to_string())
+                    .or_default();
+                *counter += 1;
+                *counter
+            };
+            let entry = WalEntry {
+                sequence,
+                market_sequence,
+                timestamp: now_ns(),
+                idempotency_key,
+                operation,
+            };
+            let record = encode_record(&entry)?;
+
+            if segment_bytes >= self.segment_max_bytes {
+                segment_bytes = self.backend.open_segment(sequence)?;
+            }
+            self.backend.write(&record)?;
+
+            segment_bytes += record.len() as u64;
+            appended_bytes += record.len() as u64;
+            next_sequence += 1;
+            sequences.push(sequence);
+        }
+        if durable {
+            self.backend.sync()?;
+            self.pending_sync = false;
+        } else {
+            self.pending_sync = true;
+        }
+        // Commit bookkeeping only after the sync (or buffered write, for an
+        // async batch): a failed batch consumes no sequences and its partial
+        // bytes are dropped as a torn tail.
+        self.current_segment_bytes = segment_bytes;
+        self.next_sequence = next_sequence;
+        self.market_sequences = market_sequences;
+        self.bytes_appended += appended_bytes;
+        Ok(sequences)
+    }
+
+    /// Record bytes appended since open; monotonic, so write volume over any
+    /// window is a difference of two readings. Used to trigger checkpoints
+    /// by bytes written rather than wall time.
+    pub fn bytes_appended(&self) -> u64 {
+        self.bytes_appended
+    }
+
+    fn decode_segment(data: &[u8]) -> io::Result<Vec<WalEntry>> {
+        let mut entries = Vec::new();
+        let mut pos = 0usize;
+        while pos + 4 <= data.len() {
+            let len = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            pos += 4;
+            if len == 0 || pos + len > data.len() {
+                // Torn tail write; ignore the partial record.
+                break;
+            }
+            entries.push(Self::decode_entry(data[pos], &data[pos + 1..pos + len])?);
+            pos += len;
+        }
+        Ok(entries)
+    }
+
+    /// Decodes one record payload according to its version byte. This is the
+    /// migration point for schema evolution: when the format bumps, the old
+    /// version keeps its decode arm here (mapping the old layout onto the
+    /// current [`WalEntry`]) so mixed-version logs replay transparently.
+    fn decode_entry(version: u8, payload: &[u8]) -> io::Result<WalEntry> {
+        match version {
+            1 => {
+                let v1: WalEntryV1 = bincode::deserialize(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(WalEntry {
+                    sequence: v1.sequence,
+                    // Unknown before v2; global order stands in for it.
+                    market_sequence: 0,
+                    timestamp: v1.timestamp,
+                    idempotency_key: None,
+                    operation: v1.operation.into(),
+                })
+            }
+            2 => {
+                let v2: WalEntryV2 = bincode::deserialize(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(WalEntry {
+                    sequence: v2.sequence,
+                    market_sequence: v2.market_sequence,
+                    timestamp: v2.timestamp,
+                    idempotency_key: None,
+                    operation: v2.operation.into(),
+                })
+            }
+            3 => {
+                let v3: WalEntryV3 = bincode::deserialize(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(WalEntry {
+                    sequence: v3.sequence,
+                    market_sequence: v3.market_sequence,
+                    timestamp: v3.timestamp,
+                    idempotency_key: v3.idempotency_key,
+                    operation: v3.operation.into(),
+                })
+            }
+            4 => {
+                let v4: WalEntryV4 = bincode::deserialize(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(WalEntry {
+                    sequence: v4.sequence,
+                    market_sequence: v4.market_sequence,
+                    timestamp: v4.timestamp,
+                    idempotency_key: v4.idempotency_key,
+                    operation: v4.operation.into(),
+                })
+            }
+            5 => {
+                let v5: WalEntryV5 = bincode::deserialize(payload)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(WalEntry {
+                    sequence: v5.sequence,
+                    market_sequence: v5.market_sequence,
+                    timestamp: v5.timestamp,
+                    idempotency_key: v5.idempotency_key,
+                    operation: v5.operation.into(),
+                })
+            }
+            6 => bincode::deserialize(payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported WAL record version {other} (supported: 1..={WAL_FORMAT_VERSION})"),
+            )),
+        }
+    }
+
+    /// Deletes whole segments that lie entirely below `sequence`, reclaiming
+    /// space made redundant by a durable snapshot at that sequence. The
+    /// segment containing the boundary (and anything newer) is left intact,
+    /// so `read_from(sequence)` is unaffected. Returns the first sequences of
+    /// the deleted segments.
+    ///
+    /// Callers must only invoke this after a snapshot covering `sequence`
+    /// has been durably written. When a retention window is set (see
+    /// [`WAL::set_retention_ns`]), segments holding any entry younger than
+    /// the window are kept regardless.
+    pub fn truncate_before(&mut self, sequence: i64) -> io::Result<Vec<i64>> {
+        let segments = self.backend.segments()?;
+        let cutoff = now_ns() - self.retention_ns;
+        let mut deleted = Vec::new();
+        for (i, first) in segments.iter().enumerate() {
+            // A segment is entirely below the checkpoint iff the next
+            // segment starts at or before it; the current (last) segment is
+            // always kept.
+            match segments.get(i + 1) {
+                Some(next_first) if *next_first <= sequence => {
+                    // Entries are in time order, so once one segment reaches
+                    // into the retention window every later one does too.
+                    if self.retention_ns > 0 && self.segment_newest_timestamp(*first)? > cutoff {
+                        break;
+                    }
+                    self.backend.remove_segment(*first)?;
+                    deleted.push(*first);
+                }
+                _ => break,
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Timestamp of the newest entry in a segment; `i64::MIN` for an empty
+    /// one, so it never blocks reclamation.
+    fn segment_newest_timestamp(&self, first_sequence: i64) -> io::Result<i64> {
+        let entries = Self::decode_segment(&self.backend.read_segment(first_sequence)?)?;
+        Ok(entries.last().map(|e| e.timestamp).unwrap_or(i64::MIN))
+    }
+
+    /// Returns all entries with `sequence >= from`, in order. Segments whose
+    /// entire range is below `from` are skipped without being read.
+    pub fn read_from(&self, from: i64) -> io::Result<Vec<WalEntry>> {
+        let segments = self.backend.segments()?;
+        let mut entries = Vec::new();
+        for (i, first) in segments.iter().enumerate() {
+            // A segment can be skipped if the next segment starts at or
+            // before `from`: everything in it is older.
+            if let Some(next_first) = segments.get(i + 1) {
+                if *next_first <= from {
+                    continue;
+                }
+            }
+            for entry in Self::decode_segment(&self.backend.read_segment(*first)?)? {
+                if entry.sequence >= from {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn cancel_op(order_id: u64) -> WalOperation {
+        WalOperation::CancelOrder {
+            market_id: "BTC-USD".into(),
+            order_id,
+        }
+    }
+
+    #[test]
+    fn append_assigns_monotonic_sequences_and_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        {
+            let mut wal = WAL::open(dir.path(), 64 * 1024).unwrap();
+            assert_eq!(wal.append(cancel_op(1)).unwrap(), 1);
+            assert_eq!(wal.append(cancel_op(2)).unwrap(), 2);
+        }
+        let mut wal = WAL::open(dir.path(), 64 * 1024).unwrap();
+        assert_eq!(wal.next_sequence(), 3);
+        assert_eq!(wal.append(cancel_op(3)).unwrap(), 3);
+
+        let entries = wal.read_from(1).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn truncate_before_removes_only_fully_covered_segments() {
+        let dir = TempDir::new().unwrap();
+        let mut wal = WAL::open(dir.path(), 1).unwrap();
+        for i in 1..=5 {
+            wal.append(cancel_op(i)).unwrap();
+        }
+        let before = WAL::list_segments(dir.path()).unwrap().len();
+        assert!(before > 2);
+
+        let deleted = wal.truncate_before(4).unwrap();
+        assert!(!deleted.is_empty());
+        let after = WAL::list_segments(dir.path()).unwrap();
+        assert_eq!(after.len(), before - deleted.len());
+
+        // The boundary is preserved: reading from the checkpoint still
+        // returns everything at or above it.
+        let entries = wal.read_from(4).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+        // Appends continue normally after truncation.
+        assert_eq!(wal.append(cancel_op(6)).unwrap(), 6);
+    }
+
+    #[test]
+    fn read_from_skips_earlier_entries_across_segments() {
+        let dir = TempDir::new().unwrap();
+        // Tiny segment cap so every append after the first rotates.
+        let mut wal = WAL::open(dir.path(), 1).unwrap();
+        for i in 1..=5 {
+            wal.append(cancel_op(i)).unwrap();
+        }
+        assert!(WAL::list_segments(dir.path()).unwrap().len() > 1);
+
+        let entries = wal.read_from(4).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+    }
+
+    #[test]
+    fn version_1_segments_read_back_after_reopen() {
+        let dir = TempDir::new().unwrap();
+        // Hand-craft version-1 records: the pre-`market_sequence` layout.
+        {
+            let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+            wal.append(cancel_op(1)).unwrap();
+            let (_, segment) = WAL::list_segments(dir.path()).unwrap().pop().unwrap();
+            let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+            let payload = bincode::serialize(&(2i64, 0i64, cancel_op(2))).unwrap();
+            file.write_all(&(1 + payload.len() as u32).to_le_bytes())
+                .unwrap();
+            file.write_all(&[1]).unwrap();
+            file.write_all(&payload).unwrap();
+        }
+        // A log holding version-1 records must stay readable by any later
+        // build, which is what the per-record decode arm guarantees. The old
+        // layout maps onto the current entry with `market_sequence` 0.
+        let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        let entries = wal.read_from(1).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(entries[1].market_sequence, 0);
+        // New appends resume both sequence spaces above what was seen.
+        wal.append(cancel_op(3)).unwrap();
+        let entries = wal.read_from(3).unwrap();
+        assert_eq!(entries[0].sequence, 3);
+        assert_eq!(entries[0].market_sequence, 2);
+    }
+
+    #[test]
+    fn version_2_segments_read_back_after_reopen() {
+        let dir = TempDir::new().unwrap();
+        // Hand-craft a version-2 record: the pre-`idempotency_key` layout.
+        {
+            let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+            wal.append(cancel_op(1)).unwrap();
+            let (_, segment) = WAL::list_segments(dir.path()).unwrap().pop().unwrap();
+            let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+            let payload = bincode::serialize(&(2i64, 2i64, 0i64, cancel_op(2))).unwrap();
+            file.write_all(&(1 + payload.len() as u32).to_le_bytes())
+                .unwrap();
+            file.write_all(&[2]).unwrap();
+            file.write_all(&payload).unwrap();
+        }
+        let wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        let entries = wal.read_from(1).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].market_sequence, 2);
+        assert_eq!(entries[1].idempotency_key, None);
+    }
+
+    #[test]
+    fn version_3_trades_decode_with_no_aggressor() {
+        let dir = TempDir::new().unwrap();
+        // Hand-craft a version-3 record holding a `TradeExecuted` in the
+        // pre-aggressor trade layout. Bincode writes enum variants as a u32
+        // tag followed by the fields in order, so a tuple mirrors the shape.
+        {
+            let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+            wal.append(cancel_op(1)).unwrap();
+            let (_, segment) = WAL::list_segments(dir.path()).unwrap().pop().unwrap();
+            let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+            let trade = (
+                7u64,
+                "BTC-USD",
+                rust_decimal_macros::dec!(100),
+                rust_decimal_macros::dec!(2),
+                1u64,
+                2u64,
+                10u64,
+                20u64,
+                0i64,
+            );
+            let payload =
+                bincode::serialize(&(2i64, 2i64, 0i64, Option::<String>::None, 4u32, trade))
+                    .unwrap();
+            file.write_all(&(1 + payload.len() as u32).to_le_bytes())
+                .unwrap();
+            file.write_all(&[3]).unwrap();
+            file.write_all(&payload).unwrap();
+        }
+        let wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        let entries = wal.read_from(1).unwrap();
+        assert_eq!(entries.len(), 2);
+        let WalOperation::TradeExecuted(trade) = &entries[1].operation else {
+            panic!("expected a trade record");
+        };
+        assert_eq!(trade.id, 7);
+        // Old logs never recorded the aggressor; it maps to None, not a
+        // fabricated side.
+        assert_eq!(trade.aggressor, None);
+    }
+
+    #[test]
+    fn retention_window_keeps_young_segments_through_truncation() {
+        let dir = TempDir::new().unwrap();
+        let day_ns = 24 * 3600 * 1_000_000_000i64;
+        // Hand-write two single-entry segments: one well past a one-day
+        // retention window, one inside it.
+        for (sequence, timestamp) in [(1i64, now_ns() - 10 * day_ns), (2, now_ns())] {
+            let entry = WalEntry {
+                sequence,
+                market_sequence: sequence,
+                timestamp,
+                idempotency_key: None,
+                operation: cancel_op(sequence as u64),
+            };
+            std::fs::write(
+                WAL::segment_path(dir.path(), sequence),
+                encode_record(&entry).unwrap(),
+            )
+            .unwrap();
+        }
+        // Tiny cap so the next append rotates into a third segment.
+        let mut wal = WAL::open(dir.path(), 1).unwrap();
+        wal.set_retention_ns(day_ns);
+        wal.append(cancel_op(3)).unwrap();
+
+        // Both old segments lie below the checkpoint, but only the one
+        // outside the window may be reclaimed.
+        assert_eq!(wal.truncate_before(3).unwrap(), vec![1]);
+        let remaining: Vec<i64> = WAL::list_segments(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|(first, _)| first)
+            .collect();
+        assert_eq!(remaining, vec![2, 3]);
+
+        // Without a window the same checkpoint reclaims it.
+        wal.set_retention_ns(0);
+        assert_eq!(wal.truncate_before(3).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn keyed_appends_deduplicate_and_return_the_original_sequence() {
+        let dir = TempDir::new().unwrap();
+        let first;
+        {
+            let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+            first = wal.append_keyed(cancel_op(1), "req-1").unwrap();
+            // The retry after a lost ack hits the recently-seen set: no new
+            // entry, same sequence back.
+            assert_eq!(wal.append_keyed(cancel_op(1), "req-1").unwrap(), first);
+            assert_eq!(wal.read_from(1).unwrap().len(), 1);
+            // A different key is a different event.
+            assert_ne!(wal.append_keyed(cancel_op(2), "req-2").unwrap(), first);
+        }
+        // Keys are persisted in their entries, so a retry that straddles a
+        // restart still deduplicates.
+        let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        assert_eq!(wal.append_keyed(cancel_op(1), "req-1").unwrap(), first);
+        assert_eq!(wal.read_from(1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn idempotency_window_evicts_the_oldest_keys() {
+        let mut wal = WAL::in_memory(u64::MAX);
+        let first = wal.append_keyed(cancel_op(0), "key-0").unwrap();
+        for i in 1..=IDEMPOTENCY_WINDOW as u64 {
+            wal.append_keyed(cancel_op(i), &format!("key-{i}")).unwrap();
+        }
+        // "key-0" has been evicted, so its retry appends a fresh entry.
+        assert_ne!(wal.append_keyed(cancel_op(0), "key-0").unwrap(), first);
+    }
+
+    #[test]
+    fn interleaved_markets_get_independent_market_sequences() {
+        let op = |market: &str, order_id: u64| WalOperation::CancelOrder {
+            market_id: market.into(),
+            order_id,
+        };
+        let dir = TempDir::new().unwrap();
+        {
+            let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+            wal.append(op("BTC-USD", 1)).unwrap();
+            wal.append(op("ETH-USD", 2)).unwrap();
+            wal.append(op("BTC-USD", 3)).unwrap();
+            wal.append(op("ETH-USD", 4)).unwrap();
+        }
+        // Per-market counters survive a reopen.
+        let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        wal.append(op("BTC-USD", 5)).unwrap();
+
+        let entries = wal.read_from(1).unwrap();
+        let per_market = |market: &str| {
+            entries
+                .iter()
+                .filter(|e| e.operation.market_id() == market)
+                .map(|e| e.market_sequence)
+                .collect::<Vec<_>>()
+        };
+        // Each market's stream counts independently of the global offset.
+        assert_eq!(per_market("BTC-USD"), vec![1, 2, 3]);
+        assert_eq!(per_market("ETH-USD"), vec![1, 2]);
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn unknown_record_version_is_rejected_not_misparsed() {
+        let dir = TempDir::new().unwrap();
+        let mut wal = WAL::open(dir.path(), u64::MAX).unwrap();
+        wal.append(WalOperation::CancelOrder {
+            market_id: "BTC-USD".into(),
+            order_id: 1,
+        })
+        .unwrap();
+
+        // Hand-craft a record claiming a future format version.
+        let (_, segment) = WAL::list_segments(dir.path()).unwrap().pop().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&4u32.to_le_bytes()).unwrap();
+        file.write_all(&[WAL_FORMAT_VERSION + 1, 0, 0, 0]).unwrap();
+
+        let err = wal.read_from(1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unsupported WAL record version"));
+    }
+
+    #[test]
+    fn memory_backend_round_trips_appends_without_touching_disk() {
+        let mut wal = WAL::in_memory(u64::MAX);
+        for i in 1..=3 {
+            assert_eq!(wal.append(cancel_op(i)).unwrap(), i as i64);
+        }
+        let entries = wal.read_from(2).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(entries[0].operation, cancel_op(2));
+    }
+
+    #[test]
+    fn batched_appends_get_distinct_sequences_under_one_sync() {
+        let mut wal = WAL::in_memory(u64::MAX);
+        wal.append(cancel_op(0)).unwrap();
+
+        let sequences = wal
+            .append_batch((1..=10).map(cancel_op).collect())
+            .unwrap();
+        assert_eq!(sequences, (2..=11).collect::<Vec<i64>>());
+
+        let entries = wal.read_from(1).unwrap();
+        assert_eq!(entries.len(), 11);
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            (1..=11).collect::<Vec<i64>>()
+        );
+    }
+
+    #[test]
+    fn async_appends_defer_the_sync_until_flush_or_a_durable_batch() {
+        struct CountingSync {
+            inner: MemoryWalBackend,
+            syncs: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        }
+        impl WalBackend for CountingSync {
+            fn segments(&self) -> io::Result<Vec<i64>> {
+                self.inner.segments()
+            }
+            fn read_segment(&self, first_sequence: i64) -> io::Result<Vec<u8>> {
+                self.inner.read_segment(first_sequence)
+            }
+            fn open_segment(&mut self, first_sequence: i64) -> io::Result<u64> {
+                self.inner.open_segment(first_sequence)
+            }
+            fn write(&mut self, record: &[u8]) -> io::Result<()> {
+                self.inner.write(record)
+            }
+            fn sync(&mut self) -> io::Result<()> {
+                self.syncs
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                self.inner.sync()
+            }
+            fn remove_segment(&mut self, first_sequence: i64) -> io::Result<()> {
+                self.inner.remove_segment(first_sequence)
+            }
+        }
+
+        let syncs = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let backend = CountingSync {
+            inner: MemoryWalBackend::default(),
+            syncs: syncs.clone(),
+        };
+        let mut wal = WAL::with_backend(Box::new(backend), u64::MAX).unwrap();
+
+        // Fast path: sequences are assigned and the records are readable,
+        // but no sync has happened yet.
+        let sequences = wal
+            .append_batch_async((1..=3).map(cancel_op).collect())
+            .unwrap();
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert_eq!(syncs.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(wal.read_from(1).unwrap().len(), 3);
+
+        // Flush makes them durable; a second flush has nothing to do.
+        wal.flush().unwrap();
+        assert_eq!(syncs.load(std::sync::atomic::Ordering::SeqCst), 1);
+        wal.flush().unwrap();
+        assert_eq!(syncs.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A durable batch's sync also covers earlier async appends.
+        wal.append_batch_async(vec![cancel_op(4)]).unwrap();
+        wal.append(cancel_op(5)).unwrap();
+        assert_eq!(syncs.load(std::sync::atomic::Ordering::SeqCst), 2);
+        wal.flush().unwrap();
+        assert_eq!(syncs.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn memory_backend_rotates_and_truncates_like_the_file_backend() {
+        // Tiny segment cap: every append after the first rotates.
+        let mut wal = WAL::in_memory(1);
+        for i in 1..=5 {
+            wal.append(cancel_op(i)).unwrap();
+        }
+        let deleted = wal.truncate_before(4).unwrap();
+        assert!(!deleted.is_empty());
+        let entries = wal.read_from(4).unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![4, 5]
+        );
+    }
+}